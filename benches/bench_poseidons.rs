@@ -3,7 +3,7 @@ use std::{rc::Rc, iter::repeat_with};
 use criterion::{criterion_group, criterion_main, Criterion};
 use ff::Field;
 use halo2::halo2curves::bn256;
-use protostar_works::{gadgets::{poseidon::poseidon_gadget_internal, input::input}, circuit::{ExternalValue, Circuit}, gate::{Gatebb, Gate}, utils::poly_utils::bits_le, commitment::CkRound, witness::CSSystemCommit, folding::poseidon::Poseidon};
+use protostar_works::{gadgets::{poseidon::poseidon_gadget_internal, poseidon2::poseidon2_permutation_gadget, input::input}, circuit::{ExternalValue, Circuit}, gate::{Gatebb, Gate}, utils::poly_utils::bits_le, commitment::CkRound, witness::CSSystemCommit, folding::{poseidon::Poseidon, poseidon2::Poseidon2}};
 use rand_core::OsRng;
 
 
@@ -56,14 +56,16 @@ pub fn assemble_poseidon_circuit<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>
     }
 }
 
-pub fn poseidons_pseudo_fold(c: &mut Criterion) {
-    let cfg = Poseidon::new();
-
-    let mut circuit = Circuit::new(25, 1);
-    let pi = circuit.ext_val(1)[0];
+/// Same chain of permutations of width 2 as assemble_poseidon_circuit, using Poseidon2.
+pub fn assemble_poseidon2_circuit<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon2, pi: ExternalValue<F>) {
+    let mut state = vec![input(circuit, pi, 0); 2];
 
-    assemble_poseidon_circuit(&mut circuit, &cfg, pi);
+    for _ in 0..1000 {
+        state = poseidon2_permutation_gadget(circuit, cfg, 2, 0, state);
+    }
+}
 
+pub fn pseudo_fold<'a>(c: &mut Criterion, name: &str, circuit: Circuit<'a, F, Gatebb<'a, F>>, pi: ExternalValue<F>) {
     let constructed = circuit.finalize();
     let mut instance = constructed.spawn();
 
@@ -87,19 +89,12 @@ pub fn poseidons_pseudo_fold(c: &mut Criterion) {
         bench_data.push((gate, a, b, randomness));
     }
 
-    c.bench_function("poseidons pseudo fold", |b| b.iter(|| {
+    c.bench_function(name, |b| b.iter(|| {
         bench_data.iter().for_each(|(gate, a, b, randomness)| evaluate_on_random_linear_combinations(gate, a, b, randomness))
     }));
-
 }
 
-pub fn poseidons_msm(c: &mut Criterion) {
-    let cfg = Poseidon::new();
-
-    let mut circuit = Circuit::new(25, 1);
-    let pi = circuit.ext_val(1)[0];
-    assemble_poseidon_circuit(&mut circuit, &cfg, pi);
-
+pub fn msm<'a>(c: &mut Criterion, name: &str, circuit: Circuit<'a, F, Gatebb<'a, F>>, pi: ExternalValue<F>) {
     let constructed = circuit.finalize();
     let mut instance = constructed.spawn();
 
@@ -114,8 +109,48 @@ pub fn poseidons_msm(c: &mut Criterion) {
         ck.push(rck);
     }
 
-    c.bench_function("poseidons msm", |b| b.iter(|| instance.cs.commit(&ck)));
+    c.bench_function(name, |b| b.iter(|| instance.cs.commit(&ck)));
+}
+
+pub fn poseidons_pseudo_fold(c: &mut Criterion) {
+    let cfg = Poseidon::new();
+
+    let mut circuit = Circuit::new(25, 1);
+    let pi = circuit.ext_val(1)[0];
+    assemble_poseidon_circuit(&mut circuit, &cfg, pi);
+
+    pseudo_fold(c, "poseidons pseudo fold", circuit, pi);
+}
+
+pub fn poseidons_msm(c: &mut Criterion) {
+    let cfg = Poseidon::new();
+
+    let mut circuit = Circuit::new(25, 1);
+    let pi = circuit.ext_val(1)[0];
+    assemble_poseidon_circuit(&mut circuit, &cfg, pi);
+
+    msm(c, "poseidons msm", circuit, pi);
+}
+
+pub fn poseidon2s_pseudo_fold(c: &mut Criterion) {
+    let cfg = Poseidon2::new(2);
+
+    let mut circuit = Circuit::new(25, 1);
+    let pi = circuit.ext_val(1)[0];
+    assemble_poseidon2_circuit(&mut circuit, &cfg, pi);
+
+    pseudo_fold(c, "poseidon2s pseudo fold", circuit, pi);
+}
+
+pub fn poseidon2s_msm(c: &mut Criterion) {
+    let cfg = Poseidon2::new(2);
+
+    let mut circuit = Circuit::new(25, 1);
+    let pi = circuit.ext_val(1)[0];
+    assemble_poseidon2_circuit(&mut circuit, &cfg, pi);
+
+    msm(c, "poseidon2s msm", circuit, pi);
 }
 
-criterion_group!(poseidon, poseidons_pseudo_fold, poseidons_msm);
-criterion_main!(poseidon);
\ No newline at end of file
+criterion_group!(poseidon, poseidons_pseudo_fold, poseidons_msm, poseidon2s_pseudo_fold, poseidon2s_msm);
+criterion_main!(poseidon);
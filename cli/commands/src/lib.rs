@@ -31,6 +31,7 @@ type F = bn256::Fr;
 pub fn precompute_half_squares(output: &mut dyn Write, limit: u64) -> (){
     let mut s : String = "".to_string();
     s+="use halo2::halo2curves::{bn256::Fr as F, serde::SerdeObject};\n";
+    s+=&format!("/// Values of k in 0..LIMIT are tabulated.\npub const LIMIT: u64 = {};\n", limit);
     s+="pub fn half_square(k:u64) -> F {\n";
     s+="    match k {\n";
     for i in 0..limit {
@@ -61,6 +62,7 @@ fn inv_lagrange_prod(k: u64, n: u64) -> F {
 pub fn precompute_inv_lagrange_prod(output: &mut dyn Write, limit: u64) -> () {
     let mut s : String = "".to_string();
     s+="use halo2::halo2curves::{bn256::Fr as F, serde::SerdeObject};\n";
+    s+=&format!("/// Domain sizes n in 2..LIMIT are tabulated.\npub const LIMIT: u64 = {};\n", limit);
     s+="pub fn inv_lagrange_prod(k: u64, n: u64) -> F {\n";
     s+="    match (k, n) {\n";
     for n in 2..limit {
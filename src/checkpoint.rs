@@ -0,0 +1,184 @@
+// This module implements serialization of partially executed circuit runs.
+
+use ff::PrimeField;
+
+use crate::{witness::RoundWtns, external_interface::RunIndex};
+
+const MAGIC: &[u8; 4] = b"PGRN";
+const VERSION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointError {
+    /// Data is not a checkpoint, or is truncated / corrupted.
+    Malformed,
+    /// Checkpoint was made with a different circuit.
+    ShapeMismatch{ expected: u64, found: u64 },
+    /// The run index slot is occupied by an active run.
+    SlotInUse(usize),
+    /// Checkpoint holds the oracle state and should be restored by `resume_with_oracle`, or vice versa.
+    OracleMismatch,
+}
+
+/// FNV-1a hasher used for the shape digest. It is stable across builds and platforms,
+/// which is all we need to detect that the checkpoint belongs to another circuit.
+pub(crate) struct ShapeHasher {
+    state: u64,
+}
+
+impl ShapeHasher {
+    pub fn new() -> Self {
+        Self { state: 0xcbf29ce484222325 }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= byte as u64;
+            self.state = self.state.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_usize(&mut self, x: usize) {
+        self.write_bytes(&(x as u64).to_le_bytes())
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+/// Contents of a checkpoint.
+pub(crate) struct RunSnapshot<F: PrimeField> {
+    pub digest: u64,
+    pub run_idx: RunIndex,
+    pub round_counter: usize,
+    pub wtns: Vec<RoundWtns<F>>,
+    pub ext_vals: Vec<Option<F>>,
+    pub int_vals: Vec<Option<F>>,
+    /// State of the oracle which absorbed the rounds before `round_counter`.
+    pub oracle: Option<Vec<F>>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CheckpointError> {
+        if self.data.len() < n {
+            return Err(CheckpointError::Malformed)
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, CheckpointError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_usize(&mut self) -> Result<usize, CheckpointError> {
+        usize::try_from(self.read_u64()?).map_err(|_| CheckpointError::Malformed)
+    }
+
+    fn read_values<F: PrimeField>(&mut self) -> Result<Vec<Option<F>>, CheckpointError> {
+        let len = self.read_usize()?;
+        // every value takes at least one byte, this protects from allocating garbage lengths
+        if len > self.data.len() {
+            return Err(CheckpointError::Malformed)
+        }
+        let mut ret = Vec::with_capacity(len);
+        for _ in 0..len {
+            match self.take(1)?[0] {
+                0 => ret.push(None),
+                1 => {
+                    let mut repr = F::Repr::default();
+                    let n = repr.as_ref().len();
+                    repr.as_mut().copy_from_slice(self.take(n)?);
+                    let value = Option::from(F::from_repr(repr)).ok_or(CheckpointError::Malformed)?;
+                    ret.push(Some(value));
+                },
+                _ => return Err(CheckpointError::Malformed),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn write_u64(buf: &mut Vec<u8>, x: u64) {
+    buf.extend(x.to_le_bytes());
+}
+
+fn write_values<F: PrimeField>(buf: &mut Vec<u8>, values: &[Option<F>]) {
+    write_u64(buf, values.len() as u64);
+    for value in values {
+        match value {
+            None => buf.push(0),
+            Some(value) => {
+                buf.push(1);
+                buf.extend(value.to_repr().as_ref());
+            },
+        }
+    }
+}
+
+impl<F: PrimeField> RunSnapshot<F> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend(MAGIC);
+        buf.push(VERSION);
+        write_u64(&mut buf, self.digest);
+        write_u64(&mut buf, self.run_idx.muid as u64);
+        write_u64(&mut buf, self.run_idx.slot as u64);
+        write_u64(&mut buf, self.round_counter as u64);
+        write_u64(&mut buf, self.wtns.len() as u64);
+        for round in &self.wtns {
+            write_values(&mut buf, &round.pubs);
+            write_values(&mut buf, &round.privs);
+        }
+        write_values(&mut buf, &self.ext_vals);
+        write_values(&mut buf, &self.int_vals);
+        match &self.oracle {
+            None => buf.push(0),
+            Some(state) => {
+                buf.push(1);
+                write_values(&mut buf, &state.iter().cloned().map(Some).collect::<Vec<_>>());
+            },
+        }
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CheckpointError> {
+        let mut reader = Reader { data };
+        if reader.take(MAGIC.len())? != MAGIC || reader.take(1)?[0] != VERSION {
+            return Err(CheckpointError::Malformed)
+        }
+        let digest = reader.read_u64()?;
+        let muid = reader.read_usize()?;
+        let slot = reader.read_usize()?;
+        let round_counter = reader.read_usize()?;
+        let num_rounds = reader.read_usize()?;
+        if num_rounds > data.len() {
+            return Err(CheckpointError::Malformed)
+        }
+        let mut wtns = Vec::with_capacity(num_rounds);
+        for _ in 0..num_rounds {
+            let pubs = reader.read_values()?;
+            let privs = reader.read_values()?;
+            wtns.push(RoundWtns { pubs, privs });
+        }
+        let ext_vals = reader.read_values()?;
+        let int_vals = reader.read_values()?;
+        let oracle = match reader.take(1)?[0] {
+            0 => None,
+            1 => Some(reader.read_values()?.into_iter().collect::<Option<Vec<F>>>().ok_or(CheckpointError::Malformed)?),
+            _ => return Err(CheckpointError::Malformed),
+        };
+        if !reader.data.is_empty() {
+            return Err(CheckpointError::Malformed)
+        }
+
+        Ok(Self { digest, run_idx: RunIndex { muid, slot }, round_counter, wtns, ext_vals, int_vals, oracle })
+    }
+}
@@ -1,9 +1,10 @@
-use std::{rc::{Rc, Weak}, marker::PhantomData, iter::repeat_with, cell::RefCell};
+use std::{rc::{Rc, Weak}, marker::PhantomData, iter::repeat_with, cell::{RefCell, OnceCell}};
 use elsa::map::FrozenMap;
 use ff::PrimeField;
+use halo2::halo2curves::CurveAffine;
 use itertools::Itertools;
 
-use crate::{witness::{CSWtns, ProtostarWtns, ProtostarLhsWtns}, gate::{Gatebb, Gate}, constraint_system::{Variable, ProtoGalaxyConstraintSystem, CommitKind, Visibility, CS, Constraint}, utils::poly_utils::check_poly, circuit::circuit_operations::{AttachedAdvice, AttachedPolynomialAdvice, AttachedAdvicePub}, external_interface::{RunIndex, RunAllocator} };
+use crate::{checkpoint::{CheckpointError, RunSnapshot, ShapeHasher}, commitment::{CkWtns, CommitmentKey, CtRound}, folding::{encode::encode_point, oracle::{Oracle, SerializableOracle}}, witness::{CSWtns, ProtostarWtns, ProtostarLhsWtns}, gate::{Gatebb, Gate}, constraint_system::{Variable, ProtoGalaxyConstraintSystem, CommitKind, Visibility, CS, Constraint, WitnessSpec}, utils::poly_utils::check_poly, circuit::circuit_operations::{AttachedAdvice, AttachedPolynomialAdvice, AttachedAdvicePub}, external_interface::{RunIndex, RunAllocator} };
 
 use self::circuit_operations::CircuitOperation;
 
@@ -67,6 +68,27 @@ impl<F: PrimeField> InternalValue<F> {
     }
 }
 
+/// Reason for an advice to fail to compute its outputs, typically because the inputs do not satisfy
+/// the precondition of the gadget.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdviceError {
+    /// Inverse of zero was requested.
+    DivisionByZero,
+    /// Square root of a quadratic non-residue was requested.
+    NonResidue,
+    /// Looked up value is not in the table.
+    NotInTable,
+}
+
+/// Failure of the witness generation, see `CircuitRun::try_execute`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionError {
+    pub round: usize,
+    /// Index of the failed operation in its round.
+    pub op: usize,
+    pub error: AdviceError,
+}
+
 /// A (possibly non-polynomial) circuit advice
 ///
 /// Closure inside an advice may depend on some auxiliary values.
@@ -75,11 +97,16 @@ impl<F: PrimeField> InternalValue<F> {
     pub ivar: usize,
 //    pub iext: usize,
     pub o: usize,
-    pub f: Rc<dyn Fn(&[F], &RunIndex)-> Vec<F> + 'closure>,
+    pub f: Rc<dyn Fn(&[F], &RunIndex)-> Result<Vec<F>, AdviceError> + 'closure>,
 }
 
 impl<'closure, F: PrimeField> Advice<'closure, F> {
     pub fn new(ivar: usize, o: usize, f: impl Fn(&[F], &RunIndex) -> Vec<F> + 'closure) -> Self {
+        Self::new_fallible(ivar, o, move |args, idx| Ok(f(args, idx)))
+    }
+
+    /// Advice which can reject its inputs. The error stops the execution of the run, see `CircuitRun::try_execute`.
+    pub fn new_fallible(ivar: usize, o: usize, f: impl Fn(&[F], &RunIndex) -> Result<Vec<F>, AdviceError> + 'closure) -> Self {
         let f = Rc::new(f);
 
         Self { ivar, o, f }
@@ -105,10 +132,10 @@ pub mod circuit_operations {
     use std::rc::Rc;
     use ff::PrimeField;
     use crate::{constraint_system::Variable, gate::Gate, witness::CSWtns};
-    use super::{ExternalValue, RunIndex};
+    use super::{AdviceError, ExternalValue, RunIndex};
 
     pub trait CircuitOperation<'a, F: PrimeField, G: Gate<'a, F>> {
-        fn execute(&self, witness: &mut CSWtns<'a, F, G>, idx: &RunIndex);
+        fn execute(&self, witness: &mut CSWtns<'a, F, G>, idx: &RunIndex) -> Result<(), AdviceError>;
     }
 
     pub struct AttachedAdvicePub<'advice, F: PrimeField> {
@@ -124,13 +151,14 @@ pub mod circuit_operations {
     }
 
     impl<'advice, F: PrimeField, G: Gate<'advice, F>> CircuitOperation<'advice, F, G> for AttachedAdvicePub<'advice, F> {
-        fn execute(&self, witness: &mut CSWtns<'advice, F, G>, idx: &RunIndex) {
+        fn execute(&self, witness: &mut CSWtns<'advice, F, G>, _: &RunIndex) -> Result<(), AdviceError> {
             let aux: Vec<_> = self.aux.iter().map(|ev| witness.getext(*ev)).collect();
 
             let output = (self.closure)(&aux);
 
             let value_set: Vec<_> = self.output.iter().cloned().zip(output).collect();
             witness.set_vars(&value_set);
+            Ok(())
         }
     }
 
@@ -138,23 +166,24 @@ pub mod circuit_operations {
     pub struct AttachedAdvice<'advice, F: PrimeField> {
         input: Vec<Variable>,
         output: Vec<Variable>,
-        closure: Rc<dyn Fn(&[F], &RunIndex) -> Vec<F> + 'advice>,
+        closure: Rc<dyn Fn(&[F], &RunIndex) -> Result<Vec<F>, AdviceError> + 'advice>,
     }
 
     impl<'advice, F: PrimeField> AttachedAdvice<'advice, F> {
-        pub fn new(input: Vec<Variable>, output: Vec<Variable>, closure: Rc<dyn Fn(&[F], &RunIndex) -> Vec<F> + 'advice>) -> Self {
+        pub fn new(input: Vec<Variable>, output: Vec<Variable>, closure: Rc<dyn Fn(&[F], &RunIndex) -> Result<Vec<F>, AdviceError> + 'advice>) -> Self {
             Self { input, output, closure }
         }
     }
 
     impl<'advice, F: PrimeField, G: Gate<'advice, F>> CircuitOperation<'advice, F, G> for AttachedAdvice<'advice, F> {
-        fn execute(&self, witness: &mut CSWtns<'advice, F, G>, idx: &RunIndex) {
+        fn execute(&self, witness: &mut CSWtns<'advice, F, G>, idx: &RunIndex) -> Result<(), AdviceError> {
             let input = witness.get_vars(&self.input);
 
-            let output = (self.closure)(&input, &idx);
+            let output = (self.closure)(&input, &idx)?;
 
             let value_set: Vec<_> = self.output.iter().cloned().zip(output).collect();
             witness.set_vars(&value_set);
+            Ok(())
         }
     }
 
@@ -171,13 +200,14 @@ pub mod circuit_operations {
     }
 
     impl<'closure, F: PrimeField, G: Gate<'closure, F>> CircuitOperation<'closure, F, G> for AttachedPolynomialAdvice<'closure, F> {
-        fn execute(&self, witness: &mut CSWtns<'closure, F, G>, _: &RunIndex) {
+        fn execute(&self, witness: &mut CSWtns<'closure, F, G>, _: &RunIndex) -> Result<(), AdviceError> {
             let input = witness.get_vars(&self.input);
 
             let output = (self.closure)(&input, &[]);
 
             let value_set: Vec<_> = self.output.iter().cloned().zip(output).collect();
             witness.set_vars(&value_set);
+            Ok(())
         }
     }
 }
@@ -186,6 +216,7 @@ pub struct Circuit<'circuit, F: PrimeField, G: Gate<'circuit, F> + From<PolyOp<'
     gate_registry: FrozenMap<String, Box<G>>,
     pub cs: ProtoGalaxyConstraintSystem<'circuit, F, G>,
     ops: Vec<Vec<Box<dyn CircuitOperation<'circuit, F, G> + 'circuit>>>,
+    challenges: Vec<Vec<Variable>>,
     max_degree: usize,
 //    round_counter : usize,
 //    _state_marker: PhantomData<S>,
@@ -202,6 +233,7 @@ where
                 gate_registry: FrozenMap::new(),
                 cs,
                 ops: repeat_with(|| Vec::default()).take(num_rounds).collect(),  // this particular Vec::default() is !Clone
+                challenges: vec![vec![]; num_rounds],
                 max_degree,
                 //_state_marker: PhantomData,
         };
@@ -215,6 +247,10 @@ where
         prep
     }
 
+    pub fn max_degree(&self) -> usize {
+        self.max_degree
+    }
+
     pub fn advice(&mut self, round: usize, advice: Advice<'circuit, F>, input: Vec<Variable>) -> Vec<Variable> {
         assert!(round < self.ops.len(), "The round is too large.");
 
@@ -312,7 +348,59 @@ where
         ConstructedCircuit {
             circuit: self,
             run_allocator: RefCell::new(RunAllocator::new()),
+            shape_digest: OnceCell::new(),
+        }
+    }
+
+    /// Allocates a challenge which becomes available in round `round + 1`.
+    ///
+    /// Its value is not set by the user: it is derived by `CircuitRun::execute_with_oracle`
+    /// from the commitments to the witness of all previous rounds.
+    pub fn challenge(&mut self, round: usize) -> Variable {
+        assert!(round + 1 < self.ops.len(), "Challenge after round {} requires round {} to exist.", round, round + 1);
+
+        let challenge = self.cs.alloc_in_round(round + 1, Visibility::Public, 1)[0];
+        self.challenges[round + 1].push(challenge);
+
+        challenge
+    }
+
+    /// Digest of the circuit shape: witness layout, challenges, operation counts and constraints.
+    ///
+    /// Gates are opaque, so each one is fingerprinted by its evaluation at a fixed point.
+    fn compute_shape_digest(&self) -> u64 {
+        let mut hasher = ShapeHasher::new();
+        let hash_var = |hasher: &mut ShapeHasher, var: &Variable| {
+            hasher.write_usize(match var.visibility { Visibility::Public => 0, Visibility::Private => 1 });
+            hasher.write_usize(var.round);
+            hasher.write_usize(var.index);
+        };
+
+        let WitnessSpec{round_specs, num_exts, num_ints} = self.cs.witness_spec();
+        hasher.write_usize(round_specs.len());
+        for spec in round_specs {
+            hasher.write_usize(spec.pubs);
+            hasher.write_usize(spec.privs);
+        }
+        hasher.write_usize(*num_exts);
+        hasher.write_usize(*num_ints);
+
+        for (ops, challenges) in self.ops.iter().zip(self.challenges.iter()) {
+            hasher.write_usize(ops.len());
+            hasher.write_usize(challenges.len());
+            challenges.iter().for_each(|v| hash_var(&mut hasher, v));
+        }
+
+        for constr in self.cs.iter_constraints() {
+            hasher.write_usize(constr.gate.d());
+            hasher.write_usize(constr.gate.i());
+            hasher.write_usize(constr.gate.o());
+            constr.inputs.iter().for_each(|v| hash_var(&mut hasher, v));
+            let point = (0..constr.gate.i()).map(|k| F::from(0x9e3779b97f4a7c15u64.wrapping_mul(k as u64 + 1))).collect_vec();
+            constr.gate.exec(&point).iter().for_each(|out| hasher.write_bytes(out.to_repr().as_ref()));
         }
+
+        hasher.finish()
     }
 
     pub fn one(&self) -> Variable {
@@ -327,6 +415,7 @@ where
 pub struct ConstructedCircuit<'circuit, F: PrimeField, G: Gate<'circuit, F> + From<PolyOp<'circuit, F>>> {
     pub circuit: Circuit<'circuit, F, G>,
     run_allocator: RefCell<RunAllocator>,
+    shape_digest: OnceCell<u64>,
 }
 
 impl<'circuit, F: PrimeField, G: Gate<'circuit, F> + From<PolyOp<'circuit, F>>> ConstructedCircuit<'circuit, F, G> {
@@ -343,6 +432,59 @@ impl<'circuit, F: PrimeField, G: Gate<'circuit, F> + From<PolyOp<'circuit, F>>>
         self.run_allocator.borrow_mut().deallocate(idx);
     }
 
+    /// Digest of the circuit shape, computed on the first call, i.e. on the first checkpoint or resume.
+    pub fn shape_digest(&self) -> u64 {
+        *self.shape_digest.get_or_init(|| self.circuit.compute_shape_digest())
+    }
+
+    /// Restores a run from the data produced by `CircuitRun::checkpoint`.
+    ///
+    /// The run keeps its slot if it is free, so values of `InnerValue`s written by other runs are not affected.
+    pub fn resume<'constructed>(&'constructed self, data: &[u8]) -> Result<CircuitRun<'constructed, 'circuit, F, G>, CheckpointError> {
+        let snapshot = RunSnapshot::<F>::from_bytes(data)?;
+        if snapshot.oracle.is_some() {
+            return Err(CheckpointError::OracleMismatch)
+        }
+        self.restore(snapshot)
+    }
+
+    /// Restores a run together with its oracle from the data produced by `CircuitRun::checkpoint_with_oracle`.
+    pub fn resume_with_oracle<'constructed, O: SerializableOracle<F>>(&'constructed self, data: &[u8]) -> Result<(CircuitRun<'constructed, 'circuit, F, G>, O), CheckpointError> {
+        let mut snapshot = RunSnapshot::<F>::from_bytes(data)?;
+        let state = snapshot.oracle.take().ok_or(CheckpointError::OracleMismatch)?;
+        let oracle = O::restore(&state).ok_or(CheckpointError::Malformed)?;
+        Ok((self.restore(snapshot)?, oracle))
+    }
+
+    fn restore<'constructed>(&'constructed self, snapshot: RunSnapshot<F>) -> Result<CircuitRun<'constructed, 'circuit, F, G>, CheckpointError> {
+        if snapshot.digest != self.shape_digest() {
+            return Err(CheckpointError::ShapeMismatch { expected: self.shape_digest(), found: snapshot.digest })
+        }
+
+        let mut cs = CSWtns::<F,G>::new(&self.circuit.cs);
+        let shape_matches = snapshot.round_counter <= cs.wtns.len()
+            && snapshot.wtns.len() == cs.wtns.len()
+            && snapshot.wtns.iter().zip(cs.wtns.iter()).all(|(a, b)| a.pubs.len() == b.pubs.len() && a.privs.len() == b.privs.len())
+            && snapshot.ext_vals.len() == cs.ext_vals.len()
+            && snapshot.int_vals.len() == cs.int_vals.len();
+        if !shape_matches {
+            return Err(CheckpointError::Malformed)
+        }
+
+        let run_idx = self.run_allocator.borrow_mut().reserve(&snapshot.run_idx).ok_or(CheckpointError::SlotInUse(snapshot.run_idx.slot))?;
+
+        cs.wtns = snapshot.wtns;
+        cs.ext_vals = snapshot.ext_vals;
+        cs.int_vals = snapshot.int_vals;
+
+        Ok(CircuitRun {
+            constructed: &self,
+            cs,
+            round_counter: snapshot.round_counter,
+            run_idx,
+        })
+    }
+
     pub fn perepare_protostar_chellanges(&self, mut beta: F) -> Vec<F> {
         let m = self.circuit.cs.constr_spec().num_nonlinear_constraints;
         let mut p = 1;
@@ -369,15 +511,78 @@ where
     G: Gate<'circuit, F> + From<PolyOp<'circuit, F>>,
 {
     /// Executes the circuit up from the current program counter to round k.
+    /// Panics if an advice fails, see `try_execute`.
     pub fn execute(&mut self, round: usize) {
+        if let Err(e) = self.try_execute(round) {
+            panic!("Operation #{} of round {} failed: {:?}", e.op, e.round, e.error)
+        }
+    }
+
+    /// Executes the circuit up from the current program counter to round k, stopping at the first failed advice.
+    /// The witness of the failed round is then partially assigned, so the run can not be continued.
+    pub fn try_execute(&mut self, round: usize) -> Result<(), ExecutionError> {
         assert!(self.round_counter <= round, "Execution is already at round {}, tried to execute up to round {}", self.round_counter, round);
 
         while self.round_counter <= round {
-            for op in &self.constructed.circuit.ops[self.round_counter] {
-                op.execute(&mut self.cs, &self.run_idx);
+            assert!(self.constructed.circuit.challenges[self.round_counter].is_empty(),
+                "Round {} has challenges, use execute_with_oracle instead", self.round_counter);
+            self.execute_round()?;
+        }
+        Ok(())
+    }
+
+    /// Executes the circuit up to round k, filling the challenges from the oracle.
+    ///
+    /// Before each round r > 0, the witness of round r-1 is committed using ck[r-1], and its commitment
+    /// together with public inputs are absorbed into the oracle, prefixed by r-1 and the amount of public inputs. Then, the challenges of round r are
+    /// squeezed. In order for all rounds to be absorbed, the run should be driven by this method from the start.
+    /// Panics if an advice fails, see `try_execute_with_oracle`.
+    pub fn execute_with_oracle<C, O>(&mut self, round: usize, ck: &CkWtns<C>, oracle: &mut O)
+    where
+        C: CurveAffine<ScalarExt = F>,
+        O: Oracle<Vec<F>, F>,
+    {
+        if let Err(e) = self.try_execute_with_oracle(round, ck, oracle) {
+            panic!("Operation #{} of round {} failed: {:?}", e.op, e.round, e.error)
+        }
+    }
+
+    /// Executes the circuit up to round k, filling the challenges from the oracle, stopping at the first failed advice.
+    /// As in `try_execute`, the run can not be continued after a failure.
+    pub fn try_execute_with_oracle<C, O>(&mut self, round: usize, ck: &CkWtns<C>, oracle: &mut O) -> Result<(), ExecutionError>
+    where
+        C: CurveAffine<ScalarExt = F>,
+        O: Oracle<Vec<F>, F>,
+    {
+        assert!(self.round_counter <= round, "Execution is already at round {}, tried to execute up to round {}", self.round_counter, round);
+
+        while self.round_counter <= round {
+            let r = self.round_counter;
+            if r > 0 {
+                let CtRound { pubs, pt } = ck[r - 1].commit(&self.cs.wtns[r - 1]);
+                // the round index and the amount of public inputs separate the messages of different rounds
+                let mut msg = vec![F::from((r - 1) as u64), F::from(pubs.len() as u64)];
+                msg.extend(pubs);
+                msg.extend(encode_point(pt));
+                oracle.update(msg);
+            }
+            for &challenge in &self.constructed.circuit.challenges[r] {
+                let value = oracle.response();
+                self.cs.setvar(challenge, value);
+                oracle.update(vec![value]);
             }
-            self.round_counter += 1;
+            self.execute_round()?;
         }
+        Ok(())
+    }
+
+    fn execute_round(&mut self) -> Result<(), ExecutionError> {
+        let round = self.round_counter;
+        for (op, operation) in self.constructed.circuit.ops[round].iter().enumerate() {
+            operation.execute(&mut self.cs, &self.run_idx).map_err(|error| ExecutionError { round, op, error })?;
+        }
+        self.round_counter += 1;
+        Ok(())
     }
 
     pub fn end(&self, beta: F) -> ProtostarWtns<F> {
@@ -401,6 +606,36 @@ where
         }
     }
 
+    /// Serializes the state of the run, which can be restored later by `ConstructedCircuit::resume`.
+    ///
+    /// Values kept by advices outside of the witness (see `InnerValue`) are not the part of the checkpoint.
+    /// Panics if the run is driven by an oracle which has absorbed some rounds, see `checkpoint_with_oracle`.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let challenges = &self.constructed.circuit.challenges;
+        assert!(self.round_counter == 0 || challenges[self.round_counter..].iter().all(|c| c.is_empty()),
+            "Rounds after {} have challenges, use checkpoint_with_oracle instead", self.round_counter);
+
+        self.snapshot(None).to_bytes()
+    }
+
+    /// Serializes the state of the run together with the state of the oracle driving it,
+    /// which can be restored later by `ConstructedCircuit::resume_with_oracle`.
+    pub fn checkpoint_with_oracle<O: SerializableOracle<F>>(&self, oracle: &O) -> Vec<u8> {
+        self.snapshot(Some(oracle.state())).to_bytes()
+    }
+
+    fn snapshot(&self, oracle: Option<Vec<F>>) -> RunSnapshot<F> {
+        RunSnapshot {
+            digest: self.constructed.shape_digest(),
+            run_idx: RunIndex { muid: self.run_idx.muid, slot: self.run_idx.slot },
+            round_counter: self.round_counter,
+            wtns: self.cs.wtns.clone(),
+            ext_vals: self.cs.ext_vals.clone(),
+            int_vals: self.cs.int_vals.clone(),
+            oracle,
+        }
+    }
+
     pub fn set_ext(&mut self, ext: ExternalValue<F>, value: F) -> () {
         self.cs.setext(ext, value);
     }
@@ -427,3 +662,18 @@ where
     }
 }
 
+
+/// Random commitment key for the witness of a circuit, for tests which drive the run by an oracle.
+#[cfg(test)]
+pub fn random_ck<'circuit, G>(constructed: &ConstructedCircuit<'circuit, halo2::halo2curves::bn256::Fr, G>) -> CkWtns<halo2::halo2curves::bn256::G1Affine>
+where
+    G: Gate<'circuit, halo2::halo2curves::bn256::Fr> + From<PolyOp<'circuit, halo2::halo2curves::bn256::Fr>>,
+{
+    use group::{Curve, Group};
+    use halo2::halo2curves::bn256;
+    use rand_core::OsRng;
+
+    constructed.circuit.cs.witness_spec().round_specs.iter()
+        .map(|spec| (0..spec.privs).map(|_| bn256::G1::random(OsRng).to_affine()).collect())
+        .collect()
+}
@@ -22,6 +22,25 @@ impl RunAllocator {
         RunIndex { muid: self.total_spawned - 1, slot }
     }
     
+    /// Claims the slot of a given run index, e.g. the one of a run restored from a checkpoint.
+    ///
+    /// Returns `None` if the slot is occupied. The muid is kept unless a later run was already spawned,
+    /// in which case a fresh one is issued so values stored by that run are never shadowed.
+    pub fn reserve(&mut self, idx: &RunIndex) -> Option<RunIndex> {
+        if idx.slot < self.allocated {
+            let pos = self.free_slots.iter().position(|&slot| slot == idx.slot)?;
+            self.free_slots.swap_remove(pos);
+        } else {
+            self.free_slots.extend(self.allocated..idx.slot);
+            self.allocated = idx.slot + 1;
+        }
+        self.active += 1;
+
+        let muid = if idx.muid >= self.total_spawned { idx.muid } else { self.total_spawned };
+        self.total_spawned = muid + 1;
+        Some(RunIndex { muid, slot: idx.slot })
+    }
+
     pub fn deallocate(&mut self, idx: RunIndex) {
         self.free_slots.push(idx.slot);
         self.active -= 1;
@@ -103,3 +122,19 @@ fn normal_usage() {
     assert_eq!(x.replace(&RunIndex { muid: 1, slot: 1 }, 5), Some(1))
 }
 
+
+#[test]
+fn reserve_slots() {
+    let mut allocator = RunAllocator::new();
+    let a = allocator.allocate();
+    assert!(allocator.reserve(&RunIndex { muid: 0, slot: 0 }).is_none());
+
+    let b = allocator.reserve(&RunIndex { muid: 5, slot: 3 }).unwrap();
+    assert_eq!((b.muid, b.slot), (5, 3));
+    let c = allocator.allocate();
+    assert_eq!((c.muid, c.slot), (6, 2));
+
+    allocator.deallocate(a);
+    let d = allocator.reserve(&RunIndex { muid: 0, slot: 0 }).unwrap();
+    assert_eq!((d.muid, d.slot), (7, 0));
+}
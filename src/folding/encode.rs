@@ -3,22 +3,60 @@
 use std::iter::repeat;
 
 use ff::PrimeField;
+use halo2::halo2curves::CurveAffine;
 use itertools::Itertools;
 use num_bigint::BigUint;
 use num_traits::FromBytes;
 
-use crate::utils::arith_helper::shift64;
+use crate::utils::arith_helper::{shift64, to_biguint, from_biguint, modulus};
 
 
 
+/// Encodes an element of an arbitrary (at most 256-bit) prime field as two 128-bit limbs.
+pub fn encode_nonnative<R: PrimeField, B: PrimeField>(x: B) -> Vec<R> {
+    let x = BigUint::from_le_bytes(x.to_repr().as_ref());
+    let mut x = x.to_u64_digits();
+    assert!(x.len() <= 4);
+    x.extend(repeat(0).take(4-x.len()));
+    let x = x.into_iter().map(|v|R::from(v)).collect_vec();
+    vec![x[0]+shift64(x[1]), x[2]+shift64(x[3])]
+}
+
+/// Encodes a curve point by its coordinates. Point at infinity is encoded as all zeros.
+pub fn encode_point<R: PrimeField, C: CurveAffine>(pt: C) -> Vec<R> {
+    let coords = pt.coordinates();
+    if bool::from(coords.is_some()) {
+        let coords = coords.unwrap();
+        let mut ret = encode_nonnative(*coords.x());
+        ret.extend(encode_nonnative::<R, _>(*coords.y()));
+        ret
+    } else {
+        vec![R::ZERO; 4]
+    }
+}
+
+/// Decodes an element encoded by encode_nonnative. Panics if the encoding is not canonical.
+pub fn decode_nonnative<R: PrimeField, B: PrimeField>(limbs: &[R]) -> B {
+    assert!(limbs.len() == 2);
+    let x = to_biguint(limbs[0]) + (to_biguint(limbs[1]) << 128u32);
+    assert!(to_biguint(limbs[0]) >> 128u32 == BigUint::from(0u8) && x < modulus::<B>(), "Non-canonical encoding.");
+    from_biguint(&x)
+}
+
+/// Decodes a point encoded by encode_point. Panics if the encoding is not canonical or the point is not on the curve.
+pub fn decode_point<R: PrimeField, C: CurveAffine>(limbs: &[R]) -> C {
+    assert!(limbs.len() == 4);
+    if limbs.iter().all(|x| bool::from(x.is_zero())) {
+        return C::identity()
+    }
+    let x = decode_nonnative(&limbs[..2]);
+    let y = decode_nonnative(&limbs[2..]);
+    Option::from(C::from_xy(x, y)).expect("Point is not on the curve.")
+}
+
 pub trait Encoded<R: PrimeField> : PrimeField + Copy{
     fn encode(self) -> Vec<R> {
-        let x = BigUint::from_le_bytes(self.to_repr().as_ref());
-        let mut x = x.to_u64_digits();
-        assert!(x.len() <= 4);
-        x.extend(repeat(0).take(4-x.len()));
-        let x = x.into_iter().map(|v|R::from(v)).collect_vec();
-        vec![x[0]+shift64(x[1]), x[2]+shift64(x[3])]
+        encode_nonnative(self)
     }
 }
 
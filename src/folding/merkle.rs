@@ -0,0 +1,164 @@
+// Native sparse Merkle tree with Poseidon as a 2-to-1 compression function.
+// Only non-empty nodes are stored; empty subtrees are replaced by precomputed roots of empty trees.
+
+use std::collections::HashMap;
+
+use ff::PrimeField;
+
+use super::poseidon::Poseidon;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Authentication path of a leaf. Siblings go from the leaf level up to the root.
+pub struct MerklePath<F: PrimeField> {
+    pub index: u64,
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> MerklePath<F> {
+    /// Computes the root of the tree containing a given leaf on this path.
+    pub fn root(&self, hasher: &Poseidon<F>, leaf: F) -> F {
+        self.siblings.iter().enumerate().fold(leaf, |acc, (i, sibling)| {
+            if (self.index >> i) & 1 == 0 {
+                hasher.hash(vec![acc, *sibling])
+            } else {
+                hasher.hash(vec![*sibling, acc])
+            }
+        })
+    }
+}
+
+pub struct MerkleTree<F: PrimeField> {
+    depth: usize,
+    hasher: Poseidon<F>,
+    /// empty[i] is the root of an empty tree of height i.
+    empty: Vec<F>,
+    /// Nodes indexed by (level, index), level 0 being leaves.
+    nodes: HashMap<(usize, u64), F>,
+    next_index: u64,
+}
+
+impl<F: PrimeField> MerkleTree<F> {
+    /// Creates a tree of 2^depth leaves, all of which are zero.
+    pub fn new(hasher: Poseidon<F>, depth: usize) -> Self {
+        assert!(depth > 0 && depth <= 64, "Unsupported tree depth {}", depth);
+        let mut empty = vec![F::ZERO];
+        for i in 0..depth {
+            empty.push(hasher.hash(vec![empty[i], empty[i]]));
+        }
+        Self { depth, hasher, empty, nodes: HashMap::new(), next_index: 0 }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn hasher(&self) -> &Poseidon<F> {
+        &self.hasher
+    }
+
+    fn check_index(&self, index: u64) {
+        assert!(self.depth == 64 || index >> self.depth == 0, "Leaf index {} is out of range.", index);
+    }
+
+    fn node(&self, level: usize, index: u64) -> F {
+        *self.nodes.get(&(level, index)).unwrap_or(&self.empty[level])
+    }
+
+    pub fn root(&self) -> F {
+        self.node(self.depth, 0)
+    }
+
+    pub fn get(&self, index: u64) -> F {
+        self.check_index(index);
+        self.node(0, index)
+    }
+
+    /// Sets a leaf and recomputes its path. Returns the previous value.
+    pub fn update(&mut self, index: u64, leaf: F) -> F {
+        self.check_index(index);
+        let old = self.node(0, index);
+        let mut cur = leaf;
+        let mut idx = index;
+        for level in 0..self.depth {
+            if cur == self.empty[level] {
+                self.nodes.remove(&(level, idx));
+            } else {
+                self.nodes.insert((level, idx), cur);
+            }
+            let sibling = self.node(level, idx ^ 1);
+            cur = if idx & 1 == 0 {
+                self.hasher.hash(vec![cur, sibling])
+            } else {
+                self.hasher.hash(vec![sibling, cur])
+            };
+            idx >>= 1;
+        }
+        self.nodes.insert((self.depth, 0), cur);
+        if index >= self.next_index {
+            self.next_index = index + 1;
+        }
+        old
+    }
+
+    /// Appends a leaf after the last occupied index, and returns its index.
+    pub fn insert(&mut self, leaf: F) -> u64 {
+        let index = self.next_index;
+        self.update(index, leaf);
+        index
+    }
+
+    pub fn prove(&self, index: u64) -> MerklePath<F> {
+        self.check_index(index);
+        let siblings = (0..self.depth).map(|level| self.node(level, (index >> level) ^ 1)).collect();
+        MerklePath { index, siblings }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use rand_core::OsRng;
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    #[test]
+    fn empty_tree() {
+        let tree = MerkleTree::<F>::new(Poseidon::new(), 4);
+        let cfg = Poseidon::new();
+        let mut root = F::ZERO;
+        for _ in 0..4 {
+            root = cfg.hash(vec![root, root]);
+        }
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.prove(5).root(&cfg, F::ZERO), root);
+    }
+
+    #[test]
+    fn update_and_prove() {
+        let mut sparse = MerkleTree::<F>::new(Poseidon::new(), 3);
+        let leaves: Vec<F> = (0..8).map(|_| F::random(OsRng)).collect();
+        for leaf in &leaves {
+            sparse.insert(*leaf);
+        }
+
+        // dense reference
+        let cfg = Poseidon::new();
+        let mut level = leaves.clone();
+        while level.len() > 1 {
+            level = level.chunks(2).map(|c| cfg.hash(vec![c[0], c[1]])).collect();
+        }
+        assert_eq!(sparse.root(), level[0]);
+
+        for i in 0..8 {
+            assert_eq!(sparse.prove(i).root(&cfg, leaves[i as usize]), sparse.root());
+        }
+
+        let old = sparse.update(6, F::ZERO);
+        assert_eq!(old, leaves[6]);
+        assert_eq!(sparse.prove(6).root(&cfg, F::ZERO), sparse.root());
+        assert_eq!(sparse.prove(2).root(&cfg, leaves[2]), sparse.root());
+    }
+}
@@ -1,6 +1,9 @@
 pub mod shape;
 pub mod poseidon;
 pub mod poseidon_constants;
+pub mod poseidon2;
 pub mod hasher;
 pub mod oracle;
-pub mod encode;
\ No newline at end of file
+pub mod encode;
+pub mod merkle;
+pub mod schnorr;
\ No newline at end of file
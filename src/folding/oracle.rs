@@ -5,6 +5,8 @@ use std::marker::PhantomData;
 use ff::{Field, PrimeField};
 use rand_core::OsRng;
 
+use super::hasher::HashConfig;
+
 pub trait Oracle<ProverMsg, Response> {
     /// Initialize a new oracle.
     fn new() -> Self;
@@ -38,7 +40,49 @@ impl<ProverMsg, R: PrimeField> Oracle<ProverMsg, R> for MockOracle<R> {
     }
 }
 
+/// Amount of elements absorbed by a single hash invocation of `HashOracle` (one more slot is taken by the digest).
+const HASH_ORACLE_RATE: usize = 15;
+
+/// Oracle which absorbs prover messages into a running digest of a hash function.
+pub struct HashOracle<R: PrimeField, H: HashConfig<R>> {
+    hasher: H,
+    state: R,
+}
 
+impl<R: PrimeField, H: HashConfig<R>> Oracle<Vec<R>, R> for HashOracle<R, H> {
+    fn new() -> Self {
+        Self{ hasher: H::new(), state: R::ZERO }
+    }
+    fn update(&mut self, msg: Vec<R>) {
+        for chunk in msg.chunks(HASH_ORACLE_RATE) {
+            let mut inp = vec![self.state];
+            inp.extend(chunk.iter().cloned());
+            self.state = self.hasher.hash(inp);
+        }
+    }
+    fn response(&self) -> R {
+        trunc128(self.state)
+    }
+}
+
+/// Oracle whose state can be saved and restored, see `CircuitRun::checkpoint_with_oracle`.
+pub trait SerializableOracle<R>: Oracle<Vec<R>, R> + Sized {
+    fn state(&self) -> Vec<R>;
+    /// Returns None if the state is not produced by `state`.
+    fn restore(state: &[R]) -> Option<Self>;
+}
+
+impl<R: PrimeField, H: HashConfig<R>> SerializableOracle<R> for HashOracle<R, H> {
+    fn state(&self) -> Vec<R> {
+        vec![self.state]
+    }
+    fn restore(state: &[R]) -> Option<Self> {
+        match state {
+            &[state] => Some(Self{ hasher: H::new(), state }),
+            _ => None,
+        }
+    }
+}
 
 mod tests{
     use super::*;
@@ -1,24 +1,29 @@
+use std::sync::{Arc, OnceLock};
 
-
-use ff::{Field, PrimeField};
+use ff::PrimeField;
 
 
 use halo2::halo2curves::bn256;
+use num_bigint::BigUint;
 
-
+use crate::utils::arith_helper::{modulus, from_biguint};
 
 use super::poseidon_constants;
 
 
-type F = bn256::Fr;
-
-pub struct Constants {
+/// Round constants and MDS matrices for all supported widths. Width t is stored at index t-2.
+#[derive(Debug)]
+pub struct Constants<F: PrimeField> {
     pub c: Vec<Vec<F>>,
     pub m: Vec<Vec<Vec<F>>>,
+    pub alpha: u64,
     pub n_rounds_f: usize,
     pub n_rounds_p: Vec<usize>,
 }
-pub fn load_constants() -> Constants {
+
+/// Parses circomlib constants for bn254. Prefer `Poseidon::new()`, which parses them only once.
+pub fn load_constants() -> Constants<bn256::Fr> {
+    type F = bn256::Fr;
     let (c_str, m_str) = poseidon_constants::constants();
     let mut c: Vec<Vec<F>> = Vec::new();
     for i in 0..c_str.len() {
@@ -45,6 +50,7 @@ pub fn load_constants() -> Constants {
     Constants {
         c,
         m,
+        alpha: 5,
         n_rounds_f: 8,
         n_rounds_p: vec![
             56, 57, 56, 60, 60, 63, 64, 63, 60, 66, 60, 65, 70, 60, 64, 68,
@@ -52,67 +58,267 @@ pub fn load_constants() -> Constants {
     }
 }
 
+/// Grain LFSR in self-shrinking mode, used to generate Poseidon parameters.
+/// Follows generate_parameters_grain.sage from the reference implementation.
+pub struct Grain {
+    state: Vec<bool>,
+}
+
+impl Grain {
+    /// Initializes the LFSR for the prime field of a given bit size and x^alpha sbox.
+    pub fn new(field_bits: usize, t: usize, n_rounds_f: usize, n_rounds_p: usize) -> Self {
+        let mut state = vec![];
+        let mut push_bits = |value: usize, len: usize| {
+            for i in (0..len).rev() {
+                state.push((value >> i) & 1 == 1);
+            }
+        };
+        push_bits(1, 2); // prime field
+        push_bits(0, 4); // x^alpha sbox
+        push_bits(field_bits, 12);
+        push_bits(t, 12);
+        push_bits(n_rounds_f, 10);
+        push_bits(n_rounds_p, 10);
+        push_bits((1 << 30) - 1, 30);
+
+        let mut grain = Self { state };
+        for _ in 0..160 {
+            grain.step();
+        }
+        grain
+    }
+
+    fn step(&mut self) -> bool {
+        let s = &self.state;
+        let bit = s[62] ^ s[51] ^ s[38] ^ s[23] ^ s[13] ^ s[0];
+        self.state.remove(0);
+        self.state.push(bit);
+        bit
+    }
+
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let b1 = self.step();
+            let b2 = self.step();
+            if b1 {
+                return b2
+            }
+        }
+    }
+
+    fn next_biguint(&mut self, bits: usize) -> BigUint {
+        (0..bits).fold(BigUint::from(0u8), |acc, _| (acc << 1u8) + BigUint::from(self.next_bit() as u8))
+    }
+
+    /// Samples a field element, rejecting values which are not smaller than the modulus.
+    pub fn next_field_element<F: PrimeField>(&mut self) -> F {
+        let p = modulus::<F>();
+        loop {
+            let x = self.next_biguint(F::NUM_BITS as usize);
+            if x < p {
+                return from_biguint(&x)
+            }
+        }
+    }
+
+    /// Samples a field element, reducing it modulo the field characteristic (this is what the reference does for MDS).
+    pub fn next_field_element_reduced<F: PrimeField>(&mut self) -> F {
+        let x = self.next_biguint(F::NUM_BITS as usize) % modulus::<F>();
+        from_biguint(&x)
+    }
+}
+
+impl<F: PrimeField> Constants<F> {
+    /// Generates constants for widths from 2 to n_rounds_p.len() + 1, where the partial round count for width t
+    /// is n_rounds_p[t-2]. Round constants and Cauchy MDS matrices are sampled from the Grain LFSR.
+    ///
+    /// The round numbers are not checked for security, and neither are MDS matrices checked
+    /// for invariant subspaces (algorithms 1-3 of the reference script); those are on the caller.
+    pub fn generate(alpha: u64, n_rounds_f: usize, n_rounds_p: Vec<usize>) -> Self {
+        assert!(alpha >= 3, "Sbox x^alpha requires alpha >= 3");
+        assert!(n_rounds_f % 2 == 0, "Amount of full rounds must be even");
+
+        let mut c = vec![];
+        let mut m = vec![];
+        for (i, &r_p) in n_rounds_p.iter().enumerate() {
+            let t = i + 2;
+            let mut grain = Grain::new(F::NUM_BITS as usize, t, n_rounds_f, r_p);
+            c.push((0..(n_rounds_f + r_p) * t).map(|_| grain.next_field_element()).collect());
+
+            let (xs, ys) = loop {
+                let sample: Vec<F> = (0..2*t).map(|_| grain.next_field_element_reduced()).collect();
+                let distinct = (0..2*t).all(|a| (0..a).all(|b| sample[a] != sample[b]));
+                if distinct {
+                    break (sample[..t].to_vec(), sample[t..].to_vec())
+                }
+            };
+            m.push(
+                xs.iter().map(|x| ys.iter().map(|y| (*x + y).invert().expect("sums in Cauchy matrix should be nonzero")).collect()).collect()
+            );
+        }
+
+        Self { c, m, alpha, n_rounds_f, n_rounds_p }
+    }
+}
+
+static CIRCOMLIB: OnceLock<Arc<Constants<bn256::Fr>>> = OnceLock::new();
 
-pub struct Poseidon {
-    pub constants: Constants,
+#[derive(Debug)]
+pub struct Poseidon<F: PrimeField = bn256::Fr> {
+    pub constants: Arc<Constants<F>>,
 }
 
-impl Poseidon {
-    pub fn new() -> Poseidon {
+impl<F: PrimeField> Clone for Poseidon<F> {
+    fn clone(&self) -> Self {
+        Self { constants: self.constants.clone() }
+    }
+}
+
+impl Poseidon<bn256::Fr> {
+    /// Circomlib instance over bn254. Constants are parsed on the first call and shared afterwards.
+    pub fn new() -> Self {
         Poseidon {
-            constants: load_constants(),
+            constants: CIRCOMLIB.get_or_init(|| Arc::new(load_constants())).clone(),
         }
     }
+}
 
+impl<F: PrimeField> Poseidon<F> {
+    pub fn from_constants(constants: Constants<F>) -> Self {
+        Self { constants: Arc::new(constants) }
+    }
+
+    /// Poseidon instance with Grain-generated parameters, see `Constants::generate`.
+    pub fn generate(alpha: u64, n_rounds_f: usize, n_rounds_p: Vec<usize>) -> Self {
+        Self::from_constants(Constants::generate(alpha, n_rounds_f, n_rounds_p))
+    }
+
+    /// Applies the permutation to a state of width t.
+    pub fn permute(&self, state: &mut Vec<F>) {
+        let t = state.len();
+        assert!(t >= 2 && t <= self.constants.n_rounds_p.len() + 1, "Unsupported state width {}", t);
+
+        let n_rounds_f = self.constants.n_rounds_f;
+        let n_rounds_p = self.constants.n_rounds_p[t - 2];
+
+        for i in 0..(n_rounds_f + n_rounds_p) {
+            ark(state, &self.constants.c[t - 2], i * t);
+            sbox(self.constants.alpha, n_rounds_f, n_rounds_p, state, i);
+            *state = mix(&state, &self.constants.m[t - 2]);
+        }
+    }
 
     pub fn hash(&self, inp: Vec<F>) -> F {
         let t = inp.len() + 1;
         // if inp.len() == 0 || inp.len() >= self.constants.n_rounds_p.len() - 1 {
         assert!(! (inp.is_empty() || inp.len() > self.constants.n_rounds_p.len()),
             "Wrong inputs length");
-        
-        let n_rounds_f = self.constants.n_rounds_f.clone();
-        let n_rounds_p = self.constants.n_rounds_p[t - 2].clone();
 
         let mut state = vec![F::ZERO; t];
         state[1..].clone_from_slice(&inp);
 
-        for i in 0..(n_rounds_f + n_rounds_p) {
-            ark(&mut state, &self.constants.c[t - 2], i * t);
-            sbox(n_rounds_f, n_rounds_p, &mut state, i);
-            state = mix(&state, &self.constants.m[t - 2]);
-        }
+        self.permute(&mut state);
 
         state[0]
     }
 }
 
-pub fn ark(state: &mut Vec<F>, c: &Vec<F>, it: usize) -> (){
+/// Position in the rate of the next absorbed or squeezed element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpongeMode {
+    Absorbing(usize),
+    Squeezing(usize),
+}
+
+/// Duplex sponge over the Poseidon permutation of width rate+1. The capacity element state[0] is initialized with
+/// a domain separation tag, and elements are added to the rate state[1..], which is permuted when full.
+/// Switching from absorbing to squeezing pads the absorbed elements with a single one, so inputs which differ only
+/// by trailing zeros are not confused. Squeezing outputs the rate elements one by one, permuting in between, and
+/// absorbing after squeezing starts from the beginning of the rate again.
+#[derive(Clone)]
+pub struct PoseidonSponge<F: PrimeField = bn256::Fr> {
+    cfg: Poseidon<F>,
+    state: Vec<F>,
+    mode: SpongeMode,
+}
+
+impl<F: PrimeField> PoseidonSponge<F> {
+    pub fn new(cfg: &Poseidon<F>, rate: usize, domain: F) -> Self {
+        assert!(rate > 0 && rate <= cfg.constants.n_rounds_p.len(), "Unsupported rate {}", rate);
+        let mut state = vec![F::ZERO; rate + 1];
+        state[0] = domain;
+        Self { cfg: cfg.clone(), state, mode: SpongeMode::Absorbing(0) }
+    }
+
+    pub fn rate(&self) -> usize {
+        self.state.len() - 1
+    }
+
+    pub fn mode(&self) -> SpongeMode {
+        self.mode
+    }
+
+    fn absorb_one(&mut self, x: F) {
+        let pos = match self.mode {
+            SpongeMode::Absorbing(pos) if pos == self.rate() => {
+                self.cfg.permute(&mut self.state);
+                0
+            },
+            SpongeMode::Absorbing(pos) => pos,
+            SpongeMode::Squeezing(_) => 0,
+        };
+        self.state[1 + pos] += x;
+        self.mode = SpongeMode::Absorbing(pos + 1);
+    }
+
+    pub fn absorb(&mut self, inp: &[F]) {
+        for x in inp {
+            self.absorb_one(*x);
+        }
+    }
+
+    pub fn squeeze(&mut self) -> F {
+        let pos = match self.mode {
+            SpongeMode::Absorbing(_) => {
+                self.absorb_one(F::ONE);
+                self.cfg.permute(&mut self.state);
+                0
+            },
+            SpongeMode::Squeezing(pos) if pos == self.rate() => {
+                self.cfg.permute(&mut self.state);
+                0
+            },
+            SpongeMode::Squeezing(pos) => pos,
+        };
+        self.mode = SpongeMode::Squeezing(pos + 1);
+        self.state[1 + pos]
+    }
+
+    pub fn squeeze_n(&mut self, n: usize) -> Vec<F> {
+        (0..n).map(|_| self.squeeze()).collect()
+    }
+}
+
+pub fn ark<F: PrimeField>(state: &mut Vec<F>, c: &Vec<F>, it: usize) -> (){
     for i in 0..state.len() {
         state[i] += &c[it+i];
     }
 }
 
-pub fn sbox(n_rounds_f: usize, n_rounds_p: usize, state: &mut Vec<F>, i: usize) -> () {
+pub fn sbox<F: PrimeField>(alpha: u64, n_rounds_f: usize, n_rounds_p: usize, state: &mut Vec<F>, i: usize) -> () {
     if i < n_rounds_f / 2 || i >= n_rounds_f / 2 + n_rounds_p {
         for j in 0..state.len() {
-            let aux = state[j];
-            state[j] = state[j].square();
-            state[j] = state[j].square();
-            state[j] *= &aux;
+            state[j] = state[j].pow_vartime([alpha]);
         }
     } else {
-        let aux = state[0];
-        state[0] = state[0].square();
-        state[0] = state[0].square();
-        state[0] *= &aux;
+        state[0] = state[0].pow_vartime([alpha]);
     }
 }
 
-pub fn mix(state: &Vec<F>, m: &Vec<Vec<F>>) -> Vec<F> {
+pub fn mix<F: PrimeField>(state: &Vec<F>, m: &Vec<Vec<F>>) -> Vec<F> {
     let mut new_state: Vec<F> = Vec::new();
     for i in 0..state.len() {
-        new_state.push(F::zero());
+        new_state.push(F::ZERO);
         for j in 0..state.len() {
             let mut mij = m[i][j];
             mij *= &state[j];
@@ -120,4 +326,89 @@ pub fn mix(state: &Vec<F>, m: &Vec<Vec<F>>) -> Vec<F> {
         }
     }
     new_state
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::{Field, PrimeField};
+    use halo2::halo2curves::{bn256, pasta};
+
+    use super::{Constants, Poseidon, PoseidonSponge};
+
+    #[test]
+    fn test_grain_reproduces_circomlib() {
+        let circomlib = Poseidon::new();
+        let generated = Constants::<bn256::Fr>::generate(5, 8, circomlib.constants.n_rounds_p[..4].to_vec());
+        for t in 2..6 {
+            assert_eq!(generated.c[t-2], circomlib.constants.c[t-2]);
+            assert_eq!(generated.m[t-2], circomlib.constants.m[t-2]);
+        }
+    }
+
+    #[test]
+    fn test_circomlib_vectors() {
+        let cfg = Poseidon::new();
+        assert_eq!(cfg.hash(vec![bn256::Fr::from(1)]), bn256::Fr::from_str_vartime("18586133768512220936620570745912940619677854269274689475585506675881198879027").unwrap());
+        assert_eq!(cfg.hash(vec![bn256::Fr::from(1), bn256::Fr::from(2)]), bn256::Fr::from_str_vartime("7853200120776062878684798364095072458815029376092732009249414926327459813530").unwrap());
+    }
+
+    #[test]
+    fn test_constants_shared() {
+        let a = Poseidon::new();
+        let b = Poseidon::new();
+        assert!(std::sync::Arc::ptr_eq(&a.constants, &b.constants));
+    }
+
+    #[test]
+    fn test_generic_field() {
+        let cfg = Poseidon::<pasta::Fp>::generate(5, 8, vec![56, 56]);
+        let x = cfg.hash(vec![pasta::Fp::from(1), pasta::Fp::from(2)]);
+        let y = cfg.hash(vec![pasta::Fp::from(2), pasta::Fp::from(1)]);
+        assert_ne!(x, y);
+        assert_eq!(x, cfg.hash(vec![pasta::Fp::from(1), pasta::Fp::from(2)]));
+        assert_eq!(cfg.constants.c[1].len(), (8 + 56) * 3);
+        assert!(cfg.constants.c[0][0] != pasta::Fp::from_u128(0));
+    }
+
+    #[test]
+    fn test_sponge_matches_permutation() {
+        type F = bn256::Fr;
+        let cfg = Poseidon::new();
+        let mut sponge = PoseidonSponge::new(&cfg, 2, F::from(7));
+        sponge.absorb(&[F::from(1), F::from(2), F::from(3)]);
+        let out = sponge.squeeze_n(3);
+
+        let mut state = vec![F::from(7), F::from(1), F::from(2)];
+        cfg.permute(&mut state);
+        state[1] += F::from(3);
+        state[2] += F::from(1);
+        cfg.permute(&mut state);
+        let mut expected = state[1..].to_vec();
+        cfg.permute(&mut state);
+        expected.push(state[1]);
+        assert_eq!(out, expected);
+
+        // Absorbing after squeezing overwrites the rate from the start.
+        sponge.absorb(&[F::from(4)]);
+        state[1] += F::from(4);
+        state[2] += F::from(1);
+        cfg.permute(&mut state);
+        assert_eq!(sponge.squeeze(), state[1]);
+    }
+
+    #[test]
+    fn test_sponge_domain_separation() {
+        type F = bn256::Fr;
+        let cfg = Poseidon::new();
+        let squeeze = |domain: u64, inp: &[F]| {
+            let mut sponge = PoseidonSponge::new(&cfg, 3, F::from(domain));
+            sponge.absorb(inp);
+            sponge.squeeze()
+        };
+        let x = F::from(5);
+        assert_ne!(squeeze(0, &[x]), squeeze(0, &[x, F::ZERO]));
+        assert_ne!(squeeze(0, &[]), squeeze(0, &[F::ZERO]));
+        assert_ne!(squeeze(0, &[x]), squeeze(1, &[x]));
+        assert_eq!(squeeze(1, &[x, F::ONE, x]), squeeze(1, &[x, F::ONE, x]));
+    }
+}
@@ -0,0 +1,248 @@
+// Poseidon2 permutation (https://eprint.iacr.org/2023/323).
+// Differs from Poseidon by its linear layers: external rounds use a cheap MDS matrix, and internal rounds use the
+// matrix J + diag(mu), which is evaluated with t multiplications instead of t^2. Internal rounds add a single round
+// constant, and the permutation starts with an application of the external matrix.
+
+use std::sync::{Arc, OnceLock};
+
+use ff::PrimeField;
+use halo2::halo2curves::bn256;
+
+use super::poseidon::Grain;
+
+/// 4x4 MDS matrix of the external layer for widths divisible by 4.
+const M4: [[u64; 4]; 4] = [
+    [5, 7, 1, 3],
+    [4, 6, 1, 1],
+    [1, 3, 5, 7],
+    [1, 1, 4, 6],
+];
+
+/// Round constants and linear layers of Poseidon2 for a single width t.
+pub struct Poseidon2Constants<F: PrimeField> {
+    pub t: usize,
+    pub alpha: u64,
+    pub n_rounds_f: usize,
+    pub n_rounds_p: usize,
+    /// Round constants of external rounds, first and second half together.
+    pub c_ext: Vec<Vec<F>>,
+    /// Round constants of internal rounds, added to state[0].
+    pub c_int: Vec<F>,
+    /// Internal matrix is J + diag(mu), where J is the all-ones matrix.
+    pub mu: Vec<F>,
+}
+
+impl<F: PrimeField> Poseidon2Constants<F> {
+    /// Generates round constants from the Grain LFSR, like the reference implementation does: t per external round
+    /// and one per internal round. Supported widths are 2, 3 and multiples of 4.
+    ///
+    /// Neither the round numbers nor the internal matrix given by mu are checked for security, this is on the caller.
+    pub fn generate(t: usize, alpha: u64, n_rounds_f: usize, n_rounds_p: usize, mu: Vec<F>) -> Self {
+        assert!(t == 2 || t == 3 || t % 4 == 0, "Unsupported state width {}", t);
+        assert!(alpha >= 3, "Sbox x^alpha requires alpha >= 3");
+        assert!(n_rounds_f % 2 == 0, "Amount of full rounds must be even");
+        assert!(mu.len() == t, "Internal matrix diagonal must have length t");
+
+        let mut grain = Grain::new(F::NUM_BITS as usize, t, n_rounds_f, n_rounds_p);
+        let mut c_ext = vec![];
+        let mut c_int = vec![];
+        for i in 0..(n_rounds_f + n_rounds_p) {
+            if i < n_rounds_f / 2 || i >= n_rounds_f / 2 + n_rounds_p {
+                c_ext.push((0..t).map(|_| grain.next_field_element()).collect());
+            } else {
+                c_int.push(grain.next_field_element());
+            }
+        }
+
+        Self { t, alpha, n_rounds_f, n_rounds_p, c_ext, c_int, mu }
+    }
+
+    pub fn is_external(&self, i: usize) -> bool {
+        i < self.n_rounds_f / 2 || i >= self.n_rounds_f / 2 + self.n_rounds_p
+    }
+
+    pub fn ark(&self, state: &mut [F], i: usize) {
+        if self.is_external(i) {
+            let c = &self.c_ext[if i < self.n_rounds_f / 2 {i} else {i - self.n_rounds_p}];
+            for j in 0..self.t {
+                state[j] += c[j];
+            }
+        } else {
+            state[0] += self.c_int[i - self.n_rounds_f / 2];
+        }
+    }
+
+    pub fn sbox(&self, state: &mut [F], i: usize) {
+        if self.is_external(i) {
+            for x in state.iter_mut() {
+                *x = x.pow_vartime([self.alpha]);
+            }
+        } else {
+            state[0] = state[0].pow_vartime([self.alpha]);
+        }
+    }
+
+    pub fn mix(&self, state: &mut [F], i: usize) {
+        if self.is_external(i) {
+            external_mix(state);
+        } else {
+            internal_mix(state, &self.mu);
+        }
+    }
+
+    /// Executes the i-th round.
+    pub fn round(&self, state: &mut [F], i: usize) {
+        self.ark(state, i);
+        self.sbox(state, i);
+        self.mix(state, i);
+    }
+}
+
+/// Applies the external matrix: circ(2, 1, ..., 1) for t = 2, 3, M4 for t = 4 and circ(2 M4, M4, ..., M4) for larger
+/// multiples of 4.
+pub fn external_mix<F: PrimeField>(state: &mut [F]) {
+    let t = state.len();
+    if t < 4 {
+        let sum = state.iter().fold(F::ZERO, |acc, x| acc + x);
+        for x in state.iter_mut() {
+            *x += sum;
+        }
+        return
+    }
+
+    for chunk in state.chunks_mut(4) {
+        let tmp: Vec<F> = M4.iter()
+            .map(|row| row.iter().zip(chunk.iter()).fold(F::ZERO, |acc, (m, x)| acc + F::from(*m) * x))
+            .collect();
+        chunk.copy_from_slice(&tmp);
+    }
+    if t == 4 {
+        return
+    }
+    let mut sums = [F::ZERO; 4];
+    for chunk in state.chunks(4) {
+        for j in 0..4 {
+            sums[j] += chunk[j];
+        }
+    }
+    for chunk in state.chunks_mut(4) {
+        for j in 0..4 {
+            chunk[j] += sums[j];
+        }
+    }
+}
+
+/// Applies the internal matrix J + diag(mu).
+pub fn internal_mix<F: PrimeField>(state: &mut [F], mu: &[F]) {
+    let sum = state.iter().fold(F::ZERO, |acc, x| acc + x);
+    for (x, m) in state.iter_mut().zip(mu.iter()) {
+        *x = *x * m + sum;
+    }
+}
+
+static BN254: [OnceLock<Arc<Poseidon2Constants<bn256::Fr>>>; 2] = [OnceLock::new(), OnceLock::new()];
+
+pub struct Poseidon2<F: PrimeField = bn256::Fr> {
+    pub constants: Arc<Poseidon2Constants<F>>,
+}
+
+impl<F: PrimeField> Clone for Poseidon2<F> {
+    fn clone(&self) -> Self {
+        Self { constants: self.constants.clone() }
+    }
+}
+
+impl Poseidon2<bn256::Fr> {
+    /// Reference bn254 instance of width 2 or 3, with 8 full and 56 partial rounds. Constants are generated on the
+    /// first call and shared afterwards.
+    pub fn new(t: usize) -> Self {
+        assert!(t == 2 || t == 3, "Reference bn254 parameters are only available for widths 2 and 3.");
+        let constants = BN254[t - 2].get_or_init(|| {
+            let mu = if t == 2 {vec![1, 2]} else {vec![1, 1, 2]};
+            Arc::new(Poseidon2Constants::generate(t, 5, 8, 56, mu.into_iter().map(bn256::Fr::from).collect()))
+        });
+        Self { constants: constants.clone() }
+    }
+}
+
+impl<F: PrimeField> Poseidon2<F> {
+    pub fn from_constants(constants: Poseidon2Constants<F>) -> Self {
+        Self { constants: Arc::new(constants) }
+    }
+
+    /// Poseidon2 instance with Grain-generated parameters, see `Poseidon2Constants::generate`.
+    pub fn generate(t: usize, alpha: u64, n_rounds_f: usize, n_rounds_p: usize, mu: Vec<F>) -> Self {
+        Self::from_constants(Poseidon2Constants::generate(t, alpha, n_rounds_f, n_rounds_p, mu))
+    }
+
+    pub fn width(&self) -> usize {
+        self.constants.t
+    }
+
+    pub fn permute(&self, state: &mut [F]) {
+        let constants = &self.constants;
+        assert!(state.len() == constants.t, "Wrong state width {}, expected {}", state.len(), constants.t);
+
+        external_mix(state);
+        for i in 0..(constants.n_rounds_f + constants.n_rounds_p) {
+            constants.round(state, i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::{Field, PrimeField};
+    use halo2::halo2curves::{bn256, pasta};
+
+    use super::{Poseidon2, external_mix};
+
+    #[test]
+    fn test_reference_vector() {
+        type F = bn256::Fr;
+        let cfg = Poseidon2::new(3);
+        let mut state = vec![F::from(0), F::from(1), F::from(2)];
+        cfg.permute(&mut state);
+        let expected = [
+            "5297208644449048816064511434384511824916970985131888684874823260532015509555",
+            "21816030159894113985964609355246484851575571273661473159848781012394295965040",
+            "13940986381491601233448981668101586453321811870310341844570924906201623195336",
+        ];
+        assert_eq!(state, expected.iter().map(|x| F::from_str_vartime(x).unwrap()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_constants_shared() {
+        let a = Poseidon2::new(2);
+        let b = Poseidon2::new(2);
+        assert!(std::sync::Arc::ptr_eq(&a.constants, &b.constants));
+        assert!(!std::sync::Arc::ptr_eq(&a.constants, &Poseidon2::new(3).constants));
+    }
+
+    #[test]
+    fn test_external_mix_width_8() {
+        type F = pasta::Fp;
+        let mut state = (0..8).map(|i| F::from(i + 1)).collect::<Vec<_>>();
+        external_mix(&mut state);
+        // M4 applied to (1, 2, 3, 4) and (5, 6, 7, 8), then every block gets the sum of both.
+        let a = [34u64, 23, 50, 39];
+        let b = [98u64, 71, 114, 87];
+        let expected = (0..8).map(|i| F::from(if i < 4 {2 * a[i] + b[i]} else {a[i - 4] + 2 * b[i - 4]})).collect::<Vec<_>>();
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn test_generic_field() {
+        type F = pasta::Fp;
+        let mu = (1..9).map(F::from).collect();
+        let cfg = Poseidon2::<F>::generate(8, 5, 8, 57, mu);
+        assert_eq!(cfg.constants.c_ext.len(), 8);
+        assert_eq!(cfg.constants.c_int.len(), 57);
+        let mut x = vec![F::ZERO; 8];
+        let mut y = vec![F::ZERO; 8];
+        y[7] = F::ONE;
+        cfg.permute(&mut x);
+        cfg.permute(&mut y);
+        assert_ne!(x, y);
+    }
+}
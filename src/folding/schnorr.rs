@@ -0,0 +1,108 @@
+// Native Schnorr signatures over a curve whose base field is the field of Poseidon, f.e. grumpkin with bn254 Poseidon.
+// A signature (R, s) of msg under pk is valid if s G = R + e pk. The challenge e is the lowest CHALLENGE_BITS bits of
+// Poseidon(R.x, R.y, pk.x, pk.y, msg), so it is a valid element of both the base and the scalar field.
+
+use ff::{Field, PrimeField};
+use group::Group;
+use halo2::halo2curves::CurveExt;
+use num_bigint::BigUint;
+use num_traits::One;
+use rand_core::RngCore;
+
+use crate::utils::arith_helper::{j2a, to_biguint, from_biguint};
+
+use super::poseidon::Poseidon;
+
+pub const CHALLENGE_BITS: usize = 128;
+
+/// Affine coordinates of a point which is not the identity.
+fn coordinates<C: CurveExt>(pt: C) -> (C::Base, C::Base) {
+    assert!(!bool::from(pt.is_identity()), "Point at infinity does not have affine coordinates.");
+    j2a(pt.jacobian_coordinates())
+}
+
+/// Reinterprets an integer below 2^CHALLENGE_BITS as an element of another field.
+fn convert<F: PrimeField, S: PrimeField>(x: F) -> S {
+    from_biguint(&to_biguint(x))
+}
+
+pub fn challenge<C: CurveExt>(hasher: &Poseidon<C::Base>, r: C, pk: C, msg: C::Base) -> C::Base {
+    let (rx, ry) = coordinates(r);
+    let (px, py) = coordinates(pk);
+    let h = to_biguint(hasher.hash(vec![rx, ry, px, py, msg]));
+    from_biguint(&(h % (BigUint::one() << CHALLENGE_BITS)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SchnorrSignature<C: CurveExt> {
+    pub r: C,
+    pub s: C::ScalarExt,
+}
+
+impl<C: CurveExt> SchnorrSignature<C> {
+    /// Splits s into halves of 128 bits, represented in the base field.
+    pub fn s_halves(&self) -> (C::Base, C::Base) {
+        let s = to_biguint(self.s);
+        let mask = (BigUint::one() << 128) - BigUint::one();
+        (from_biguint(&(&s & mask)), from_biguint(&(s >> 128)))
+    }
+}
+
+pub struct SigningKey<C: CurveExt> {
+    secret: C::ScalarExt,
+    public: C,
+}
+
+impl<C: CurveExt> SigningKey<C> {
+    pub fn random(mut rng: impl RngCore) -> Self {
+        let secret = C::ScalarExt::random(&mut rng);
+        Self { secret, public: C::generator() * secret }
+    }
+
+    pub fn public_key(&self) -> C {
+        self.public
+    }
+
+    pub fn sign(&self, hasher: &Poseidon<C::Base>, msg: C::Base, mut rng: impl RngCore) -> SchnorrSignature<C> {
+        let k = C::ScalarExt::random(&mut rng);
+        let r = C::generator() * k;
+        let e = challenge(hasher, r, self.public, msg);
+        SchnorrSignature { r, s: k + convert::<_, C::ScalarExt>(e) * self.secret }
+    }
+}
+
+pub fn verify<C: CurveExt>(hasher: &Poseidon<C::Base>, pk: C, msg: C::Base, sig: &SchnorrSignature<C>) -> bool {
+    if bool::from(pk.is_identity() | sig.r.is_identity()) {
+        return false
+    }
+    let e = challenge(hasher, sig.r, pk, msg);
+    C::generator() * sig.s == sig.r + pk * convert::<_, C::ScalarExt>(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2::halo2curves::{bn256, grumpkin};
+    use rand_core::OsRng;
+
+    use super::*;
+
+    type F = bn256::Fr;
+    type C = grumpkin::G1;
+
+    #[test]
+    fn sign_and_verify() {
+        let hasher = Poseidon::new();
+        let key = SigningKey::<C>::random(OsRng);
+        let msg = F::random(OsRng);
+        let sig = key.sign(&hasher, msg, OsRng);
+        assert!(verify(&hasher, key.public_key(), msg, &sig));
+
+        assert!(!verify(&hasher, key.public_key(), msg + F::ONE, &sig));
+        assert!(!verify(&hasher, C::generator(), msg, &sig));
+        let forged = SchnorrSignature { r: sig.r, s: sig.s + <C as CurveExt>::ScalarExt::ONE };
+        assert!(!verify(&hasher, key.public_key(), msg, &forged));
+
+        let (lo, hi) = sig.s_halves();
+        assert_eq!(to_biguint(lo) + (to_biguint(hi) << 128), to_biguint(sig.s));
+    }
+}
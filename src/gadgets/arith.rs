@@ -2,7 +2,7 @@ use std::rc::Rc;
 use ff::PrimeField;
 use gate_macro::make_gate;
 use itertools::Itertools;
-use crate::{circuit::{Circuit, Advice}, utils::field_precomp::FieldUtils, gate::Gatebb, constraint_system::Variable};
+use crate::{circuit::{Circuit, Advice, AdviceError, PolyOp}, utils::field_precomp::FieldUtils, gate::Gatebb, constraint_system::Variable, gatelib::nonzero_check};
 use elsa::FrozenMap;
 
 
@@ -28,6 +28,30 @@ pub fn read_const_gate<'c, F: PrimeField>(c: F)->Gatebb<'c, F>{
         vec![a-c]
     }), vec![c])
 }
+/// b z = 0 and inv z = 0, see div_or_zero_gadget.
+#[make_gate]
+pub fn div_or_zero_gate<'c, F: PrimeField>()->Gatebb<'c, F>{
+    Gatebb::new(2, 3, 2, Rc::new(|args, _|{
+        let (b, inv, z) = (args[0], args[1], args[2]);
+        vec![b*z, inv*z]
+    }), vec![])
+}
+
+/// Checks the outputs of sqrt_gadget, with g being the multiplicative generator: s is boolean,
+/// r^2 = x for s = 1, and r^2 = g x with r != 0 for s = 0.
+#[make_gate]
+pub fn sqrt_gate<'c, F: PrimeField>()->Gatebb<'c, F>{
+    Gatebb::new(3, 4, 3, Rc::new(|args, _|{
+        let (x, r, s, w) = (args[0], args[1], args[2], args[3]);
+        let g = F::MULTIPLICATIVE_GENERATOR;
+        vec![
+            s*(F::ONE-s),
+            r*r - x*(s + (F::ONE-s)*g),
+            (F::ONE-s)*(r*w - F::ONE),
+        ]
+    }), vec![])
+}
+
 pub fn arith_gadget<'a, F: PrimeField+FieldUtils>(
     circuit: &mut Circuit<'a,F,Gatebb<'a,F>>,
     a: Variable,
@@ -86,3 +110,179 @@ pub fn read_const_gadget<'a, F: PrimeField+FieldUtils>(
     circuit.constrain_with(&vec![v], &read_const_gate(c));
     v
 }
+
+/// Returns 1/x, constraining x * inv = 1. Fails with DivisionByZero for x = 0.
+pub fn inv_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a,F,Gatebb<'a,F>>,
+    x: Variable,
+    round: usize,
+) -> Variable {
+    let advice = Advice::new_fallible(1, 1, |args: &[F], _| {
+        Option::<F>::from(args[0].invert()).map(|inv| vec![inv]).ok_or(AdviceError::DivisionByZero)
+    });
+    let inv = circuit.advice(round, advice, vec![x])[0];
+    circuit.constrain_with(&vec![x, inv], &nonzero_check());
+    inv
+}
+
+/// Returns a/b, constraining b * q = a. Fails with DivisionByZero for b = 0.
+/// Does not check that b != 0, and for a = b = 0 any q satisfies the constraint: use inv_gadget or Nonzeros
+/// if b is not known to be nonzero, or div_or_zero_gadget.
+pub fn div_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a,F,Gatebb<'a,F>>,
+    a: Variable,
+    b: Variable,
+    round: usize,
+) -> Variable {
+    let advice = Advice::new_fallible(2, 1, |args: &[F], _| {
+        Option::<F>::from(args[1].invert()).map(|inv| vec![args[0] * inv]).ok_or(AdviceError::DivisionByZero)
+    });
+    let q = circuit.advice(round, advice, vec![a, b])[0];
+    circuit.constrain_with(&vec![b, q, a], &arith_gate(F::ONE, F::ZERO, F::ZERO, F::ZERO));
+    q
+}
+
+/// Returns (q, z), where z = 1 if b = 0 and z = 0 otherwise, and q = a/b, or 0 for b = 0. Never fails.
+pub fn div_or_zero_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a,F,Gatebb<'a,F>>,
+    a: Variable,
+    b: Variable,
+    round: usize,
+) -> (Variable, Variable) {
+    let inv = circuit.advice(round, Advice::new(1, 1, |args: &[F], _| {
+        vec![args[0].invert().unwrap_or(F::ZERO)]
+    }), vec![b])[0];
+
+    let ret = circuit.apply(round, PolyOp::new(2, 3, 2, |args, _| {
+        let (a, b, inv) = (args[0], args[1], args[2]);
+        vec![a*inv, F::ONE - b*inv]
+    }), vec![a, b, inv]);
+    let (q, z) = (ret[0], ret[1]);
+
+    // For b != 0, b z = 0 forces z = 0, and so inv = 1/b. For b = 0, z = 1, and inv z = 0 forces inv = 0.
+    circuit.constrain_with(&vec![b, inv, z], &div_or_zero_gate());
+    (q, z)
+}
+
+/// Returns (r, s), where s = 1 if x is a square (including 0) and s = 0 otherwise. For squares r^2 = x,
+/// and for non-squares r^2 = g x, g being the multiplicative generator of the field, which is not a square.
+/// Allocates an additional witness, which is 1/r for non-squares.
+pub fn sqrt_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a,F,Gatebb<'a,F>>,
+    x: Variable,
+    round: usize,
+) -> (Variable, Variable) {
+    let advice = Advice::new_fallible(1, 3, |args: &[F], _| {
+        let x = args[0];
+        if let Some(r) = Option::<F>::from(x.sqrt()) {
+            return Ok(vec![r, F::ONE, F::ZERO])
+        }
+        // x is nonzero here, so r is invertible.
+        let r = Option::<F>::from((F::MULTIPLICATIVE_GENERATOR * x).sqrt()).ok_or(AdviceError::NonResidue)?;
+        Ok(vec![r, F::ZERO, r.invert().unwrap()])
+    });
+    let ret = circuit.advice(round, advice, vec![x]);
+    let (r, s, w) = (ret[0], ret[1], ret[2]);
+
+    circuit.constrain_with(&vec![x, r, s, w], &sqrt_gate());
+    (r, s)
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use rand_core::OsRng;
+
+    use crate::{circuit::ExecutionError, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    #[test]
+    fn inversion_and_division() {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(2);
+        let a = input(&mut circuit, ext[0], 0);
+        let b = input(&mut circuit, ext[1], 0);
+        let inv = inv_gadget(&mut circuit, b, 0);
+        let q = div_gadget(&mut circuit, a, b, 0);
+        let constructed = circuit.finalize();
+
+        let (x, y) = (F::random(OsRng), F::random(OsRng));
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext[0], x);
+        instance.set_ext(ext[1], y);
+        instance.execute(0);
+        instance.valid_witness();
+        assert_eq!(instance.cs.getvar(inv) * y, F::ONE);
+        assert_eq!(instance.cs.getvar(q) * y, x);
+        instance.finish();
+
+        // Operations are loading of one, the two inputs, then the advice of inv_gadget.
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext[0], x);
+        instance.set_ext(ext[1], F::ZERO);
+        assert_eq!(instance.try_execute(0), Err(ExecutionError { round: 0, op: 3, error: AdviceError::DivisionByZero }));
+        instance.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "DivisionByZero")]
+    fn execute_panics_on_failed_advice() {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(1)[0];
+        let x = input(&mut circuit, ext, 0);
+        inv_gadget(&mut circuit, x, 0);
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext, F::ZERO);
+        instance.execute(0);
+    }
+
+    #[test]
+    fn division_or_zero() {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(2);
+        let a = input(&mut circuit, ext[0], 0);
+        let b = input(&mut circuit, ext[1], 0);
+        let (q, z) = div_or_zero_gadget(&mut circuit, a, b, 0);
+        let constructed = circuit.finalize();
+
+        let (x, y) = (F::random(OsRng), F::random(OsRng));
+        for (va, vb) in [(x, y), (x, F::ZERO), (F::ZERO, F::ZERO), (F::ZERO, y)] {
+            let mut instance = constructed.spawn();
+            instance.set_ext(ext[0], va);
+            instance.set_ext(ext[1], vb);
+            instance.execute(0);
+            instance.valid_witness();
+            assert_eq!(instance.cs.getvar(z), if vb == F::ZERO {F::ONE} else {F::ZERO});
+            assert_eq!(instance.cs.getvar(q), if vb == F::ZERO {F::ZERO} else {va * vb.invert().unwrap()});
+            assert_eq!(instance.cs.wtns[0].privs.len(), 3);
+            instance.finish();
+        }
+    }
+
+    #[test]
+    fn square_roots() {
+        let mut circuit = Circuit::new(3, 1);
+        let ext = circuit.ext_val(1)[0];
+        let x = input(&mut circuit, ext, 0);
+        let (r, s) = sqrt_gadget(&mut circuit, x, 0);
+        let constructed = circuit.finalize();
+
+        let y = F::random(OsRng);
+        let g = F::MULTIPLICATIVE_GENERATOR;
+        for (v, is_square) in [(F::ZERO, true), (F::ONE, true), (y.square(), true), (g, false), (g * y.square(), false)] {
+            let mut instance = constructed.spawn();
+            instance.set_ext(ext, v);
+            instance.execute(0);
+            instance.valid_witness();
+            let root = instance.cs.getvar(r);
+            assert_eq!(instance.cs.getvar(s), if is_square {F::ONE} else {F::ZERO});
+            assert_eq!(root.square(), if is_square {v} else {g * v});
+            instance.finish();
+        }
+    }
+}
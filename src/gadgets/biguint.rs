@@ -0,0 +1,419 @@
+// Arbitrary precision unsigned integers, represented by a fixed number of little-endian limbs in base 2^limb_bits.
+// Like in emulated.rs, multiplication and reduction are proven as integer identities in limbs, but the modulus is
+// a variable here, so the identities contain products q*m of two variables.
+//
+// RSA verification computes s^e mod n by square-and-multiply with the public exponent e, and compares the result
+// with the PKCS#1 v1.5 encoding of a SHA-256 digest.
+
+use std::marker::PhantomData;
+
+use ff::PrimeField;
+use itertools::Itertools;
+use num_bigint::{BigInt, BigUint};
+use num_traits::{One, Zero};
+
+use crate::{
+    circuit::{Circuit, Advice},
+    constraint_system::Variable,
+    gate::Gatebb,
+    utils::{field_precomp::FieldUtils, arith_helper::{modulus, from_biguint, to_biguint}},
+};
+
+use super::{
+    arith::read_const_gadget,
+    emulated::{LimbIdentity, alloc_limbs, range_check_limb, split},
+    rangecheck_common::{VarRange, lc_uint},
+};
+
+#[derive(Clone)]
+/// Little-endian limbs, not necessarily of limb_bits size.
+pub struct BigUintVar<F: PrimeField+FieldUtils> {
+    limbs: Vec<VarRange<F>>,
+}
+
+impl<F: PrimeField+FieldUtils> BigUintVar<F> {
+    pub fn limbs(&self) -> &[VarRange<F>] {
+        &self.limbs
+    }
+
+    pub fn vars(&self) -> Vec<Variable> {
+        self.limbs.iter().map(|l| l.var()).collect()
+    }
+
+    pub fn num_limbs(&self) -> usize {
+        self.limbs.len()
+    }
+}
+
+pub struct BigUintArith<F: PrimeField+FieldUtils> {
+    limb_bits: usize,
+    rc_base: u32,
+    _marker: PhantomData<F>,
+}
+
+impl<F: PrimeField+FieldUtils> BigUintArith<F> {
+    /// Limbs are range-checked in base rc_base, which must be a power of two dividing 2^limb_bits;
+    /// this requires circuit degree at least rc_base.
+    pub fn new(limb_bits: usize, rc_base: u32) -> Self {
+        assert!(rc_base >= 2 && rc_base.is_power_of_two(), "Range check base must be a power of two.");
+        assert!(limb_bits % (rc_base.trailing_zeros() as usize) == 0, "Limb size must be a multiple of range check digit size.");
+        assert!(BigUint::one() << limb_bits < modulus::<F>(), "Limbs do not fit in the native field.");
+        Self { limb_bits, rc_base, _marker: PhantomData }
+    }
+
+    pub fn limb_bits(&self) -> usize {
+        self.limb_bits
+    }
+
+    pub fn num_limbs_for(&self, x: &BigUint) -> usize {
+        ((x.bits() as usize + self.limb_bits - 1) / self.limb_bits).max(1)
+    }
+
+    /// Upper bound on the value of an integer.
+    pub fn max_value(&self, a: &BigUintVar<F>) -> BigUint {
+        a.limbs.iter().enumerate().fold(BigUint::zero(), |acc, (i, l)| acc + ((l.range() - 1u8) << (self.limb_bits * i)))
+    }
+
+    /// Integer value given the values of the limbs.
+    pub fn value(&self, limbs: &[F]) -> BigUint {
+        limbs.iter().enumerate().fold(BigUint::zero(), |acc, (i, l)| acc + (to_biguint(*l) << (self.limb_bits * i)))
+    }
+
+    pub fn to_limbs(&self, x: &BigUint, num_limbs: usize) -> Vec<F> {
+        split(x, self.limb_bits, num_limbs).iter().map(from_biguint).collect()
+    }
+
+    /// Range-checks variables to limb_bits size and constructs an integer from them.
+    pub fn range_check<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, vars: &[Variable], round: usize) -> BigUintVar<F> {
+        BigUintVar { limbs: vars.iter().map(|v| range_check_limb(circuit, *v, self.limb_bits, self.rc_base, round)).collect() }
+    }
+
+    pub fn constant<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, x: &BigUint, round: usize) -> BigUintVar<F> {
+        BigUintVar {
+            limbs: split(x, self.limb_bits, self.num_limbs_for(x)).iter().map(|l| {
+                VarRange::new_unchecked(read_const_gadget(circuit, from_biguint(l), round), l + 1u8)
+            }).collect()
+        }
+    }
+
+    fn alloc<'a>(
+        &self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        inputs: &[&BigUintVar<F>],
+        num_limbs: &[usize],
+        f: impl Fn(&[BigUint]) -> Vec<BigUint> + 'a,
+        round: usize,
+    ) -> Vec<BigUintVar<F>> {
+        let inputs = inputs.iter().map(|x| x.limbs()).collect_vec();
+        alloc_limbs(circuit, &inputs, num_limbs, self.limb_bits, self.rc_base, f, round).into_iter()
+            .map(|limbs| BigUintVar { limbs }).collect()
+    }
+
+    /// Limb-wise sum, only grows the ranges of the limbs.
+    pub fn add<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &BigUintVar<F>, b: &BigUintVar<F>, round: usize) -> BigUintVar<F> {
+        let n = a.limbs.len().max(b.limbs.len());
+        BigUintVar {
+            limbs: (0..n).map(|i| match (a.limbs.get(i), b.limbs.get(i)) {
+                (Some(x), Some(y)) => lc_uint(circuit, &[BigUint::one(), BigUint::one()], &[x.clone(), y.clone()], round),
+                (Some(x), None) | (None, Some(x)) => x.clone(),
+                (None, None) => unreachable!(),
+            }).collect()
+        }
+    }
+
+    /// Exact product, with limbs of limb_bits size.
+    pub fn mul<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &BigUintVar<F>, b: &BigUintVar<F>, round: usize) -> BigUintVar<F> {
+        let n = self.num_limbs_for(&(self.max_value(a) * self.max_value(b)));
+        let r = self.alloc(circuit, &[a, b], &[n], |v| vec![&v[0] * &v[1]], round).pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_prod(&a.limbs, &b.limbs, 1);
+        identity.add_lin(&r.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        r
+    }
+
+    /// Amount of limbs of the quotient of x by a modulus with m_limbs limbs, the highest of which is nonzero.
+    fn quotient_limbs(&self, x: &BigUint, m_limbs: usize) -> usize {
+        self.num_limbs_for(&(x >> (self.limb_bits * (m_limbs - 1))))
+    }
+
+    /// Computes a*b mod m, the quotient and the remainder are given by the prover. The result has as many limbs as m,
+    /// but is not necessarily smaller than m. The highest limb of m must be nonzero, otherwise the honest prover fails.
+    pub fn mul_mod<'a>(
+        &self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        a: &BigUintVar<F>,
+        b: &BigUintVar<F>,
+        m: &BigUintVar<F>,
+        round: usize,
+    ) -> BigUintVar<F> {
+        let nq = self.quotient_limbs(&(self.max_value(a) * self.max_value(b)), m.num_limbs());
+        let mut out = self.alloc(circuit, &[a, b, m], &[nq, m.num_limbs()], |v| {
+            let prod = &v[0] * &v[1];
+            vec![&prod / &v[2], &prod % &v[2]]
+        }, round);
+        let r = out.pop().unwrap();
+        let q = out.pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_prod(&a.limbs, &b.limbs, 1);
+        identity.add_prod(&q.limbs, &m.limbs, -1);
+        identity.add_lin(&r.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        r
+    }
+
+    /// Computes a mod m, see mul_mod.
+    pub fn reduce<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &BigUintVar<F>, m: &BigUintVar<F>, round: usize) -> BigUintVar<F> {
+        let nq = self.quotient_limbs(&self.max_value(a), m.num_limbs());
+        let mut out = self.alloc(circuit, &[a, m], &[nq, m.num_limbs()], |v| vec![&v[0] / &v[1], &v[0] % &v[1]], round);
+        let r = out.pop().unwrap();
+        let q = out.pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_lin(&a.limbs, 1);
+        identity.add_prod(&q.limbs, &m.limbs, -1);
+        identity.add_lin(&r.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        r
+    }
+
+    /// Constrains a = b as integers.
+    pub fn assert_equal<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &BigUintVar<F>, b: &BigUintVar<F>, round: usize) {
+        let mut identity = LimbIdentity::new();
+        identity.add_lin(&a.limbs, 1);
+        identity.add_lin(&b.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+    }
+
+    /// Constrains a < b, by checking that b - a - 1 is nonnegative.
+    pub fn assert_lt<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &BigUintVar<F>, b: &BigUintVar<F>, round: usize) {
+        let n = self.num_limbs_for(&self.max_value(b));
+        let d = self.alloc(circuit, &[a, b], &[n], |v| {
+            assert!(v[0] < v[1], "Comparison does not hold.");
+            vec![&v[1] - &v[0] - 1u8]
+        }, round).pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_lin(&a.limbs, 1);
+        identity.add_lin(&d.limbs, 1);
+        identity.add_const(&[BigUint::one()], 1);
+        identity.add_lin(&b.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+    }
+
+    /// Returns a boolean variable, which is 1 iff a < b. The difference d = bit (2b - 2a - 1) + a - b,
+    /// which is b - a - 1 for bit = 1 and a - b for bit = 0, is checked to be nonnegative.
+    pub fn lt<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &BigUintVar<F>, b: &BigUintVar<F>, round: usize) -> Variable {
+        let limb_bits = self.limb_bits;
+        let (na, nb) = (a.num_limbs(), b.num_limbs());
+        let bit = circuit.advice(round, Advice::new(na + nb, 1, move |args, _| {
+            let value = |limbs: &[F]| limbs.iter().enumerate()
+                .fold(BigUint::zero(), |acc, (i, l)| acc + (to_biguint(*l) << (limb_bits * i)));
+            vec![if value(&args[..na]) < value(&args[na..]) {F::ONE} else {F::ZERO}]
+        }), a.vars().into_iter().chain(b.vars()).collect())[0];
+        let bit = VarRange::new_no_lookup(circuit, bit, 2);
+
+        let n = self.num_limbs_for(&self.max_value(a).max(self.max_value(b)));
+        let d = self.alloc(circuit, &[a, b], &[n], |v| {
+            vec![if v[0] < v[1] {&v[1] - &v[0] - 1u8} else {&v[0] - &v[1]}]
+        }, round).pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        for (i, x) in b.limbs.iter().enumerate() {
+            identity.push(i, BigInt::from(2), &[&bit, x]);
+        }
+        for (i, x) in a.limbs.iter().enumerate() {
+            identity.push(i, BigInt::from(-2), &[&bit, x]);
+        }
+        identity.push(0, BigInt::from(-1), &[&bit]);
+        identity.add_lin(&a.limbs, 1);
+        identity.add_lin(&b.limbs, -1);
+        identity.add_lin(&d.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        bit.var()
+    }
+
+    /// Computes base^exp mod m for a public exponent exp > 0. The result is canonical, i.e. smaller than m.
+    pub fn modexp<'a>(
+        &self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        base: &BigUintVar<F>,
+        exp: &BigUint,
+        m: &BigUintVar<F>,
+        round: usize,
+    ) -> BigUintVar<F> {
+        assert!(!exp.is_zero(), "Exponent must be positive.");
+        let bits = exp.bits();
+        let mut acc = self.reduce(circuit, base, m, round);
+        let base = acc.clone();
+        for i in (0..bits - 1).rev() {
+            acc = self.mul_mod(circuit, &acc, &acc, m, round);
+            if exp.bit(i) {
+                acc = self.mul_mod(circuit, &acc, &base, m, round);
+            }
+        }
+        self.assert_lt(circuit, &acc, m, round);
+        acc
+    }
+}
+
+/// DER encoding of the SHA-256 AlgorithmIdentifier, which precedes the digest in PKCS#1 v1.5 signatures.
+pub const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+];
+
+/// PKCS#1 v1.5 encoding 00 01 FF .. FF 00 DigestInfo H of a SHA-256 digest, for a modulus of modulus_bytes bytes.
+pub fn pkcs1v15_sha256_encode(digest: &[u8; 32], modulus_bytes: usize) -> BigUint {
+    let padding = modulus_bytes - 3 - SHA256_DIGEST_INFO.len() - 32;
+    assert!(padding >= 8, "Modulus is too short.");
+    let mut em = vec![0x00, 0x01];
+    em.extend(std::iter::repeat(0xff).take(padding));
+    em.push(0x00);
+    em.extend(SHA256_DIGEST_INFO);
+    em.extend(digest);
+    BigUint::from_bytes_be(&em)
+}
+
+/// Verifies a PKCS#1 v1.5 signature sig of a SHA-256 digest under the public key (n, exp), where n has
+/// modulus_bits bits. The digest is given as an integer in big-endian byte order, limbs of which must cover
+/// exactly 256 bits, so limb_bits must divide 256. For RSA-2048 with 64-bit limbs this takes ~31k private witnesses.
+pub fn rsa_verify_pkcs1v15_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    arith: &BigUintArith<F>,
+    n: &BigUintVar<F>,
+    exp: &BigUint,
+    sig: &BigUintVar<F>,
+    digest: &BigUintVar<F>,
+    modulus_bits: usize,
+    round: usize,
+) {
+    let limb_bits = arith.limb_bits();
+    assert!(256 % limb_bits == 0 && digest.num_limbs() * limb_bits == 256, "Digest limbs must cover 256 bits.");
+    assert!(modulus_bits % 8 == 0 && n.num_limbs() == arith.num_limbs_for(&(BigUint::one() << (modulus_bits - 1))));
+
+    arith.assert_lt(circuit, sig, n, round);
+    let em = arith.modexp(circuit, sig, exp, n, round);
+
+    let prefix = pkcs1v15_sha256_encode(&[0; 32], modulus_bits / 8);
+    let mut identity = LimbIdentity::new();
+    identity.add_lin(em.limbs(), 1);
+    identity.add_lin(digest.limbs(), -1);
+    identity.add_const(&split(&prefix, limb_bits, arith.num_limbs_for(&prefix)), -1);
+    identity.constrain(circuit, limb_bits, arith.rc_base, round);
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use num_bigint::RandBigInt;
+    use rand_core::OsRng;
+    use sha2::{Sha256, Digest};
+
+    use crate::gadgets::input::input;
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    /// Test RSA-2048 key.
+    const N: &str = concat!(
+        "dca1c2162a18abf69eb3f5e70a00249b75dbcdb1f584ee112f6b2fe6fcfb9944ce6493635842a98ffb9a9ab3ea1d79e22f0db5b1f59ca34d85ed33e4fce887fa",
+        "8972c33d22ac07374b12cdc01db0069b4c3a8ced2bad63a79b8a80df01a6cfa9120ccef348e792c356159ce343472d3d8a4a21147047a69cdb87cd142bf94d42",
+        "437837e96522b84866de26d251b1eba58e9695625ef575da01cbbb8c2ead42fb8f318cc5d2f35446fd44bcc7a4a4ddf44575f2e13f40bbd424ac2c19661da158",
+        "26a236d25de2257372d8c825097b6ca8500392a46c112f7fa08c5814993472b2745755b44d515ddf9e1dc10810f9c76119518fae2741f0e2ed4d9a05d6a9ee9f",
+    );
+    const D: &str = concat!(
+        "90481f4732e59f79fa9efe0a1b9d736f88763835dcedbea1d583593f3d3a4f4559938a59d5461f53fa2f9df2c8564acbf3ccfd2b664099e355d7f65a869454df",
+        "66c17a7884da4044e970b5374ca4931104b7c8f67ebfe23c850a3641d8daa750aa352616668d02c3478cd5643ebbdfdada574fde3eb600239cc59302e0ad250f",
+        "ca50c0f5696b6bf49d9ed3e483cb89b7ea1dbea71888ab16792f947a6d471d851213d1e935e7546d70686df70bb9ffa1b8c0def595eaac94a205a7ae6fedde29",
+        "9eae4a1b32329b8629fdd537fedae17d930eecbc36be5f35b294f4e2e437825a7cfdd62556d8dac964d4d49c5c2d0e704de1651807d4ac4d2d8b359b1fc52d9",
+    );
+
+    fn hex(s: &str) -> BigUint {
+        BigUint::parse_bytes(s.as_bytes(), 16).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let arith = BigUintArith::<F>::new(64, 16);
+        let mut circuit = Circuit::new(16, 1);
+        let ext = circuit.ext_val(12);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let a = arith.range_check(&mut circuit, &vars[0..5], 0);
+        let b = arith.range_check(&mut circuit, &vars[5..9], 0);
+        let m = arith.range_check(&mut circuit, &vars[9..12], 0);
+
+        let sum = arith.add(&mut circuit, &a, &b, 0);
+        let prod = arith.mul(&mut circuit, &sum, &b, 0);
+        let prod_mod = arith.mul_mod(&mut circuit, &a, &b, &m, 0);
+        let reduced = arith.reduce(&mut circuit, &prod, &m, 0);
+        let lt = [arith.lt(&mut circuit, &a, &b, 0), arith.lt(&mut circuit, &b, &a, 0), arith.lt(&mut circuit, &b, &b, 0)];
+        arith.assert_lt(&mut circuit, &b, &a, 0);
+        let five = arith.constant(&mut circuit, &BigUint::from(5u8), 0);
+        let pow = arith.modexp(&mut circuit, &a, &BigUint::from(11u8), &m, 0);
+        let pow_b = arith.mul(&mut circuit, &pow, &five, 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let x = OsRng.gen_biguint(320);
+        let y = OsRng.gen_biguint(256);
+        let p = OsRng.gen_biguint(192) | (BigUint::one() << 191);
+        for (e, v) in ext.iter().zip_eq(arith.to_limbs(&x, 5).into_iter().chain(arith.to_limbs(&y, 4)).chain(arith.to_limbs(&p, 3))) {
+            instance.set_ext(*e, v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        let value = |v: &BigUintVar<F>| arith.value(&instance.cs.get_vars(&v.vars()));
+        assert_eq!(value(&prod), (&x + &y) * &y);
+        assert_eq!(value(&prod_mod) % &p, &x * &y % &p);
+        assert_eq!(value(&reduced) % &p, (&x + &y) * &y % &p);
+        assert_eq!(instance.cs.get_vars(&lt), vec![F::ZERO, F::ONE, F::ZERO]);
+        assert_eq!(value(&pow), x.modpow(&BigUint::from(11u8), &p));
+        assert_eq!(value(&pow_b), x.modpow(&BigUint::from(11u8), &p) * 5u8);
+    }
+
+    /// Verifies a signature of SHA-256 of msg, produced with the test key for message signed_msg,
+    /// and returns the private witness size.
+    fn check_rsa(msg: &[u8], signed_msg: &[u8]) -> usize {
+        let (n, d) = (hex(N), hex(D));
+        let e = BigUint::from(65537u32);
+        let arith = BigUintArith::<F>::new(64, 16);
+
+        let mut circuit = Circuit::new(16, 1);
+        let ext = circuit.ext_val(68);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let n_var = arith.range_check(&mut circuit, &vars[0..32], 0);
+        let sig_var = arith.range_check(&mut circuit, &vars[32..64], 0);
+        let digest_var = arith.range_check(&mut circuit, &vars[64..68], 0);
+        rsa_verify_pkcs1v15_gadget(&mut circuit, &arith, &n_var, &e, &sig_var, &digest_var, 2048, 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let sig = pkcs1v15_sha256_encode(&Sha256::digest(signed_msg).into(), 256).modpow(&d, &n);
+        let digest = BigUint::from_bytes_be(&Sha256::digest(msg));
+        let values = arith.to_limbs(&n, 32).into_iter().chain(arith.to_limbs(&sig, 32)).chain(arith.to_limbs(&digest, 4));
+        for (e, v) in ext.iter().zip_eq(values) {
+            instance.set_ext(*e, v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+        let size = instance.cs.wtns[0].privs.len();
+        instance.finish();
+        size
+    }
+
+    #[test]
+    fn rsa_2048() {
+        assert_eq!(check_rsa(b"protostar", b"protostar"), 31174);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rsa_2048_wrong_message() {
+        check_rsa(b"protostar", b"protostars");
+    }
+}
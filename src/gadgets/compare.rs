@@ -0,0 +1,229 @@
+// Boolean-valued comparisons and conditional selection.
+
+use std::rc::Rc;
+
+use ff::PrimeField;
+use num_bigint::BigUint;
+use num_traits::One;
+
+use crate::{circuit::{Circuit, Advice, PolyOp}, constraint_system::Variable, gate::Gatebb, utils::{field_precomp::FieldUtils, arith_helper::{modulus, from_biguint}}};
+
+use super::{rangecheck_common::VarRange, rangecheck_small::limb_decompose_no_lookup_gadget, rangecheck_lookup::{RangeLookup, limb_decompose_with_lookup_gadget}};
+
+/// Returns a boolean variable which is 1 iff a - b = 0. Takes a single variable if b is None.
+fn is_zero_diff<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    a: Variable,
+    b: Option<Variable>,
+    round: usize,
+) -> Variable {
+    let mut args = vec![a];
+    args.extend(b);
+    let l = args.len();
+    let diff = move |args: &[F]| if l == 2 {args[0] - args[1]} else {args[0]};
+
+    let inv = circuit.advice(round, Advice::new(l, 1, move |args: &[F], _| {
+        vec![diff(args).invert().unwrap_or(F::ZERO)]
+    }), args.clone())[0];
+
+    let mut tmp = args.clone();
+    tmp.push(inv);
+    let ret = circuit.apply(round, PolyOp::new(2, l+1, 1, move |args, _| {
+        vec![F::ONE - diff(args) * args[l]]
+    }), tmp)[0];
+
+    // (a - b) * ret = 0, together with ret = 1 - (a - b) * inv this forces ret to be the correct boolean
+    args.push(ret);
+    circuit.constrain(&args, Gatebb::new(2, l+1, 1, Rc::new(move |args, _| {
+        vec![diff(args) * args[l]]
+    }), vec![]));
+
+    ret
+}
+
+/// Returns a boolean variable which is 1 iff x = 0.
+pub fn is_zero<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    x: Variable,
+    round: usize,
+) -> Variable {
+    is_zero_diff(circuit, x, None, round)
+}
+
+/// Returns a boolean variable which is 1 iff a = b.
+pub fn is_equal<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    a: Variable,
+    b: Variable,
+    round: usize,
+) -> Variable {
+    is_zero_diff(circuit, a, Some(b), round)
+}
+
+/// Returns cond ? a : b. Does not check that cond is boolean.
+pub fn select<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cond: Variable,
+    a: Variable,
+    b: Variable,
+    round: usize,
+) -> Variable {
+    circuit.apply(round, PolyOp::new(2, 3, 1, |args, _| {
+        vec![args[0] * (args[1] - args[2]) + args[2]]
+    }), vec![cond, a, b])[0]
+}
+
+/// Amount of limbs k such that base^k bounds both values, and the shift base^k.
+pub(crate) fn comparison_domain<F: PrimeField+FieldUtils>(a: &VarRange<F>, b: &VarRange<F>, base: u32) -> (usize, BigUint) {
+    let range = a.range().max(b.range());
+    let mut shift = BigUint::one();
+    let mut k = 0;
+    while shift < range {
+        shift *= base;
+        k += 1;
+    }
+    assert!(&shift * base < modulus::<F>(), "Values are too large to be compared.");
+    (k, shift)
+}
+
+/// Computes a - b + base^k, and returns the variable and the amount of limbs to decompose it in.
+/// Since a, b < base^k, the result lies in [0, 2 base^k), and its top limb is 1 iff a >= b.
+fn shifted_diff<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    a: &VarRange<F>,
+    b: &VarRange<F>,
+    base: u32,
+    round: usize,
+) -> (Variable, usize) {
+    let (k, shift) = comparison_domain(a, b, base);
+    let shift = from_biguint::<F>(&shift);
+    let diff = circuit.apply(round, PolyOp::new(1, 2, 1, move |args, _| {
+        vec![args[0] - args[1] + shift]
+    }), vec![a.var(), b.var()])[0];
+    (diff, k+1)
+}
+
+fn negate<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, bit: Variable, round: usize) -> Variable {
+    circuit.apply(round, PolyOp::new(1, 1, 1, |args, _| vec![F::ONE - args[0]]), vec![bit])[0]
+}
+
+/// Returns a boolean variable which is 1 iff a < b. Limbs are range-checked in a given (small) base.
+pub fn less_than<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    a: &VarRange<F>,
+    b: &VarRange<F>,
+    base: u32,
+    round: usize,
+) -> Variable {
+    let (diff, num_limbs) = shifted_diff(circuit, a, b, base, round);
+    let limbs = limb_decompose_no_lookup_gadget(circuit, base, round, num_limbs, diff);
+    negate(circuit, limbs[num_limbs-1].var(), round)
+}
+
+/// Returns a boolean variable which is 1 iff a < b. Limbs are range-checked using a lookup.
+pub fn less_than_with_lookup<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    a: &VarRange<F>,
+    b: &VarRange<F>,
+    checker: &mut RangeLookup<F>,
+    round: usize,
+) -> Variable {
+    let (diff, num_limbs) = shifted_diff(circuit, a, b, checker.range() as u32, round);
+    let limbs = limb_decompose_with_lookup_gadget(circuit, round, num_limbs, checker, diff);
+    negate(circuit, limbs[num_limbs-1].var(), round)
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use itertools::Itertools;
+    use num_bigint::BigUint;
+    use rand_core::OsRng;
+
+    use crate::{circuit::Circuit, gadgets::{input::input, lookup::Lookup, rangecheck_common::VarRange, rangecheck_lookup::RangeLookup}};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    #[test]
+    fn zero_and_equality() {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(2);
+        let a = input(&mut circuit, ext[0], 0);
+        let b = input(&mut circuit, ext[1], 0);
+        let a_zero = is_zero(&mut circuit, a, 0);
+        let eq = is_equal(&mut circuit, a, b, 0);
+        let sel = select(&mut circuit, eq, a, b, 0);
+
+        let constructed = circuit.finalize();
+        let x = F::random(OsRng);
+        let y = F::random(OsRng);
+        for (va, vb) in [(F::ZERO, F::ZERO), (F::ZERO, y), (x, x), (x, y)] {
+            let mut instance = constructed.spawn();
+            instance.set_ext(ext[0], va);
+            instance.set_ext(ext[1], vb);
+            instance.execute(0);
+            instance.valid_witness();
+
+            assert_eq!(instance.cs.getvar(a_zero), if va == F::ZERO {F::ONE} else {F::ZERO});
+            assert_eq!(instance.cs.getvar(eq), if va == vb {F::ONE} else {F::ZERO});
+            assert_eq!(instance.cs.getvar(sel), if va == vb {va} else {vb});
+            instance.finish();
+        }
+    }
+
+    #[test]
+    fn less_than_no_lookup() {
+        let mut circuit = Circuit::new(4, 1);
+        let ext = circuit.ext_val(2);
+        let a = input(&mut circuit, ext[0], 0);
+        let b = input(&mut circuit, ext[1], 0);
+        let a = VarRange::new_unchecked(a, BigUint::from(20u8));
+        let b = VarRange::new_unchecked(b, BigUint::from(11u8));
+        let lt = less_than(&mut circuit, &a, &b, 4, 0);
+        let gt = less_than(&mut circuit, &b, &a, 4, 0);
+
+        let constructed = circuit.finalize();
+        for (x, y) in (0..20u64).cartesian_product(0..11u64) {
+            let mut instance = constructed.spawn();
+            instance.set_ext(ext[0], F::from(x));
+            instance.set_ext(ext[1], F::from(y));
+            instance.execute(0);
+            instance.valid_witness();
+
+            assert_eq!(instance.cs.getvar(lt), F::from((x < y) as u64));
+            assert_eq!(instance.cs.getvar(gt), F::from((y < x) as u64));
+            instance.finish();
+        }
+    }
+
+    #[test]
+    fn less_than_lookup() {
+        let mut circuit = Circuit::new(2, 2);
+        let challenge = circuit.ext_val(1)[0];
+        let ext = circuit.ext_val(2);
+        let a = input(&mut circuit, ext[0], 0);
+        let b = input(&mut circuit, ext[1], 0);
+        let a = VarRange::new_unchecked(a, BigUint::from(1u64 << 20));
+        let b = VarRange::new_unchecked(b, BigUint::from(1u64 << 20));
+        let mut checker = RangeLookup::new(challenge, 256);
+        let lt = less_than_with_lookup(&mut circuit, &a, &b, &mut checker, 0);
+        checker.finalize(&mut circuit, 0, 0, 1, 2);
+
+        let constructed = circuit.finalize();
+        for (x, y) in [(0, 0), (5, 1 << 19), (1 << 19, 5), ((1 << 20) - 1, (1 << 20) - 1), (12345, 12346)] {
+            let mut instance = constructed.spawn();
+            instance.set_ext(ext[0], F::from(x));
+            instance.set_ext(ext[1], F::from(y));
+            instance.set_ext(challenge, F::random(OsRng));
+            instance.execute(0);
+            instance.execute(1);
+            instance.valid_witness();
+
+            assert_eq!(instance.cs.getvar(lt), F::from((x < y) as u64));
+            instance.finish();
+        }
+    }
+}
@@ -4,14 +4,17 @@
 // Compute all multiplicities of A for every bitstring from 0 to 2^k - 1, shifted by 2^k Z
 // Then, sequentially multiply accumulator by 2^k, and add the multiplicity, conditionally chosen from the chunk.
 
-use std::{rc::Rc, marker::PhantomData};
+use std::{cmp::max, rc::Rc, marker::PhantomData};
 
 use ff::{PrimeField, BatchInvert};
 use halo2::halo2curves::CurveExt;
+use num_bigint::BigUint;
+use num_traits::One;
 use crate::circuit::{PolyOp, Advice};
-use crate::{circuit::Circuit, constraint_system::Variable, gate::Gatebb};
+use crate::{circuit::Circuit, constraint_system::{Variable, CS}, gate::Gatebb};
 use crate::utils::field_precomp::FieldUtils;
 
+use super::input::input;
 use super::nonzero_check::Nonzeros;
 use super::rangecheck_small::{choice_gadget, limb_decompose_no_lookup_gadget};
 
@@ -142,7 +145,7 @@ pub fn ectangent_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
     circuit.constrain( // Check that slope vector is collinear with vector from pt1 to [-pt2]
         &pts,
         Gatebb::new(
-            2,
+            3,
             4,
             1,
             Rc::new(move |args, _ |{
@@ -160,7 +163,7 @@ pub fn ectangent_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
             round,
             PolyOp::new(
                 1,
-                3,
+                2,
                 1,
                 move |args, _| {
                     vec![args[0]-args[1]]
@@ -212,7 +215,7 @@ pub fn ecdouble_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
     let tmp = circuit.advice(
         round,
         Advice::new(
-            4,
+            2,
             2,
             move |args, _| {
                 let (x,y,z) = double_proj::<F,C>((args[0], args[1]));
@@ -228,176 +231,266 @@ pub fn ecdouble_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
     pt2
 }
 
-/// A gadget that multiples a point by a given scalar
-/// Prover also must provide a pair of points (a, b) satisfying b + (1+9+9^2+...9^{num_limbs-1}) a = 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A single step of multiplication of the accumulator by the base of the window.
+pub enum MulStep {
+    /// Doubling, checked by a tangent line. Costs a point and a nonzero check.
+    Double,
+    /// Tripling, checked by a single polynomial of degree 8. Costs a point, a scale factor and a nonzero check.
+    Triple,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Window of a variable base scalar multiplication: the scalar is split into limbs of base equal to the
+/// product of the steps, and the accumulator is multiplied by the base by performing the steps in order.
+pub struct Window {
+    steps: Vec<MulStep>,
+}
+
+impl Window {
+    pub fn new(steps: Vec<MulStep>) -> Self {
+        assert!(steps.len() > 0, "Window must contain at least one step.");
+        Self { steps }
+    }
+
+    /// Splits the base into doublings and triplings. Returns None if the base has other prime factors,
+    /// or is divisible by 3 while triplings are not allowed.
+    pub fn from_base(base: u32, allow_triple: bool) -> Option<Self> {
+        if base < 2 {
+            return None
+        }
+        let mut steps = vec![];
+        let mut rest = base;
+        while rest % 2 == 0 {
+            steps.push(MulStep::Double);
+            rest /= 2;
+        }
+        while allow_triple && rest % 3 == 0 {
+            steps.push(MulStep::Triple);
+            rest /= 3;
+        }
+        if rest != 1 {
+            return None
+        }
+        Some(Self::new(steps))
+    }
+
+    pub fn steps(&self) -> &[MulStep] {
+        &self.steps
+    }
+
+    pub fn base(&self) -> u32 {
+        self.steps.iter().map(|s| match s {MulStep::Double => 2, MulStep::Triple => 3}).product()
+    }
+
+    /// Minimal degree of the circuit: range check and choice of the limb have degree equal to the base,
+    /// on-curve checks have degree 3 and the tripling check has degree 8.
+    pub fn min_degree(&self) -> usize {
+        let steps = if self.steps.contains(&MulStep::Triple) {8} else {3};
+        max(self.base() as usize, steps)
+    }
+}
+
+/// (1 + base + base^2 + ... + base^{num_limbs-1}), the multiple of the offset point accumulated by the
+/// scalar multiplication gadget.
+pub fn offset_scalar<S: PrimeField>(base: u32, num_limbs: usize) -> S {
+    (S::from(base as u64).pow([num_limbs as u64]) - S::ONE) * S::from(base as u64 - 1).invert().unwrap()
+}
+
+/// Returns b such that b + (1 + base + ... + base^{num_limbs-1}) a = 0.
+pub fn offset_point<C: CurveExt>(a: C, base: u32, num_limbs: usize) -> C {
+    -(a * offset_scalar::<C::ScalarExt>(base, num_limbs))
+}
+
+/// A gadget that multiples a point by a given scalar, processing a limb of the scalar per window.
+/// Prover also must provide a pair of points (a, b) satisfying b + (1+B+B^2+...B^{num_limbs-1}) a = 0,
+/// where B is the base of the window, see offset_point.
 /// The proof is sound if this equation holds, and complete if a is chosen at random.
 /// In practice these points typically should be given as public inputs, and constrained by the decider.
-/// base9 - version
-pub fn escalarmul_gadget_9<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
+pub fn escalarmul_gadget<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
     circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
     sc: Variable,
     pt: EcAffinePoint<F,C>,
+    window: &Window,
     num_limbs: usize,
     round: usize,
     a: EcAffinePoint<F,C>,
     b: EcAffinePoint<F,C>,
     nonzeros: &mut Nonzeros,
 ) -> EcAffinePoint<F, C> {
-    // The algorithm:
-    // We compute a, pt+a, 2pt+a, ..., 8pt+a
-    // Then, we start from the last limb, fetch precomputed point using lagrange polynomial
-    // Multiply by 9 (using two triplings, which are themselves polynomials of degree 8)
-    // Go to the next limb, and so on. Accumulated error becomes a*(1+9+9^2+...9^{num_limbs-1}), which is assumed to be -b.
-    // Tripling is checked by verifying 2X = Y-X inside of a polynomial.
+    ecmsm_gadget(circuit, &[sc], &[pt], window, num_limbs, round, &[a], b, nonzeros)
+}
 
-    // Compute limbs:
+#[derive(Clone, Copy, Debug)]
+/// Operation on the accumulator of the multi-scalar multiplication.
+enum MsmOp {
+    Step(MulStep),
+    /// Addition of the chosen point of a term for a limb.
+    Add(usize, usize),
+}
 
-    let limbs = limb_decompose_no_lookup_gadget(circuit, 9, round, num_limbs, sc);
+/// Computes a, pt+a, 2pt+a, ..., (base-1)pt+a. Tables can be shared by several multiplications of the same point
+/// with the same offset, see ecmsm_tables_gadget.
+pub fn ec_table_gadget<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    pt: EcAffinePoint<F,C>,
+    a: EcAffinePoint<F,C>,
+    base: u32,
+    round: usize,
+    nonzeros: &mut Nonzeros,
+) -> Vec<EcAffinePoint<F,C>> {
     let mut precomputed_pts = vec![a];
     let mut curr = a;
-    for _ in 1..9 {
+    for _ in 1..base {
         curr = ecadd_gadget(circuit, curr, pt, nonzeros, round);
         precomputed_pts.push(curr);
     }
+    precomputed_pts
+}
 
-    // Compute lookups:
-
-    let precomputed_pts_prep : Vec<_> = precomputed_pts.iter().map(|pt|vec![pt.x, pt.y]).collect();
-    let precomputed_pts_prep : Vec<_> = precomputed_pts_prep.iter().map(|x|x.as_ref()).collect();
-
-    let mut pts_limbs = vec![];
-    for i in 0..num_limbs {
-        pts_limbs.append(&mut choice_gadget(circuit, &precomputed_pts_prep, limbs[i].clone(), round));
-    }
+/// Multi-scalar multiplication gadget, computing sum of sc_i pt_i. All terms share the accumulator, so
+/// the steps multiplying it by the base are performed once per limb, and every term only costs its precomputed
+/// table, the choice of its limbs and one addition per limb.
+/// Every term has its own offset point a_i, and b must satisfy b + (1+B+B^2+...B^{num_limbs-1}) (a_1 + ... + a_m) = 0.
+/// The proof is sound if this equation holds, and complete if the offset points are chosen at random.
+pub fn ecmsm_gadget<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    scalars: &[Variable],
+    pts: &[EcAffinePoint<F,C>],
+    window: &Window,
+    num_limbs: usize,
+    round: usize,
+    a: &[EcAffinePoint<F,C>],
+    b: EcAffinePoint<F,C>,
+    nonzeros: &mut Nonzeros,
+) -> EcAffinePoint<F, C> {
+    assert!(pts.len() == scalars.len() && a.len() == scalars.len());
+    let tables : Vec<_> = pts.iter().zip(a.iter())
+        .map(|(pt, a)| ec_table_gadget(circuit, *pt, *a, window.base(), round, nonzeros))
+        .collect();
+    ecmsm_tables_gadget(circuit, scalars, &tables, window, num_limbs, round, b, nonzeros)
+}
 
-    // Compute advices:
+/// Multi-scalar multiplication with precomputed tables of the terms, see ec_table_gadget. The first entry of
+/// every table is its offset point, so b must satisfy the same equation as in ecmsm_gadget.
+pub fn ecmsm_tables_gadget<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    scalars: &[Variable],
+    tables: &[Vec<EcAffinePoint<F,C>>],
+    window: &Window,
+    num_limbs: usize,
+    round: usize,
+    b: EcAffinePoint<F,C>,
+    nonzeros: &mut Nonzeros,
+) -> EcAffinePoint<F, C> {
+    // The algorithm:
+    // For every term we have a, pt+a, 2pt+a, ..., (B-1)pt+a
+    // Then, we start from the last limb, fetch precomputed points using lagrange polynomial, and add them all
+    // Multiply by B (performing the steps of the window)
+    // Go to the next limb, and so on. Accumulated error becomes (a_1+...+a_m)*(1+B+B^2+...B^{num_limbs-1}), which is assumed to be -b.
+    // Tripling is checked by verifying 2X = Y-X inside of a polynomial.
 
-    let adv = Advice::new(
-        2*num_limbs,
-        8*(num_limbs-1),
-        move |args, _| {
-            let mut pts = vec![];
-            for i in 0..num_limbs {
-                pts.push(C::new_jacobian(args[2*i], args[2*i+1], F::ONE).unwrap());
-            }
-            let mut x3 = vec![];
-            let mut x9 = vec![];
-            let mut acc = vec![];
-            for i in 0..num_limbs {
-                if i == 0 {
-                    acc.push(pts[num_limbs-i-1])
-                } else {
-                    acc.push(pts[num_limbs-i-1] + x9[i-1])    
-                }
+    let num_terms = scalars.len();
+    assert!(num_terms > 0 && num_limbs > 0);
+    assert!(tables.len() == num_terms);
+    assert!(circuit.max_degree() >= window.min_degree(), "Window of base {} requires degree at least {}.", window.base(), window.min_degree());
+    let base = window.base();
 
-                if i<num_limbs-1 {
-                    x3.push(acc[i].double() + acc[i]);
-                    x9.push(x3[i].double() + x3[i]);
-                }
-            }
+    // Compute limbs and lookups:
 
-            let mut zinv : Vec<_> = acc.iter().chain(x3.iter()).chain(x9.iter()).map(|pt|pt.jacobian_coordinates().2).collect();
-            
-            zinv.batch_invert();
+    let mut pts_limbs = vec![]; // pts_limbs[j][i] is the chosen point of term j for limb i
+    for j in 0..num_terms {
+        assert!(tables[j].len() == base as usize, "Table size does not match the base of the window.");
+        let limbs = limb_decompose_no_lookup_gadget(circuit, base, round, num_limbs, scalars[j]);
 
-            let mut acc_aff = vec![];
-            let mut x3_aff = vec![];
-            let mut x9_aff = vec![];
+        let precomputed_pts_prep : Vec<_> = tables[j].iter().map(|pt|vec![pt.x, pt.y]).collect();
+        let precomputed_pts_prep : Vec<_> = precomputed_pts_prep.iter().map(|x|x.as_ref()).collect();
 
-            for i in 0..num_limbs-1 {
-                let zacc = zinv[i];
-                let zx3 = zinv[i+num_limbs];
-                let zx9 = zinv[i+2*num_limbs-1];
+        pts_limbs.push(limbs.into_iter().map(|limb| {
+            let chosen = choice_gadget(circuit, &precomputed_pts_prep, limb, round);
+            EcAffinePoint::<F,C>::new_unchecked(chosen[0], chosen[1])
+        }).collect::<Vec<_>>());
+    }
 
-                let zacc_sq = zacc.square();
-                let zx3_sq = zx3.square();
-                let zx9_sq = zx9.square();
+    // Accumulator starts from the chosen point of the first term for the last limb.
 
-                let zacc_cb = zacc*zacc_sq;
-                let zx3_cb = zx3*zx3_sq;
-                let zx9_cb = zx9*zx9_sq;
+    let mut ops = vec![];
+    for j in 1..num_terms {
+        ops.push(MsmOp::Add(j, num_limbs-1));
+    }
+    for i in (0..num_limbs-1).rev() {
+        ops.extend(window.steps().iter().map(|s| MsmOp::Step(*s)));
+        ops.extend((0..num_terms).map(|j| MsmOp::Add(j, i)));
+    }
 
-                acc_aff.push((acc[i].jacobian_coordinates().0 * zacc_sq, acc[i].jacobian_coordinates().1 * zacc_cb));
-                x3_aff.push((x3[i].jacobian_coordinates().0 * zx3_sq, x3[i].jacobian_coordinates().1 * zx3_cb));
-                x9_aff.push((x9[i].jacobian_coordinates().0 * zx9_sq, x9[i].jacobian_coordinates().1 * zx9_cb));
-            }
+    // Compute advices:
 
-            let zacc_last = zinv[num_limbs-1];
-            let zacc_last_sq = zacc_last.square();
-            let zacc_last_cb = zacc_last*zacc_last_sq;
-            acc_aff.push(
-                (
-                    acc[num_limbs-1].jacobian_coordinates().0 * zacc_last_sq,
-                    acc[num_limbs-1].jacobian_coordinates().1 * zacc_last_cb
-                )
-            );
-
-            let mut scale_factors = vec![]; // Total length 2*(num_limbs-1), for all mul by 3 transitions
-            for i in 0..num_limbs-1 {
-                scale_factors.push(acc_aff[i].1.scale(2).cube()); // 3rd coordinate of the projective doubling
+    let num_triples = ops.iter().filter(|op| matches!(op, MsmOp::Step(MulStep::Triple))).count();
+    let adv_ops = ops.clone();
+    let adv = Advice::new(
+        2*num_terms*num_limbs,
+        2*ops.len() + num_triples,
+        move |args, _| {
+            let chosen = |j: usize, i: usize| (args[2*(j*num_limbs + i)], args[2*(j*num_limbs + i) + 1]);
+            let start = chosen(0, num_limbs-1);
+
+            let mut jac = vec![];
+            let mut curr = C::new_jacobian(start.0, start.1, F::ONE).unwrap();
+            for op in &adv_ops {
+                curr = match op {
+                    MsmOp::Step(MulStep::Double) => curr.double(),
+                    MsmOp::Step(MulStep::Triple) => curr.double() + curr,
+                    MsmOp::Add(j, i) => {
+                        let pt = chosen(*j, *i);
+                        curr + C::new_jacobian(pt.0, pt.1, F::ONE).unwrap()
+                    }
+                };
+                jac.push(curr);
             }
 
-            for i in 0..num_limbs-1 {
-                scale_factors.push(x3_aff[i].1.scale(2).cube());
+            let mut zinv : Vec<_> = jac.iter().map(|pt|pt.jacobian_coordinates().2).collect();
+            zinv.batch_invert();
+            let aff : Vec<_> = jac.iter().zip(zinv.iter()).map(|(pt, z)| {
+                let (x, y, _) = pt.jacobian_coordinates();
+                let zsq = z.square();
+                (x*zsq, y*zsq*z)
+            }).collect();
+
+            // Scale factors compare projective 2A and B-A for all tripling transitions A -> B.
+            let mut scale_factors = vec![];
+            let mut transitions = vec![];
+            for (k, op) in adv_ops.iter().enumerate() {
+                if let MsmOp::Step(MulStep::Triple) = op {
+                    let prev = if k == 0 {start} else {aff[k-1]};
+                    scale_factors.push(prev.1.scale(2).cube()); // 3rd coordinate of the projective doubling
+                    transitions.push((prev, aff[k]));
+                }
             }
 
             scale_factors.batch_invert();
-
-
-            for i in 0..num_limbs-1 {
-                scale_factors[i] *= (acc_aff[i].0 - x3_aff[i].0).cube(); // 3rd coordinate of the projective addition/subtraction
-                scale_factors[num_limbs-1+i] *= (x3_aff[i].0 - x9_aff[i].0).cube();
+            for (q, (prev, next)) in scale_factors.iter_mut().zip(transitions.iter()) {
+                *q *= (prev.0 - next.0).cube(); // 3rd coordinate of the projective addition/subtraction
             }
 
-
-            // scale_factors now contain data we need to compare projective 2A and B-A
-
             let mut ret = vec![];
-
-            for i in 1..num_limbs{ // Skip the first accumulator as we don't need it.
-                ret.push(acc_aff[i].0);
-                ret.push(acc_aff[i].1);
-            }
-
-            for i in 0..num_limbs-1{
-                ret.push(x3_aff[i].0);
-                ret.push(x3_aff[i].1);
-            }
-
-            for i in 0..num_limbs-1{
-                ret.push(x9_aff[i].0);
-                ret.push(x9_aff[i].1);
-            }
-
-            for i in 0..2*(num_limbs-1){
-                ret.push(scale_factors[i]);
+            let mut scale_factors = scale_factors.into_iter();
+            for (op, pt) in adv_ops.iter().zip(aff.iter()) {
+                ret.push(pt.0);
+                ret.push(pt.1);
+                if let MsmOp::Step(MulStep::Triple) = op {
+                    ret.push(scale_factors.next().unwrap());
+                }
             }
 
-            ret // layout: 2(nl-1) accumulators, 2(nl-1) x3, 2(nl-1) x9, 2*(nl-1) scalefactors
+            ret // layout: a point for every operation, triplings followed by their scale factors
     });
 
     let advices = circuit.advice(
         round,
         adv,
-        pts_limbs.clone(),
+        pts_limbs.iter().flatten().map(|pt| [pt.x, pt.y]).flatten().collect(),
     );
 
-    let (acc, rest) = advices.split_at(2*(num_limbs-1));
-    let (x3, rest) = rest.split_at(2*(num_limbs-1));
-    let (x9, rest) = rest.split_at(2*(num_limbs-1));
-    let (scale3, scale9) = rest.split_at(num_limbs-1);
-
-    let mut pt_acc = vec![EcAffinePoint::<F,C>::new_unchecked(pts_limbs[2*num_limbs-2], pts_limbs[2*num_limbs-1])];
-    // Insert first accumulator back where it belongs.
-    let mut pt_x3 = vec![];
-    let mut pt_x9 = vec![];
-
-    for i in 0..num_limbs-1 {
-        pt_acc.push(EcAffinePoint::<F,C>::new(circuit, acc[2*i], acc[2*i+1]));
-        pt_x3.push(EcAffinePoint::<F,C>::new(circuit, x3[2*i], x3[2*i+1]));
-        pt_x9.push(EcAffinePoint::<F,C>::new(circuit, x9[2*i], x9[2*i+1]));
-    }
-
     // Check that 2A.rescale(q) = B-A. Notice!! - q must be nonzero. alternative would be using 1/q,
     // but this would increase degree from 8 to 9
     let triple_check = Gatebb::new(
@@ -415,28 +508,85 @@ pub fn escalarmul_gadget_9<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
         vec![],
     );
 
-    for i in 0..num_limbs-1 {
-        let input = vec![pt_acc[i].x, pt_acc[i].y, pt_x3[i].x, pt_x3[i].y, scale3[i]];
-        circuit.constrain(&input, triple_check.clone());
-        nonzeros.push(scale3[i]);
-        let input = vec![pt_x3[i].x, pt_x3[i].y, pt_x9[i].x, pt_x9[i].y, scale9[i]];
-        circuit.constrain(&input, triple_check.clone());
-        nonzeros.push(scale9[i]);
-
-        eclin_gadget(circuit,
-            pt_x9[i],
-            EcAffinePoint::<F,C>::new_unchecked(pts_limbs[2*num_limbs-2-2*(i+1)], pts_limbs[2*num_limbs-1-2*(i+1)]),
-            pt_acc[i+1],
-            nonzeros,
-            round
-        )
+    let mut advices = advices.into_iter();
+    let mut acc = pts_limbs[0][num_limbs-1];
+    for op in ops {
+        let (x, y) = (advices.next().unwrap(), advices.next().unwrap());
+        let next = EcAffinePoint::<F,C>::new(circuit, x, y);
+        match op {
+            MsmOp::Step(MulStep::Double) => ectangent_gadget(circuit, acc, next, nonzeros, round),
+            MsmOp::Step(MulStep::Triple) => {
+                let scale = advices.next().unwrap();
+                circuit.constrain(&[acc.x, acc.y, next.x, next.y, scale], triple_check.clone());
+                nonzeros.push(scale);
+            }
+            MsmOp::Add(j, i) => eclin_gadget(circuit, acc, pts_limbs[j][i], next, nonzeros, round),
+        }
+        acc = next;
     }
 
-    let ret = ecadd_gadget(circuit, b, pt_acc[num_limbs-1], nonzeros, round);
+    ecadd_gadget(circuit, b, acc, nonzeros, round)
+}
+
+/// A gadget that multiples a point by a given scalar
+/// Prover also must provide a pair of points (a, b) satisfying b + (1+9+9^2+...9^{num_limbs-1}) a = 0.
+/// The proof is sound if this equation holds, and complete if a is chosen at random.
+/// In practice these points typically should be given as public inputs, and constrained by the decider.
+/// base9 - version
+pub fn escalarmul_gadget_9<'a, F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    sc: Variable,
+    pt: EcAffinePoint<F,C>,
+    num_limbs: usize,
+    round: usize,
+    a: EcAffinePoint<F,C>,
+    b: EcAffinePoint<F,C>,
+    nonzeros: &mut Nonzeros,
+) -> EcAffinePoint<F, C> {
+    let window = Window::new(vec![MulStep::Triple, MulStep::Triple]);
+    escalarmul_gadget(circuit, sc, pt, &window, num_limbs, round, a, b, nonzeros)
+}
 
-    ret
+/// Amount of limbs needed to fit a scalar of a given bit length.
+pub fn num_limbs_for_bits(base: u32, num_bits: usize) -> usize {
+    let bound = BigUint::one() << num_bits;
+    let mut power = BigUint::one();
+    let mut num_limbs = 0;
+    while power < bound {
+        power *= base;
+        num_limbs += 1;
+    }
+    max(num_limbs, 1)
+}
 
+/// Private witness allocated by a scalar multiplication with a given window, including the final nonzero check.
+pub fn escalarmul_witness_size<F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(
+    max_degree: usize,
+    window: &Window,
+    num_limbs: usize,
+) -> usize {
+    let mut circuit = Circuit::<F, Gatebb<F>>::new(max_degree, 1);
+    let ext = circuit.ext_val(7);
+    let vars : Vec<_> = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect();
+    let pts : Vec<_> = vars.chunks(2).take(3).map(|v| EcAffinePoint::<F,C>::new_unchecked(v[0], v[1])).collect();
+    let before = circuit.cs.witness_spec().round_specs[0].privs;
+
+    let mut nonzeros = Nonzeros::new(max_degree - 1);
+    escalarmul_gadget(&mut circuit, vars[6], pts[0], window, num_limbs, 0, pts[1], pts[2], &mut nonzeros);
+    nonzeros.finalize(&mut circuit);
+
+    circuit.cs.witness_spec().round_specs[0].privs - before
+}
 
+/// Picks a window minimizing the witness of a multiplication by a scalar of num_bits bits in a circuit of given degree.
+/// Candidates are all bases up to max_degree which are products of 2s and 3s (3s only if tripling fits in the degree).
+pub fn choose_window<F: PrimeField + FieldUtils, C: CurveExt<Base=F>>(max_degree: usize, num_bits: usize) -> Window {
+    assert!(max_degree >= 3, "Scalar multiplication requires degree at least 3.");
+    (2..=max_degree as u32)
+        .filter_map(|base| Window::from_base(base, max_degree >= 8))
+        .filter(|window| window.min_degree() <= max_degree)
+        .min_by_key(|window| escalarmul_witness_size::<F,C>(max_degree, window, num_limbs_for_bits(window.base(), num_bits)))
+        .unwrap()
 }
 
 // Gadget that checks that 3a = b.
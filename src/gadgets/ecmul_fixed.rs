@@ -0,0 +1,301 @@
+// Elliptic curve multiplication by constant points, f.e. generators of Pedersen commitments.
+// Strategy:
+// The scalar is decomposed into limbs of base B. For the window i, the table k B^i G + H_i, k in 0..B, is known
+// in advance, so the chosen point is a polynomial of degree B in the limb with constant coefficients, and the tables
+// do not occupy the witness. The chosen points are summed, and the sum of the offsets H_i is subtracted by shifting
+// the last table, so no doublings and no offset points provided by the prover are needed.
+//
+// Offsets are derived deterministically from G, and their discrete logarithms are unknown. Then the partial sums can
+// coincide with (or be opposite to) the next chosen point only if the result is the point at infinity, which is not
+// representable, so the scalar must be nonzero.
+//
+// The limbs are only checked to recompose to the scalar modulo the native modulus p. The scalar field of the curve
+// differs from the native field, so the decomposition must be unique: tables are restricted to B^n <= p, and
+// the scalars must be below B^n.
+
+use ff::{Field, PrimeField, BatchInvert};
+use halo2::halo2curves::CurveExt;
+use itertools::Itertools;
+use num_bigint::BigUint;
+
+use crate::{
+    circuit::{Circuit, Advice, PolyOp},
+    constraint_system::Variable,
+    gate::Gatebb,
+    utils::{field_precomp::FieldUtils, arith_helper::{j2a, modulus}},
+};
+
+use super::{
+    ecmul::{EcAffinePoint, eclin_gadget},
+    nonzero_check::Nonzeros,
+    rangecheck_common::VarRange,
+    rangecheck_small::{lagrange_choice_batched, limb_decompose_no_lookup_gadget},
+};
+
+/// First point of the curve with x-coordinate at least seed. Its discrete logarithm is unknown.
+fn point_from_seed<C: CurveExt>(seed: C::Base) -> C {
+    let mut x = seed;
+    loop {
+        let y2 = x.cube() + C::a() * x + C::b();
+        if let Some(y) = Option::<C::Base>::from(y2.sqrt()) {
+            if let Some(pt) = Option::<C>::from(C::new_jacobian(x, y, C::Base::ONE)) {
+                return pt
+            }
+        }
+        x += C::Base::ONE;
+    }
+}
+
+/// Largest amount of limbs of base B such that B^n <= p, see FixedBaseTable::new.
+pub fn max_fixed_base_limbs<F: PrimeField>(base: u32) -> usize {
+    assert!(base >= 2);
+    let p = modulus::<F>();
+    let mut power = BigUint::from(base);
+    let mut num_limbs = 0;
+    while power <= p {
+        power *= base;
+        num_limbs += 1;
+    }
+    num_limbs
+}
+
+/// Precomputed multiples of a constant point, windows[i][k] = k B^i pt + H_i.
+#[derive(Clone)]
+pub struct FixedBaseTable<C: CurveExt> {
+    base: u32,
+    windows: Vec<Vec<C>>,
+    offset: C,
+}
+
+impl<C: CurveExt> FixedBaseTable<C> {
+    /// Table for scalars below B^n. It is required that B^n <= p (see max_fixed_base_limbs), otherwise the
+    /// decomposition of a scalar is not unique and the prover could multiply by sc + p instead.
+    pub fn new(pt: C, base: u32, num_limbs: usize) -> Self {
+        assert!(base >= 2 && num_limbs > 0);
+        assert!(num_limbs <= max_fixed_base_limbs::<C::Base>(base),
+            "Scalars of {} limbs of base {} can exceed the native modulus.", num_limbs, base);
+        assert!(!bool::from(pt.is_identity()), "Point at infinity does not have affine coordinates.");
+        let (x, _) = j2a(pt.jacobian_coordinates());
+        let mut windows = vec![];
+        let mut offset = C::identity();
+        let mut step = pt;
+        for i in 0..num_limbs {
+            let h = point_from_seed::<C>(x + C::Base::from((i as u64 + 1) << 32));
+            windows.push((0..base).map(|k| step * C::ScalarExt::from(k as u64) + h).collect());
+            offset = offset + h;
+            step = step * C::ScalarExt::from(base as u64);
+        }
+        Self { base, windows, offset }
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    pub fn num_limbs(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// Sum of the offsets H_i over all windows.
+    fn offset(&self) -> C {
+        self.offset
+    }
+}
+
+/// Chooses a point from constant coordinates by the limb.
+fn fixed_choice_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    coords: Vec<(F, F)>,
+    limb: &VarRange<F>,
+    round: usize,
+) -> Vec<Variable> {
+    let n = coords.len();
+    circuit.apply(round, PolyOp::new(n, 1, 2, move |args, _| {
+        let coeffs = lagrange_choice_batched(args[0], n as u64);
+        coeffs.iter().zip_eq(coords.iter())
+            .fold(vec![F::ZERO, F::ZERO], |acc, (c, (x, y))| vec![acc[0] + *c * x, acc[1] + *c * y])
+    }), vec![limb.var()])
+}
+
+/// Computes sum sc_i pt_i for constant points given by their tables. Costs a limb, a chosen point, a sum and
+/// a nonzero check per window, and requires degree at least the base of every table.
+/// Every scalar must be below B^n of its table.
+pub fn fixed_base_msm_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    scalars: &[Variable],
+    tables: &[FixedBaseTable<C>],
+    round: usize,
+    nonzeros: &mut Nonzeros,
+) -> EcAffinePoint<F, C> {
+    assert!(scalars.len() == tables.len() && scalars.len() > 0);
+    let total_offset = tables.iter().fold(C::identity(), |acc, t| acc + t.offset());
+
+    // Constant points of every window, the last one is shifted by the sum of the offsets.
+    let mut windows = tables.iter().flat_map(|t| t.windows.iter().cloned()).collect_vec();
+    assert!(windows.len() > 1, "Single window is not supported, use more limbs.");
+    let last = windows.len() - 1;
+    windows[last] = windows[last].iter().map(|pt| *pt - total_offset).collect();
+
+    let mut limbs = vec![];
+    for (sc, table) in scalars.iter().zip_eq(tables.iter()) {
+        assert!(circuit.max_degree() >= table.base as usize, "Table of base {} requires degree at least {}.", table.base, table.base);
+        limbs.extend(limb_decompose_no_lookup_gadget(circuit, table.base, round, table.num_limbs(), *sc));
+    }
+
+    let chosen = windows.iter().zip_eq(limbs.iter()).map(|(pts, limb)| {
+        let coords = pts.iter().map(|pt| {
+            assert!(!bool::from(pt.is_identity()), "Table contains the point at infinity.");
+            j2a(pt.jacobian_coordinates())
+        }).collect_vec();
+        let v = fixed_choice_gadget(circuit, coords, limb, round);
+        EcAffinePoint::<F, C>::new_unchecked(v[0], v[1])
+    }).collect_vec();
+
+    // Partial sums are computed from the limbs, using a single batch inversion.
+    let adv_windows = windows.clone();
+    let sums = circuit.advice(round, Advice::new(limbs.len(), 2 * last, move |args, _| {
+        let mut curr = C::identity();
+        let mut jac = vec![];
+        for (w, limb) in adv_windows.iter().zip_eq(args.iter()) {
+            let k = (0..w.len()).find(|k| F::from(*k as u64) == *limb).expect("Limb is out of range.");
+            curr = curr + w[k];
+            jac.push(curr);
+        }
+        let mut zinv = jac[1..].iter().map(|pt| pt.jacobian_coordinates().2).collect_vec();
+        zinv.batch_invert();
+        jac[1..].iter().zip_eq(zinv.iter()).flat_map(|(pt, z)| {
+            let (x, y, _) = pt.jacobian_coordinates();
+            let zsq = z.square();
+            [x * zsq, y * zsq * z]
+        }).collect()
+    }), limbs.iter().map(|l| l.var()).collect());
+
+    let mut acc = chosen[0];
+    for (pt, sum) in chosen[1..].iter().zip_eq(sums.chunks(2)) {
+        let next = EcAffinePoint::<F, C>::new(circuit, sum[0], sum[1]);
+        eclin_gadget(circuit, acc, *pt, next, nonzeros, round);
+        acc = next;
+    }
+    acc
+}
+
+/// Computes sc pt for a constant point given by its table, see fixed_base_msm_gadget.
+pub fn fixed_base_scalarmul_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    sc: Variable,
+    table: &FixedBaseTable<C>,
+    round: usize,
+    nonzeros: &mut Nonzeros,
+) -> EcAffinePoint<F, C> {
+    fixed_base_msm_gadget(circuit, &[sc], &[table.clone()], round, nonzeros)
+}
+
+#[cfg(test)]
+mod tests {
+    use group::Group;
+    use halo2::halo2curves::{bn256, grumpkin};
+    use rand_core::OsRng;
+
+    use crate::{gadgets::{ecmul::num_limbs_for_bits, input::input}, utils::arith_helper::{from_biguint, to_biguint}};
+
+    use super::*;
+
+    type F = bn256::Fr;
+    type C = grumpkin::G1;
+
+    fn native_mul(pt: C, sc: F) -> C {
+        pt * from_biguint::<<C as CurveExt>::ScalarExt>(&to_biguint(sc))
+    }
+
+    /// Returns the size of the private witness.
+    fn check_msm(base: u32, num_terms: usize) -> usize {
+        let num_limbs = max_fixed_base_limbs::<F>(base);
+        let bound = BigUint::from(base).pow(num_limbs as u32);
+        let pts = (0..num_terms).map(|_| C::random(OsRng)).collect_vec();
+        let tables = pts.iter().map(|pt| FixedBaseTable::new(*pt, base, num_limbs)).collect_vec();
+
+        let mut circuit = Circuit::new(base.max(3) as usize, 1);
+        let ext = circuit.ext_val(num_terms);
+        let scalars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let mut nonzeros = Nonzeros::new(base.max(3) as usize - 1);
+        let ret = fixed_base_msm_gadget(&mut circuit, &scalars, &tables, 0, &mut nonzeros);
+        nonzeros.finalize(&mut circuit);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let values = (0..num_terms).map(|_| from_biguint::<F>(&(to_biguint(F::random(OsRng)) % &bound))).collect_vec();
+        for (e, v) in ext.iter().zip_eq(values.iter()) {
+            instance.set_ext(*e, *v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        let expected = pts.iter().zip_eq(values.iter()).fold(C::identity(), |acc, (pt, sc)| acc + native_mul(*pt, *sc));
+        assert_eq!(instance.cs.get_vars(&[ret.x, ret.y]), {
+            let (x, y) = j2a(expected.jacobian_coordinates());
+            vec![x, y]
+        });
+        let size = instance.cs.wtns[0].privs.len();
+        instance.finish();
+        size
+    }
+
+    // Every window of n limbs costs a limb, a chosen point and (except the first one) a partial sum and a nonzero
+    // entry, so the size is 6n - 2 plus the running product of the nonzero check.
+    #[test]
+    fn scalarmul() {
+        assert_eq!(check_msm(2, 1), 1767);
+        assert_eq!(check_msm(3, 1), 1116);
+        assert_eq!(check_msm(8, 1), 516);
+        assert_eq!(check_msm(16, 1), 381);
+    }
+
+    #[test]
+    fn msm() {
+        assert_eq!(check_msm(8, 3), 1552);
+    }
+
+    #[test]
+    fn small_scalars() {
+        let base = 4;
+        let pt = C::random(OsRng);
+        let table = FixedBaseTable::new(pt, base, 3);
+
+        let mut circuit = Circuit::new(4, 1);
+        let ext = circuit.ext_val(1);
+        let sc = input(&mut circuit, ext[0], 0);
+        let mut nonzeros = Nonzeros::new(3);
+        let ret = fixed_base_scalarmul_gadget(&mut circuit, sc, &table, 0, &mut nonzeros);
+        nonzeros.finalize(&mut circuit);
+
+        let constructed = circuit.finalize();
+        for value in 1..64u64 {
+            let mut instance = constructed.spawn();
+            instance.set_ext(ext[0], F::from(value));
+            instance.execute(0);
+            instance.valid_witness();
+            let (x, y) = j2a(native_mul(pt, F::from(value)).jacobian_coordinates());
+            assert_eq!(instance.cs.get_vars(&[ret.x, ret.y]), vec![x, y]);
+            instance.finish();
+        }
+    }
+
+    #[test]
+    fn limb_bound() {
+        let p = modulus::<F>();
+        for base in [2, 3, 8, 16] {
+            let num_limbs = max_fixed_base_limbs::<F>(base);
+            assert!(BigUint::from(base).pow(num_limbs as u32) <= p);
+            assert!(BigUint::from(base).pow(num_limbs as u32 + 1) > p);
+        }
+    }
+
+    // With 254 bits of limbs, the limbs of sc + p recompose to sc in the native field, and the gadget
+    // would compute (sc + p) G on grumpkin, where p is not the order of G.
+    #[test]
+    #[should_panic(expected = "Scalars of 64 limbs of base 16 can exceed the native modulus.")]
+    fn non_canonical_decomposition() {
+        let num_limbs = num_limbs_for_bits(16, F::NUM_BITS as usize);
+        FixedBaseTable::new(C::random(OsRng), 16, num_limbs);
+    }
+}
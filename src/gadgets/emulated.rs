@@ -0,0 +1,614 @@
+// Non-native field arithmetic. An element of a foreign field Ff is represented by little-endian limbs in base
+// 2^limb_bits, each of which is a VarRange over the native field F. Elements are not kept reduced: addition and
+// subtraction act limb-wise and only grow the ranges, while mul / reduce produce limbs of limb_bits size.
+//
+// Multiplication and reduction are proven as an integer identity in limbs, i.e. a*b - q*p - r = 0.
+// Columns of this identity are summed in groups, as many as fit into the native field without wraparound,
+// and the carries between groups are split into digits which are range-checked by degree rc_base gates.
+
+use std::{rc::Rc, marker::PhantomData};
+
+use ff::PrimeField;
+use itertools::Itertools;
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::{One, Zero};
+
+use crate::{circuit::{Circuit, Advice, PolyOp}, constraint_system::Variable, gate::Gatebb, utils::{field_precomp::FieldUtils, arith_helper::{modulus, from_biguint, to_biguint}}};
+
+use super::{rangecheck_common::{VarRange, lc_uint}, rangecheck_small::limb_decompose_no_lookup_gadget, arith::read_const_gadget, lc::inner_prod};
+
+fn from_bigint<F: PrimeField>(x: &BigInt) -> F {
+    let abs = from_biguint::<F>(&(x.magnitude() % modulus::<F>()));
+    if x.sign() == Sign::Minus {-abs} else {abs}
+}
+
+/// Splits x into n limbs of limb_bits size.
+pub(crate) fn split(x: &BigUint, limb_bits: usize, n: usize) -> Vec<BigUint> {
+    assert!(x.bits() as usize <= limb_bits * n, "The value has too many limbs.");
+    let mask = (BigUint::one() << limb_bits) - BigUint::one();
+    (0..n).map(|i| (x >> (limb_bits * i)) & &mask).collect()
+}
+
+#[derive(Clone)]
+/// Foreign field element: little-endian limbs, not necessarily reduced.
+pub struct EmulatedElement<F: PrimeField+FieldUtils> {
+    limbs: Vec<VarRange<F>>,
+}
+
+impl<F: PrimeField+FieldUtils> EmulatedElement<F> {
+    pub fn limbs(&self) -> &[VarRange<F>] {
+        &self.limbs
+    }
+
+    pub fn vars(&self) -> Vec<Variable> {
+        self.limbs.iter().map(|l| l.var()).collect()
+    }
+}
+
+struct Monomial {
+    coeff: BigInt,
+    vars: Vec<usize>,
+}
+
+/// Integer identity sum_k column_k * 2^(limb_bits * k) = 0, where every column is a polynomial
+/// of degree at most 2 in the limbs.
+pub(crate) struct LimbIdentity<F: PrimeField+FieldUtils> {
+    inputs: Vec<VarRange<F>>,
+    columns: Vec<Vec<Monomial>>,
+}
+
+impl<F: PrimeField+FieldUtils> LimbIdentity<F> {
+    pub(crate) fn new() -> Self {
+        Self { inputs: vec![], columns: vec![] }
+    }
+
+    fn input(&mut self, v: &VarRange<F>) -> usize {
+        match self.inputs.iter().position(|x| x.var() == v.var()) {
+            Some(i) => i,
+            None => {
+                self.inputs.push(v.clone());
+                self.inputs.len() - 1
+            }
+        }
+    }
+
+    pub(crate) fn push(&mut self, column: usize, coeff: BigInt, vars: &[&VarRange<F>]) {
+        if coeff.is_zero() {return}
+        while self.columns.len() <= column {
+            self.columns.push(vec![]);
+        }
+        let vars = vars.iter().map(|v| self.input(v)).collect();
+        self.columns[column].push(Monomial { coeff, vars });
+    }
+
+    pub(crate) fn add_prod(&mut self, a: &[VarRange<F>], b: &[VarRange<F>], coeff: i64) {
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                self.push(i + j, BigInt::from(coeff), &[x, y]);
+            }
+        }
+    }
+
+    pub(crate) fn add_lin(&mut self, a: &[VarRange<F>], coeff: i64) {
+        for (i, x) in a.iter().enumerate() {
+            self.push(i, BigInt::from(coeff), &[x]);
+        }
+    }
+
+    pub(crate) fn add_const_prod(&mut self, a: &[VarRange<F>], c: &[BigUint], coeff: i64) {
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in c.iter().enumerate() {
+                self.push(i + j, BigInt::from(coeff) * BigInt::from(y.clone()), &[x]);
+            }
+        }
+    }
+
+    pub(crate) fn add_const(&mut self, c: &[BigUint], coeff: i64) {
+        for (i, y) in c.iter().enumerate() {
+            self.push(i, BigInt::from(coeff) * BigInt::from(y.clone()), &[]);
+        }
+    }
+
+    /// Bound on the absolute value of a column.
+    fn column_bound(&self, k: usize) -> BigUint {
+        let mut pos = BigUint::zero();
+        let mut neg = BigUint::zero();
+        for m in &self.columns[k] {
+            let mag = m.vars.iter().fold(m.coeff.magnitude().clone(), |acc, &v| acc * (self.inputs[v].range() - 1u8));
+            if m.coeff.sign() == Sign::Minus {neg += mag} else {pos += mag}
+        }
+        pos.max(neg)
+    }
+
+    fn eval_column(columns: &[Vec<Monomial>], k: usize, values: &[BigInt]) -> BigInt {
+        columns[k].iter().fold(BigInt::zero(), |acc, m| {
+            acc + m.vars.iter().fold(m.coeff.clone(), |p, &v| p * &values[v])
+        })
+    }
+
+    /// Constrains the identity. Carries between column groups are offset to be nonnegative
+    /// and decomposed into base rc_base digits.
+    pub(crate) fn constrain<'a>(self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, limb_bits: usize, rc_base: u32, round: usize) {
+        let native = modulus::<F>();
+        let ncols = self.columns.len();
+        let bounds = (0..ncols).map(|k| self.column_bound(k)).collect_vec();
+
+        // |carry out of the first e columns| <= carry_bounds[e]
+        let mut carry_bounds = vec![BigUint::zero(); ncols + 1];
+        let mut prefix = BigUint::zero();
+        for e in 1..ncols {
+            prefix += &bounds[e-1] << (limb_bits * (e-1));
+            carry_bounds[e] = (&prefix + (BigUint::one() << (limb_bits * e)) - 1u8) >> (limb_bits * e);
+        }
+        // the offset carry lies in [0, 2*bound], it is decomposed into num_digits[e] digits, i.e. is < caps[e]
+        let mut num_digits = vec![];
+        let mut caps = vec![];
+        for b in &carry_bounds {
+            let mut cap = BigUint::one();
+            let mut d = 0;
+            while cap <= b * 2u8 {
+                cap *= rc_base;
+                d += 1;
+            }
+            num_digits.push(d);
+            caps.push(cap);
+        }
+
+        let mut groups = vec![];
+        let mut s = 0;
+        while s < ncols {
+            let mut e = s;
+            let mut acc = BigUint::zero();
+            for cand in s+1..=ncols {
+                acc += &bounds[cand-1] << (limb_bits * (cand-1-s));
+                if &acc + &caps[s] + (&caps[cand] << (limb_bits * (cand-s))) < native {
+                    e = cand;
+                } else {
+                    break
+                }
+            }
+            assert!(e > s, "Column {} of emulated field identity does not fit in the native field, reduce the operands.", s);
+            groups.push((s, e));
+            s = e;
+        }
+
+        let columns = Rc::new(self.columns);
+        let inputs = self.inputs.iter().map(|x| x.var()).collect_vec();
+        let total_digits = groups[..groups.len()-1].iter().map(|&(_, e)| num_digits[e]).sum();
+
+        let digits = {
+            let columns = columns.clone();
+            let groups = groups.clone();
+            let carry_bounds = carry_bounds.clone();
+            let num_digits = num_digits.clone();
+            circuit.advice(round, Advice::new(inputs.len(), total_digits, move |args, _| {
+                let values = args.iter().map(|x| BigInt::from(to_biguint(*x))).collect_vec();
+                let mut carry = BigInt::zero();
+                let mut ret = vec![];
+                for &(s, e) in &groups {
+                    let t = (s..e).fold(carry, |acc, k| acc + (Self::eval_column(&columns, k, &values) << (limb_bits * (k-s))));
+                    let div = BigInt::one() << (limb_bits * (e-s));
+                    assert!((&t % &div).is_zero(), "Emulated field identity does not hold.");
+                    carry = t / div;
+                    if e == ncols {
+                        assert!(carry.is_zero(), "Emulated field identity does not hold.");
+                    } else {
+                        let mut c = (&carry + BigInt::from(carry_bounds[e].clone())).to_biguint().unwrap();
+                        for _ in 0..num_digits[e] {
+                            ret.push(from_biguint(&(&c % rc_base)));
+                            c /= rc_base;
+                        }
+                        assert!(c.is_zero());
+                    }
+                }
+                ret
+            }), inputs.clone())
+        };
+        let digits = digits.into_iter().map(|d| VarRange::new_no_lookup(circuit, d, rc_base).var()).collect_vec();
+
+        let base = F::from(rc_base as u64);
+        let mut digit_offset = 0;
+        let mut prev: Vec<Variable> = vec![];
+        let mut prev_bound = F::ZERO;
+        for &(s, e) in &groups {
+            let cur = digits[digit_offset..digit_offset + num_digits[e]].to_vec();
+            digit_offset += num_digits[e];
+            let cur_bound = from_biguint::<F>(&carry_bounds[e]);
+
+            let mut local = vec![];
+            let mut monomials = vec![];
+            for k in s..e {
+                let scale = from_biguint::<F>(&(BigUint::one() << (limb_bits * (k-s))));
+                for m in &columns[k] {
+                    let vars = m.vars.iter().map(|&v| {
+                        match local.iter().position(|&x| x == v) {
+                            Some(i) => i,
+                            None => {local.push(v); local.len() - 1}
+                        }
+                    }).collect_vec();
+                    monomials.push((from_bigint::<F>(&m.coeff) * scale, vars));
+                }
+            }
+            let degree = monomials.iter().map(|(_, vars)| vars.len()).max().unwrap_or(0).max(1);
+            let mut vars = local.iter().map(|&v| inputs[v]).collect_vec();
+            let n_local = vars.len();
+            let n_prev = prev.len();
+            vars.extend(prev.iter());
+            vars.extend(cur.iter());
+            let shift = from_biguint::<F>(&(BigUint::one() << (limb_bits * (e-s))));
+
+            circuit.constrain(&vars, Gatebb::new(degree, vars.len(), 1, Rc::new(move |args, _| {
+                let t = monomials.iter().fold(F::ZERO, |acc, (c, vars)| {
+                    acc + vars.iter().fold(*c, |p, &v| p * args[v])
+                });
+                let from_digits = |digits: &[F]| digits.iter().rev().fold(F::ZERO, |acc, d| acc * base + d);
+                let carry_in = from_digits(&args[n_local..n_local + n_prev]) - prev_bound;
+                let carry_out = from_digits(&args[n_local + n_prev..]) - cur_bound;
+                vec![t + carry_in - carry_out * shift]
+            }), vec![]));
+
+            prev = cur;
+            prev_bound = cur_bound;
+        }
+    }
+}
+
+/// Range-checks a limb of limb_bits bits using digits of base rc_base, which must be a power of two dividing 2^limb_bits.
+pub(crate) fn range_check_limb<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    var: Variable,
+    limb_bits: usize,
+    rc_base: u32,
+    round: usize,
+) -> VarRange<F> {
+    let num_digits = limb_bits / rc_base.trailing_zeros() as usize;
+    limb_decompose_no_lookup_gadget(circuit, rc_base, round, num_digits, var);
+    VarRange::new_unchecked(var, BigUint::one() << limb_bits)
+}
+
+/// Allocates range-checked integers with given amounts of limbs, computed from integer values of the inputs.
+pub(crate) fn alloc_limbs<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    inputs: &[&[VarRange<F>]],
+    num_limbs: &[usize],
+    limb_bits: usize,
+    rc_base: u32,
+    f: impl Fn(&[BigUint]) -> Vec<BigUint> + 'a,
+    round: usize,
+) -> Vec<Vec<VarRange<F>>> {
+    let sizes = inputs.iter().map(|x| x.len()).collect_vec();
+    let vars = inputs.iter().flat_map(|x| x.iter().map(|l| l.var())).collect_vec();
+    let out_limbs = num_limbs.to_vec();
+    let total = out_limbs.iter().sum();
+
+    let outputs = circuit.advice(round, Advice::new(vars.len(), total, move |args, _| {
+        let mut args = args;
+        let mut values = vec![];
+        for &size in &sizes {
+            let (head, tail) = args.split_at(size);
+            values.push(head.iter().enumerate()
+                .fold(BigUint::zero(), |acc, (i, l)| acc + (to_biguint(*l) << (limb_bits * i))));
+            args = tail;
+        }
+        f(&values).iter().zip_eq(out_limbs.iter())
+            .flat_map(|(x, &n)| split(x, limb_bits, n))
+            .map(|x| from_biguint(&x))
+            .collect()
+    }), vars);
+
+    let mut outputs = outputs.as_slice();
+    num_limbs.iter().map(|&n| {
+        let (head, tail) = outputs.split_at(n);
+        outputs = tail;
+        head.iter().map(|v| range_check_limb(circuit, *v, limb_bits, rc_base, round)).collect()
+    }).collect()
+}
+
+/// Arithmetic of the foreign field Ff in a circuit over F.
+pub struct EmulatedField<F: PrimeField+FieldUtils, Ff: PrimeField> {
+    limb_bits: usize,
+    num_limbs: usize,
+    rc_base: u32,
+    modulus: BigUint,
+    _marker: PhantomData<(F, Ff)>,
+}
+
+impl<F: PrimeField+FieldUtils, Ff: PrimeField> EmulatedField<F, Ff> {
+    /// Reduced elements use num_limbs limbs of limb_bits bits. Range checks are done in base rc_base,
+    /// which must be a power of two dividing 2^limb_bits; they require circuit degree at least rc_base.
+    pub fn new(limb_bits: usize, num_limbs: usize, rc_base: u32) -> Self {
+        assert!(rc_base >= 2 && rc_base.is_power_of_two(), "Range check base must be a power of two.");
+        assert!(limb_bits % (rc_base.trailing_zeros() as usize) == 0, "Limb size must be a multiple of range check digit size.");
+        assert!(BigUint::one() << limb_bits < modulus::<F>(), "Limbs do not fit in the native field.");
+        let modulus = modulus::<Ff>();
+        assert!(modulus.bits() as usize <= limb_bits * num_limbs, "Not enough limbs to represent the foreign field.");
+        Self { limb_bits, num_limbs, rc_base, modulus, _marker: PhantomData }
+    }
+
+    pub fn limb_bits(&self) -> usize {
+        self.limb_bits
+    }
+
+    pub fn num_limbs(&self) -> usize {
+        self.num_limbs
+    }
+
+    fn num_limbs_for(&self, x: &BigUint) -> usize {
+        ((x.bits() as usize + self.limb_bits - 1) / self.limb_bits).max(1)
+    }
+
+    /// Upper bound on the integer value of an element.
+    pub fn max_value(&self, a: &EmulatedElement<F>) -> BigUint {
+        a.limbs.iter().enumerate().fold(BigUint::zero(), |acc, (i, l)| acc + ((l.range() - 1u8) << (self.limb_bits * i)))
+    }
+
+    /// Integer value of an element given the values of its limbs.
+    pub fn value(&self, limbs: &[F]) -> BigUint {
+        limbs.iter().enumerate().fold(BigUint::zero(), |acc, (i, l)| acc + (to_biguint(*l) << (self.limb_bits * i)))
+    }
+
+    pub fn to_foreign(&self, limbs: &[F]) -> Ff {
+        from_biguint(&(self.value(limbs) % &self.modulus))
+    }
+
+    /// Splits a foreign field element into num_limbs limbs.
+    pub fn to_limbs(&self, x: Ff) -> Vec<F> {
+        split(&to_biguint(x), self.limb_bits, self.num_limbs).iter().map(from_biguint).collect()
+    }
+
+    /// Wraps already range-checked limbs, f.e. ones of CyclefoldInstanceExternalView.
+    /// Limbs must be in base 2^limb_bits, but may have any range.
+    pub fn from_limbs(&self, limbs: Vec<VarRange<F>>) -> EmulatedElement<F> {
+        EmulatedElement { limbs }
+    }
+
+    fn range_check_limb<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, var: Variable, round: usize) -> VarRange<F> {
+        range_check_limb(circuit, var, self.limb_bits, self.rc_base, round)
+    }
+
+    /// Range-checks num_limbs variables to limb_bits size and constructs an element from them.
+    pub fn range_check<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, vars: &[Variable], round: usize) -> EmulatedElement<F> {
+        assert_eq!(vars.len(), self.num_limbs);
+        EmulatedElement { limbs: vars.iter().map(|v| self.range_check_limb(circuit, *v, round)).collect() }
+    }
+
+    /// Allocates range-checked elements with given amounts of limbs, computed from integer values of the inputs.
+    fn alloc<'a>(
+        &self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        inputs: &[&EmulatedElement<F>],
+        num_limbs: &[usize],
+        f: impl Fn(&[BigUint]) -> Vec<BigUint> + 'a,
+        round: usize,
+    ) -> Vec<EmulatedElement<F>> {
+        let inputs = inputs.iter().map(|x| x.limbs()).collect_vec();
+        alloc_limbs(circuit, &inputs, num_limbs, self.limb_bits, self.rc_base, f, round).into_iter()
+            .map(|limbs| EmulatedElement { limbs }).collect()
+    }
+
+    pub fn constant<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, x: Ff, round: usize) -> EmulatedElement<F> {
+        EmulatedElement {
+            limbs: split(&to_biguint(x), self.limb_bits, self.num_limbs).iter().map(|l| {
+                VarRange::new_unchecked(read_const_gadget(circuit, from_biguint(l), round), l + 1u8)
+            }).collect()
+        }
+    }
+
+    pub fn add<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &EmulatedElement<F>, b: &EmulatedElement<F>, round: usize) -> EmulatedElement<F> {
+        let n = a.limbs.len().max(b.limbs.len());
+        EmulatedElement {
+            limbs: (0..n).map(|i| match (a.limbs.get(i), b.limbs.get(i)) {
+                (Some(x), Some(y)) => lc_uint(circuit, &[BigUint::one(), BigUint::one()], &[x.clone(), y.clone()], round),
+                (Some(x), None) | (None, Some(x)) => x.clone(),
+                (None, None) => unreachable!(),
+            }).collect()
+        }
+    }
+
+    /// Limbs of a multiple of p which are not smaller than corresponding limbs of b, so d - b is nonnegative limb-wise.
+    fn padding(&self, b: &EmulatedElement<F>) -> Vec<BigUint> {
+        let p = &self.modulus;
+        let mut d = b.limbs.iter().map(|l| l.range() - 1u8).collect_vec();
+        let max = self.max_value(b);
+        let diff = (&max + p - 1u8) / p * p - max;
+        d.resize(d.len().max(self.num_limbs), BigUint::zero());
+        for (x, y) in d.iter_mut().zip(split(&diff, self.limb_bits, self.num_limbs)) {
+            *x += y;
+        }
+        while d.len() > 1 && d.last().unwrap().is_zero() {
+            d.pop();
+        }
+        d
+    }
+
+    /// Computes a - b + d, where d is a multiple of p padding every limb of b.
+    pub fn sub<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &EmulatedElement<F>, b: &EmulatedElement<F>, round: usize) -> EmulatedElement<F> {
+        let d = self.padding(b);
+        let n = a.limbs.len().max(b.limbs.len()).max(d.len());
+        EmulatedElement {
+            limbs: (0..n).map(|i| {
+                let c = d.get(i).cloned().unwrap_or_default();
+                let range = a.limbs.get(i).map_or(BigUint::one(), |x| x.range()) + &c;
+                let mut vars = vec![];
+                let mut coeffs = vec![];
+                if let Some(x) = a.limbs.get(i) {
+                    vars.push(x.var());
+                    coeffs.push(F::ONE);
+                }
+                if let Some(y) = b.limbs.get(i) {
+                    vars.push(y.var());
+                    coeffs.push(-F::ONE);
+                }
+                let c = from_biguint::<F>(&c);
+                let var = if vars.is_empty() {
+                    read_const_gadget(circuit, c, round)
+                } else {
+                    circuit.apply(round, PolyOp::new(1, vars.len(), 1, move |args, _| vec![inner_prod(&coeffs, args) + c]), vars)[0]
+                };
+                VarRange::new_unchecked(var, range)
+            }).collect()
+        }
+    }
+
+    fn modulus_limbs(&self) -> Vec<BigUint> {
+        split(&self.modulus, self.limb_bits, self.num_limbs)
+    }
+
+    /// Multiplies two elements. The result has num_limbs limbs, but is not necessarily smaller than p.
+    pub fn mul<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &EmulatedElement<F>, b: &EmulatedElement<F>, round: usize) -> EmulatedElement<F> {
+        let p = self.modulus.clone();
+        let nq = self.num_limbs_for(&(self.max_value(a) * self.max_value(b) / &p));
+        let mut out = self.alloc(circuit, &[a, b], &[nq, self.num_limbs], move |v| {
+            let prod = &v[0] * &v[1];
+            vec![&prod / &p, &prod % &p]
+        }, round);
+        let r = out.pop().unwrap();
+        let q = out.pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_prod(&a.limbs, &b.limbs, 1);
+        identity.add_const_prod(&q.limbs, &self.modulus_limbs(), -1);
+        identity.add_lin(&r.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        r
+    }
+
+    /// Reduces an element to num_limbs limbs. The result is not necessarily smaller than p.
+    pub fn reduce<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &EmulatedElement<F>, round: usize) -> EmulatedElement<F> {
+        let p = self.modulus.clone();
+        let nq = self.num_limbs_for(&(self.max_value(a) / &p));
+        let mut out = self.alloc(circuit, &[a], &[nq, self.num_limbs], move |v| vec![&v[0] / &p, &v[0] % &p], round);
+        let r = out.pop().unwrap();
+        let q = out.pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_lin(&a.limbs, 1);
+        identity.add_const_prod(&q.limbs, &self.modulus_limbs(), -1);
+        identity.add_lin(&r.limbs, -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        r
+    }
+
+    /// Constrains a = b mod p.
+    pub fn assert_equal<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &EmulatedElement<F>, b: &EmulatedElement<F>, round: usize) {
+        let d = self.padding(b);
+        let d_value = d.iter().enumerate().fold(BigUint::zero(), |acc, (i, x)| acc + (x << (self.limb_bits * i)));
+        let p = self.modulus.clone();
+        let nq = self.num_limbs_for(&((self.max_value(a) + &d_value) / &p));
+        let q = self.alloc(circuit, &[a, b], &[nq], move |v| {
+            let x = &v[0] + &d_value - &v[1];
+            assert!((&x % &p).is_zero(), "Emulated elements are not equal.");
+            vec![x / &p]
+        }, round).pop().unwrap();
+
+        let mut identity = LimbIdentity::new();
+        identity.add_lin(&a.limbs, 1);
+        identity.add_const(&d, 1);
+        identity.add_lin(&b.limbs, -1);
+        identity.add_const_prod(&q.limbs, &self.modulus_limbs(), -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+    }
+
+    /// Reduces an element to its canonical representative in [0, p).
+    pub fn canonicalize<'a>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &EmulatedElement<F>, round: usize) -> EmulatedElement<F> {
+        let r = self.reduce(circuit, a, round);
+        let p_minus_one = &self.modulus - 1u8;
+        let d = {
+            let p_minus_one = p_minus_one.clone();
+            self.alloc(circuit, &[&r], &[self.num_limbs], move |v| vec![&p_minus_one - &v[0]], round).pop().unwrap()
+        };
+
+        // r + d = p - 1 with both nonnegative
+        let mut identity = LimbIdentity::new();
+        identity.add_lin(&r.limbs, 1);
+        identity.add_lin(&d.limbs, 1);
+        identity.add_const(&split(&p_minus_one, self.limb_bits, self.num_limbs), -1);
+        identity.constrain(circuit, self.limb_bits, self.rc_base, round);
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use itertools::Itertools;
+    use rand_core::OsRng;
+
+    use crate::{circuit::Circuit, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+    type Ff = bn256::Fq;
+
+    fn check_arithmetic(limb_bits: usize, num_limbs: usize) {
+        let field = EmulatedField::<F, Ff>::new(limb_bits, num_limbs, 16);
+        let mut circuit = Circuit::new(16, 1);
+        let a_ext = circuit.ext_val(num_limbs);
+        let b_ext = circuit.ext_val(num_limbs);
+        let a_vars = a_ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let b_vars = b_ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let a = field.range_check(&mut circuit, &a_vars, 0);
+        let b = field.range_check(&mut circuit, &b_vars, 0);
+
+        let sum = field.add(&mut circuit, &a, &b, 0);
+        let diff = field.sub(&mut circuit, &a, &b, 0);
+        let prod = field.mul(&mut circuit, &a, &b, 0);
+        let results = [&sum, &diff, &prod].map(|x| field.canonicalize(&mut circuit, x, 0));
+
+        // (a + b)(a - b) = a^2 - b^2, on unreduced operands
+        let lhs = field.mul(&mut circuit, &sum, &diff, 0);
+        let a_sq = field.mul(&mut circuit, &a, &a, 0);
+        let b_sq = field.mul(&mut circuit, &b, &b, 0);
+        let rhs = field.sub(&mut circuit, &a_sq, &b_sq, 0);
+        field.assert_equal(&mut circuit, &lhs, &rhs, 0);
+
+        let one = field.constant(&mut circuit, Ff::ONE, 0);
+        let a_one = field.mul(&mut circuit, &a, &one, 0);
+        field.assert_equal(&mut circuit, &a_one, &a, 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        let x = Ff::random(OsRng);
+        let y = Ff::random(OsRng);
+        for (e, v) in a_ext.iter().zip_eq(field.to_limbs(x)) {
+            instance.set_ext(*e, v);
+        }
+        for (e, v) in b_ext.iter().zip_eq(field.to_limbs(y)) {
+            instance.set_ext(*e, v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        let values = results.iter().map(|r| instance.cs.get_vars(&r.vars())).collect_vec();
+        for (v, expected) in values.iter().zip_eq([x + y, x - y, x * y]) {
+            assert!(field.value(v) < modulus::<Ff>());
+            assert_eq!(field.to_foreign(v), expected);
+        }
+    }
+
+    #[test]
+    fn arithmetic_64bit_limbs() {
+        check_arithmetic(64, 4);
+    }
+
+    #[test]
+    fn arithmetic_88bit_limbs() {
+        check_arithmetic(88, 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unequal_elements() {
+        let field = EmulatedField::<F, Ff>::new(64, 4, 16);
+        let mut circuit = Circuit::new(16, 1);
+        let a = field.constant(&mut circuit, Ff::ONE, 0);
+        let b = field.constant(&mut circuit, Ff::ONE.double(), 0);
+        field.assert_equal(&mut circuit, &a, &b, 0);
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        instance.execute(0);
+    }
+}
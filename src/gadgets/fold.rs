@@ -0,0 +1,448 @@
+// In-circuit counterpart of Fold::fold, for the augmented circuit verifying a folding step.
+// Public inputs and protostar challenges are folded linearly, and the error term is recomputed from the cross terms.
+// Commitments live on a curve which is generally not native to the circuit, so their folding is delegated to
+// an EC backend: either to a cyclefold circuit, or to grumpkin gadgets if the commitments are native.
+
+use std::marker::PhantomData;
+
+use ff::PrimeField;
+use group::Curve;
+use halo2::halo2curves::{CurveAffine, CurveExt};
+use itertools::Itertools;
+
+use crate::{
+    circuit::{Advice, Circuit, PolyOp},
+    constraint_system::Variable,
+    folding::{encode::{decode_point, encode_point}, shape::Shape},
+    gate::Gatebb,
+    utils::{arith_helper::{j2a, log2_ceil}, field_precomp::FieldUtils},
+};
+
+use super::{
+    ecmul::{eclin_gadget, escalarmul_gadget, EcAffinePoint, Window},
+    nonzero_check::Nonzeros,
+    polynomial::horner_gadget,
+};
+
+/// Computes folded commitments acc + t (inc - acc) in the circuit.
+pub trait FoldEcBackend<'a, F: PrimeField+FieldUtils> {
+    type Point: Clone;
+
+    fn fold_point(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        acc: &Self::Point,
+        inc: &Self::Point,
+        t: Variable,
+        round: usize,
+    ) -> Self::Point;
+}
+
+#[derive(Clone)]
+pub struct ProtostarLhsVar<P: Clone> {
+    pub round_commitments: Vec<P>,
+    pub pubs: Vec<Vec<Variable>>,
+    pub protostar_challenges: Vec<Variable>,
+}
+
+impl<P: Clone> ProtostarLhsVar<P> {
+    pub fn validate_shape(&self, shape: &Shape) {
+        shape.wspec.round_specs.iter().zip_eq(self.pubs.iter())
+            .map(|(rspec, rpubs)| {
+                assert_eq!(rspec.pubs, rpubs.len())
+            }).count();
+
+        assert_eq!(self.pubs.len(), self.round_commitments.len());
+
+        assert_eq!(self.protostar_challenges.len(), log2_ceil(shape.cspec.num_nonlinear_constraints));
+    }
+}
+
+#[derive(Clone)]
+pub struct ProtostarInstanceVar<P: Clone> {
+    pub lhs: ProtostarLhsVar<P>,
+    pub error: Variable,
+}
+
+/// A commitment encoded by 128-bit limbs of its coordinates, see encode_point.
+pub type EncodedPointVar = [Variable; 4];
+
+/// Folding of a commitment to be checked by the cyclefold circuit: acc + t (inc - acc) = res.
+#[derive(Clone, Debug)]
+pub struct DelegatedEcOp {
+    pub acc: EncodedPointVar,
+    pub inc: EncodedPointVar,
+    pub t: Variable,
+    pub res: EncodedPointVar,
+}
+
+/// Backend which does not compute anything in the circuit: folded commitments are given as advice, and operations
+/// are collected to be passed to the cyclefold circuit. Limbs of the results are not range-checked either,
+/// this is done by the cyclefold circuit consuming them.
+pub struct CyclefoldDelegation<F: PrimeField+FieldUtils, C: CurveAffine<ScalarExt = F>> {
+    ops: Vec<DelegatedEcOp>,
+    _marker: PhantomData<C>,
+}
+
+impl<F: PrimeField+FieldUtils, C: CurveAffine<ScalarExt = F>> CyclefoldDelegation<F, C> {
+    pub fn new() -> Self {
+        Self { ops: vec![], _marker: PhantomData }
+    }
+
+    pub fn ops(&self) -> &[DelegatedEcOp] {
+        &self.ops
+    }
+
+    pub fn finish(self) -> Vec<DelegatedEcOp> {
+        self.ops
+    }
+}
+
+impl<'a, F: PrimeField+FieldUtils, C: CurveAffine<ScalarExt = F>> FoldEcBackend<'a, F> for CyclefoldDelegation<F, C> {
+    type Point = EncodedPointVar;
+
+    fn fold_point(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        acc: &EncodedPointVar,
+        inc: &EncodedPointVar,
+        t: Variable,
+        round: usize,
+    ) -> EncodedPointVar {
+        let args = acc.iter().chain(inc.iter()).cloned().chain([t]).collect_vec();
+        let res = circuit.advice(round, Advice::new(9, 4, |args: &[F], _| {
+            let acc: C = decode_point(&args[..4]);
+            let inc: C = decode_point(&args[4..8]);
+            let acc = acc.to_curve();
+            encode_point(((inc.to_curve() - acc) * args[8] + acc).to_affine())
+        }), args);
+        let res = [res[0], res[1], res[2], res[3]];
+        self.ops.push(DelegatedEcOp { acc: *acc, inc: *inc, t, res });
+        res
+    }
+}
+
+/// Backend for commitments on a curve with base field F (i.e. grumpkin for bn254), using eclin_gadget and scalar
+/// multiplication. The challenge is multiplied as an integer and must be smaller than B^num_limbs, f.e. a challenge
+/// truncated to 128 bits with num_limbs_for_bits(B, 128) limbs. Points must be nonzero, and so must be the difference
+/// of commitments, so the backend is incomplete for equal commitments.
+/// Offset points (a, b) are the same as for escalarmul_gadget, and must satisfy b + (1+B+...+B^{num_limbs-1}) a = 0.
+pub struct NativeEcBackend<'n, F: PrimeField+FieldUtils, C: CurveExt<Base = F>> {
+    window: Window,
+    num_limbs: usize,
+    a: EcAffinePoint<F, C>,
+    b: EcAffinePoint<F, C>,
+    nonzeros: &'n mut Nonzeros,
+}
+
+impl<'n, F: PrimeField+FieldUtils, C: CurveExt<Base = F>> NativeEcBackend<'n, F, C> {
+    pub fn new(window: Window, num_limbs: usize, a: EcAffinePoint<F, C>, b: EcAffinePoint<F, C>, nonzeros: &'n mut Nonzeros) -> Self {
+        Self { window, num_limbs, a, b, nonzeros }
+    }
+
+    /// Allocates pt1 + sign pt2 as advice, only checking that it lies on the curve.
+    fn add<'a>(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        pt1: EcAffinePoint<F, C>,
+        pt2: EcAffinePoint<F, C>,
+        sign: F,
+        round: usize,
+    ) -> EcAffinePoint<F, C> {
+        let coords = circuit.advice(round, Advice::new(4, 2, move |args: &[F], _| {
+            let pt1 = C::new_jacobian(args[0], args[1], F::ONE).unwrap();
+            let pt2 = C::new_jacobian(args[2], sign * args[3], F::ONE).unwrap();
+            let (x, y) = j2a((pt1 + pt2).jacobian_coordinates());
+            vec![x, y]
+        }), vec![pt1.x, pt1.y, pt2.x, pt2.y]);
+        EcAffinePoint::new(circuit, coords[0], coords[1])
+    }
+}
+
+impl<'a, 'n, F: PrimeField+FieldUtils, C: CurveExt<Base = F>> FoldEcBackend<'a, F> for NativeEcBackend<'n, F, C> {
+    type Point = EcAffinePoint<F, C>;
+
+    fn fold_point(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        acc: &EcAffinePoint<F, C>,
+        inc: &EcAffinePoint<F, C>,
+        t: Variable,
+        round: usize,
+    ) -> EcAffinePoint<F, C> {
+        // acc + diff = inc
+        let diff = self.add(circuit, *inc, *acc, -F::ONE, round);
+        eclin_gadget(circuit, *acc, diff, *inc, self.nonzeros, round);
+
+        let prod = escalarmul_gadget(circuit, t, diff, &self.window, self.num_limbs, round, self.a, self.b, self.nonzeros);
+
+        let res = self.add(circuit, *acc, prod, F::ONE, round);
+        eclin_gadget(circuit, *acc, prod, res, self.nonzeros, round);
+        res
+    }
+}
+
+/// Computes a + t (b - a) for all pairs in a single operation.
+fn lerp_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    a: &[Variable],
+    b: &[Variable],
+    t: Variable,
+    round: usize,
+) -> Vec<Variable> {
+    let n = a.len();
+    if n == 0 {
+        return vec![]
+    }
+    let args = a.iter().chain(b.iter()).cloned().chain([t]).collect_vec();
+    circuit.apply(round, PolyOp::new(2, 2*n + 1, n, move |args, _| {
+        let t = args[2*n];
+        (0..n).map(|i| args[i] + t * (args[n + i] - args[i])).collect()
+    }), args)
+}
+
+/// Folds the incoming instance into the accumulated one with the challenge t, see Fold::fold.
+/// The error term is (1-t) E_acc + t E_inc - t(1-t) ev(cross_terms, t).
+pub fn fold_gadget<'a, F: PrimeField+FieldUtils, B: FoldEcBackend<'a, F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    backend: &mut B,
+    acc: &ProtostarInstanceVar<B::Point>,
+    inc: &ProtostarInstanceVar<B::Point>,
+    cross_terms: &[Variable],
+    t: Variable,
+    shape: &Shape,
+    round: usize,
+) -> ProtostarInstanceVar<B::Point> {
+    acc.lhs.validate_shape(shape);
+    inc.lhs.validate_shape(shape);
+    assert_eq!(cross_terms.len(), shape.cspec.max_degree + acc.lhs.protostar_challenges.len() - 1);
+
+    let round_commitments = acc.lhs.round_commitments.iter().zip_eq(inc.lhs.round_commitments.iter())
+        .map(|(a, b)| backend.fold_point(circuit, a, b, t, round))
+        .collect();
+
+    // Public inputs and challenges are folded together.
+    let flatten = |lhs: &ProtostarLhsVar<B::Point>| {
+        lhs.pubs.iter().flatten().chain(lhs.protostar_challenges.iter()).cloned().collect_vec()
+    };
+    let mut folded = lerp_gadget(circuit, &flatten(&acc.lhs), &flatten(&inc.lhs), t, round).into_iter();
+    let pubs = acc.lhs.pubs.iter().map(|rpubs| folded.by_ref().take(rpubs.len()).collect()).collect();
+    let protostar_challenges = folded.collect();
+
+    let v = horner_gadget(circuit, cross_terms, t, round);
+    let error = circuit.apply(round, PolyOp::new(3, 4, 1, |args, _| {
+        let (e_acc, e_inc, v, t) = (args[0], args[1], args[2], args[3]);
+        let nt = F::ONE - t;
+        vec![nt * e_acc + t * e_inc - t * nt * v]
+    }), vec![acc.error, inc.error, v, t])[0];
+
+    ProtostarInstanceVar { lhs: ProtostarLhsVar { round_commitments, pubs, protostar_challenges }, error }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use group::{prime::PrimeCurveAffine, Curve, Group};
+    use halo2::halo2curves::{bn256, grumpkin};
+    use rand_core::{OsRng, RngCore};
+
+    use crate::{
+        circuit::ExternalValue,
+        constraint_system::{ConstrSpec, RoundWitnessSpec, WitnessSpec},
+        folding::shape::{Fold, ProtostarInstance, ProtostarLhs},
+        gadgets::{ecmul::{num_limbs_for_bits, offset_point}, input::input},
+        utils::arith_helper::{from_biguint, to_biguint},
+    };
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    /// Two rounds with 2 and 1 public inputs, 5 nonlinear constraints of degree 3.
+    fn shape() -> Shape {
+        Shape {
+            wspec: WitnessSpec {
+                round_specs: vec![RoundWitnessSpec { pubs: 2, privs: 4 }, RoundWitnessSpec { pubs: 1, privs: 4 }],
+                num_ints: 0,
+                num_exts: 0,
+            },
+            cspec: ConstrSpec { num_lin_constraints: 0, num_nonlinear_constraints: 5, max_degree: 3 },
+        }
+    }
+
+    /// Allocates field elements of an instance as inputs, leaving commitments to the caller.
+    fn alloc_fields<'a, P: Clone>(
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        shape: &Shape,
+        round_commitments: Vec<P>,
+    ) -> (ProtostarInstanceVar<P>, Vec<ExternalValue<F>>) {
+        let num_pubs = shape.wspec.round_specs.iter().map(|r| r.pubs).collect_vec();
+        let num_challenges = log2_ceil(shape.cspec.num_nonlinear_constraints);
+        let ext = circuit.ext_val(num_pubs.iter().sum::<usize>() + num_challenges + 1);
+        let vars = ext.iter().map(|e| input(circuit, *e, 0)).collect_vec();
+        let mut vars_iter = vars.into_iter();
+        let pubs = num_pubs.iter().map(|n| vars_iter.by_ref().take(*n).collect()).collect();
+        let protostar_challenges = vars_iter.by_ref().take(num_challenges).collect();
+        let error = vars_iter.next().unwrap();
+        (ProtostarInstanceVar { lhs: ProtostarLhsVar { round_commitments, pubs, protostar_challenges }, error }, ext)
+    }
+
+    /// Allocates an instance with commitments encoded by limbs.
+    fn alloc_encoded<'a>(
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        shape: &Shape,
+    ) -> (ProtostarInstanceVar<EncodedPointVar>, Vec<ExternalValue<F>>, Vec<ExternalValue<F>>) {
+        let ext = circuit.ext_val(4 * shape.wspec.round_specs.len());
+        let vars = ext.iter().map(|e| input(circuit, *e, 0)).collect_vec();
+        let commitments = vars.chunks(4).map(|v| [v[0], v[1], v[2], v[3]]).collect_vec();
+        let (inst, field_ext) = alloc_fields(circuit, shape, commitments);
+        (inst, ext, field_ext)
+    }
+
+    fn alloc_point<'a, C: CurveExt<Base = F>>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>) -> (EcAffinePoint<F, C>, Vec<ExternalValue<F>>) {
+        let ext = circuit.ext_val(2);
+        let x = input(circuit, ext[0], 0);
+        let y = input(circuit, ext[1], 0);
+        (EcAffinePoint::new(circuit, x, y), ext)
+    }
+
+    fn random_instance<C: CurveAffine<ScalarExt = F>>(shape: &Shape, commitments: Vec<C>) -> ProtostarInstance<F, C> {
+        let pubs = shape.wspec.round_specs.iter().map(|r| (0..r.pubs).map(|_| F::random(OsRng)).collect()).collect();
+        let num_challenges = log2_ceil(shape.cspec.num_nonlinear_constraints);
+        let protostar_challenges = (0..num_challenges).map(|_| F::random(OsRng)).collect();
+        ProtostarInstance { lhs: ProtostarLhs { round_commitments: commitments, pubs, protostar_challenges }, error: F::random(OsRng) }
+    }
+
+    fn field_values<C: CurveAffine<ScalarExt = F>>(inst: &ProtostarInstance<F, C>) -> Vec<F> {
+        inst.lhs.pubs.iter().flatten().chain(inst.lhs.protostar_challenges.iter()).cloned().chain([inst.error]).collect()
+    }
+
+    fn field_vars<P: Clone>(inst: &ProtostarInstanceVar<P>) -> Vec<Variable> {
+        inst.lhs.pubs.iter().flatten().chain(inst.lhs.protostar_challenges.iter()).cloned().chain([inst.error]).collect()
+    }
+
+    #[test]
+    fn cyclefold_delegation() {
+        type C = bn256::G1Affine;
+        let shape = shape();
+        let num_rounds = shape.wspec.round_specs.len();
+        let num_cross_terms = shape.cspec.max_degree + log2_ceil(shape.cspec.num_nonlinear_constraints) - 1;
+
+        let mut circuit = Circuit::new(3, 1);
+        let (acc, acc_cm_ext, acc_ext) = alloc_encoded(&mut circuit, &shape);
+        let (inc, inc_cm_ext, inc_ext) = alloc_encoded(&mut circuit, &shape);
+        let cross_ext = circuit.ext_val(num_cross_terms);
+        let cross_terms = cross_ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let t_ext = circuit.ext_val(1)[0];
+        let t = input(&mut circuit, t_ext, 0);
+
+        let mut backend = CyclefoldDelegation::<F, C>::new();
+        let folded = fold_gadget(&mut circuit, &mut backend, &acc, &inc, &cross_terms, t, &shape, 0);
+        let ops = backend.finish();
+        assert_eq!(ops.len(), num_rounds);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        // The first commitment of the accumulator is zero, as it is for the initial accumulator.
+        let mut acc_cms = (0..num_rounds).map(|_| bn256::G1::random(OsRng).to_affine()).collect_vec();
+        acc_cms[0] = C::identity();
+        let native_acc = random_instance(&shape, acc_cms);
+        let native_inc = random_instance(&shape, (0..num_rounds).map(|_| bn256::G1::random(OsRng).to_affine()).collect());
+        let native_cross_terms = (0..num_cross_terms).map(|_| F::random(OsRng)).collect_vec();
+        let native_t = F::random(OsRng);
+
+        for (native, cm_ext, ext) in [(&native_acc, &acc_cm_ext, &acc_ext), (&native_inc, &inc_cm_ext, &inc_ext)] {
+            let cms = native.lhs.round_commitments.iter().flat_map(|c| encode_point::<F, C>(*c)).collect_vec();
+            for (e, v) in cm_ext.iter().zip_eq(cms).chain(ext.iter().zip_eq(field_values(native))) {
+                instance.set_ext(*e, v);
+            }
+        }
+        for (e, v) in cross_ext.iter().zip_eq(native_cross_terms.iter()) {
+            instance.set_ext(*e, *v);
+        }
+        instance.set_ext(t_ext, native_t);
+
+        instance.execute(0);
+        instance.valid_witness();
+
+        let mut fold = Fold::new(native_acc, native_inc, native_cross_terms, shape.clone());
+        fold.challenge(native_t);
+        let expected = fold.fold();
+
+        assert_eq!(instance.cs.get_vars(&field_vars(&folded)), field_values(&expected));
+        for (cm, expected) in folded.lhs.round_commitments.iter().zip_eq(expected.lhs.round_commitments.iter()) {
+            assert_eq!(decode_point::<F, C>(&instance.cs.get_vars(cm)), *expected);
+        }
+    }
+
+    #[test]
+    fn native_grumpkin() {
+        type C = grumpkin::G1;
+        let shape = shape();
+        let num_rounds = shape.wspec.round_specs.len();
+        let num_cross_terms = shape.cspec.max_degree + log2_ceil(shape.cspec.num_nonlinear_constraints) - 1;
+        let window = Window::from_base(8, false).unwrap();
+        let num_limbs = num_limbs_for_bits(window.base(), 128);
+
+        let mut circuit = Circuit::new(8, 1);
+        let (offset_a, offset_a_ext) = alloc_point::<C>(&mut circuit);
+        let (offset_b, offset_b_ext) = alloc_point::<C>(&mut circuit);
+        let mut cm_exts = vec![];
+        let mut instances = vec![];
+        for _ in 0..2 {
+            let (cms, ext): (Vec<_>, Vec<_>) = (0..num_rounds).map(|_| alloc_point::<C>(&mut circuit)).unzip();
+            let (inst, field_ext) = alloc_fields(&mut circuit, &shape, cms);
+            cm_exts.push(ext);
+            instances.push((inst, field_ext));
+        }
+        let cross_ext = circuit.ext_val(num_cross_terms);
+        let cross_terms = cross_ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let t_ext = circuit.ext_val(1)[0];
+        let t = input(&mut circuit, t_ext, 0);
+
+        let mut nonzeros = Nonzeros::new(7);
+        let mut backend = NativeEcBackend::new(window.clone(), num_limbs, offset_a, offset_b, &mut nonzeros);
+        let folded = fold_gadget(&mut circuit, &mut backend, &instances[0].0, &instances[1].0, &cross_terms, t, &shape, 0);
+        nonzeros.finalize(&mut circuit);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        let mut set_point = |ext: &[ExternalValue<F>], pt: C| {
+            let (x, y) = j2a(pt.jacobian_coordinates());
+            instance.set_ext(ext[0], x);
+            instance.set_ext(ext[1], y);
+        };
+        let a = C::random(OsRng);
+        set_point(&offset_a_ext, a);
+        set_point(&offset_b_ext, offset_point(a, window.base(), num_limbs));
+        let native_cms = cm_exts.iter().map(|exts| exts.iter().map(|ext| {
+            let pt = C::random(OsRng);
+            set_point(ext, pt);
+            pt
+        }).collect_vec()).collect_vec();
+        let native_t = F::from_u128(((OsRng.next_u64() as u128) << 64) + OsRng.next_u64() as u128);
+        instance.set_ext(t_ext, native_t);
+        for (_, ext) in instances.iter() {
+            for e in ext {
+                instance.set_ext(*e, F::random(OsRng));
+            }
+        }
+        for e in cross_ext.iter() {
+            instance.set_ext(*e, F::random(OsRng));
+        }
+
+        instance.execute(0);
+        instance.valid_witness();
+
+        let scalar = from_biguint::<<C as CurveExt>::ScalarExt>(&to_biguint(native_t));
+        for ((cm, acc), inc) in folded.lhs.round_commitments.iter().zip_eq(native_cms[0].iter()).zip_eq(native_cms[1].iter()) {
+            let (x, y) = j2a((*acc + (*inc - *acc) * scalar).jacobian_coordinates());
+            assert_eq!(instance.cs.get_vars(&[cm.x, cm.y]), vec![x, y]);
+        }
+        // Every commitment costs 663 (the scalar multiplication and two additions), plus folding of the field
+        // elements, the error term and the nonzero check.
+        assert_eq!(instance.cs.wtns[0].privs.len(), 1395);
+    }
+}
@@ -2,19 +2,17 @@
 
 use std::marker::PhantomData;
 use ff::PrimeField;
-use halo2::halo2curves::bn256;
 use itertools::Itertools;
 use num_bigint::BigUint;
 use crate::{constraint_system::Variable, utils::{field_precomp::FieldUtils, arith_helper::modulus}, gate::Gatebb, circuit::Circuit, folding::poseidon::Poseidon};
 
 use super::{rangecheck_common::{VarRange, from_limbs}, poseidon::poseidon_gadget};
 
-type F = bn256::Fr;
 pub struct RangeAwareHasher<F: PrimeField+FieldUtils> {
     values: Vec<VarRange<F>>,
 }
 
-impl RangeAwareHasher<F> {
+impl<F: PrimeField+FieldUtils> RangeAwareHasher<F> {
     pub fn new() -> Self {
         Self {values: vec![]}
     }
@@ -23,7 +21,7 @@ impl RangeAwareHasher<F> {
         self.values.push(v)
     }
 
-    pub fn hash<'a>(self, circuit: &mut Circuit<'a, F, Gatebb<'a,F>>, cfg: &'a Poseidon, rate: usize, round: usize) -> Variable {
+    pub fn hash<'a>(self, circuit: &mut Circuit<'a, F, Gatebb<'a,F>>, cfg: &'a Poseidon<F>, rate: usize, round: usize) -> Variable {
         let mut limbs = vec![];
         let mut total_range = BigUint::from(1u8);
         let mut combined = vec![];
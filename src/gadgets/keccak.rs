@@ -0,0 +1,214 @@
+// Keccak-f[1600] permutation and Keccak-256 sponge (the variant used by Ethereum, with 0x01 padding).
+//
+// Lanes are UInt64, lane (x, y) of the state is stored at index x + 5y. Every round witnesses the column
+// parities of θ and the outputs of χ, with ι folded into χ of lane (0, 0). If the circuit allows degree 9,
+// θ is folded into χ as well: every input of χ is a xor of three witnessed values, so the outputs of θ are
+// never allocated, which halves the witness.
+
+use ff::PrimeField;
+use itertools::Itertools;
+
+use crate::{circuit::Circuit, gate::Gatebb, utils::field_precomp::FieldUtils};
+
+use super::uint::{UInt64, UInt8, UInt, xor_poly};
+
+pub const RATE: usize = 136;
+
+const RC: [u64; 24] = [
+    0x0000000000000001, 0x0000000000008082, 0x800000000000808a, 0x8000000080008000,
+    0x000000000000808b, 0x0000000080000001, 0x8000000080008081, 0x8000000000008009,
+    0x000000000000008a, 0x0000000000000088, 0x0000000080008009, 0x000000008000000a,
+    0x000000008000808b, 0x800000000000008b, 0x8000000000008089, 0x8000000000008003,
+    0x8000000000008002, 0x8000000000000080, 0x000000000000800a, 0x800000008000000a,
+    0x8000000080008081, 0x8000000000008080, 0x0000000080000001, 0x8000000080008008,
+];
+
+/// Rotation offsets of ρ, indexed by [x][y].
+const ROTATIONS: [[usize; 5]; 5] = [
+    [0, 36, 3, 41, 18],
+    [1, 44, 10, 45, 2],
+    [62, 6, 43, 15, 61],
+    [28, 55, 25, 21, 56],
+    [27, 20, 39, 8, 14],
+];
+
+/// Applies the permutation to 25 lanes.
+pub fn keccak_f<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    state: &[UInt64],
+    round: usize,
+) -> Vec<UInt64> {
+    assert_eq!(state.len(), 25);
+    assert!(circuit.max_degree() >= 3, "Keccak gadget requires degree at least 3.");
+    let fused = circuit.max_degree() >= 9;
+
+    let mut a = state.to_vec();
+    for rc in RC {
+        // θ: A[x, y] ^ C[x-1] ^ rot(C[x+1], 1), kept as three operands
+        let c = (0..5).map(|x| {
+            UInt64::xor_many(circuit, &(0..5).map(|y| &a[x + 5*y]).collect_vec(), round)
+        }).collect_vec();
+
+        // ρ and π: B[y, 2x + 3y] = rot(A[x, y], r[x][y])
+        let mut b = vec![vec![]; 25];
+        for (x, y) in (0..5).cartesian_product(0..5) {
+            let r = ROTATIONS[x][y];
+            b[y + 5*((2*x + 3*y) % 5)] = vec![
+                a[x + 5*y].rotate_left(r),
+                c[(x + 4) % 5].rotate_left(r),
+                c[(x + 1) % 5].rotate_left(r + 1),
+            ];
+        }
+        if !fused {
+            b = b.iter().map(|ops| vec![UInt64::xor_many(circuit, &ops.iter().collect_vec(), round)]).collect();
+        }
+
+        // χ and ι
+        let k = b[0].len();
+        a = (0..25).map(|i| {
+            let (x, y) = (i % 5, i / 5);
+            let mut ops = b[i].iter()
+                .chain(b[(x + 1) % 5 + 5*y].iter())
+                .chain(b[(x + 2) % 5 + 5*y].iter())
+                .cloned().collect_vec();
+            if i == 0 {
+                ops.push(UInt64::constant(rc));
+            }
+            UInt64::bitwise(circuit, &ops.iter().collect_vec(), 3*k, move |v| {
+                let (u0, u1, u2) = (xor_poly(&v[..k]), xor_poly(&v[k..2*k]), xor_poly(&v[2*k..3*k]));
+                let t = (F::ONE - u1) * u2;
+                let ret = u0 + t - (u0 * t).double();
+                if v.len() > 3*k {
+                    xor_poly(&[ret, v[3*k]])
+                } else {
+                    ret
+                }
+            }, round)
+        }).collect();
+    }
+    a
+}
+
+/// Padding of a message of a given length: 0x01, zeros, and 0x80 in the last byte of the block.
+pub fn keccak256_padding(len: usize) -> Vec<u8> {
+    let n = RATE - len % RATE;
+    let mut ret = vec![0; n];
+    ret[0] |= 0x01;
+    ret[n-1] |= 0x80;
+    ret
+}
+
+/// Little-endian lane from 8 bytes.
+fn lane_from_bytes(bytes: &[UInt8]) -> UInt64 {
+    UInt::from_bits(bytes.iter().flat_map(|b| b.bits().iter().cloned()).collect())
+}
+
+/// Keccak-256 of a message of fixed length. Returns 32 bytes of the digest.
+pub fn keccak256<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    message: &[UInt8],
+    round: usize,
+) -> Vec<UInt8> {
+    let mut bytes = message.to_vec();
+    bytes.extend(keccak256_padding(message.len()).into_iter().map(|b| UInt8::constant(b as u64)));
+
+    let mut state: Option<Vec<UInt64>> = None;
+    for block in bytes.chunks(RATE) {
+        let lanes = block.chunks(8).map(lane_from_bytes).collect_vec();
+        let input = match state {
+            // initial state is zero, so the first block is absorbed for free
+            None => lanes.into_iter().chain((0..25 - RATE/8).map(|_| UInt64::constant(0))).collect_vec(),
+            Some(s) => lanes.iter().zip(s.iter()).map(|(l, x)| UInt64::xor(circuit, l, x, round))
+                .chain(s[RATE/8..].iter().cloned()).collect_vec(),
+        };
+        state = Some(keccak_f(circuit, &input, round));
+    }
+
+    state.unwrap()[..4].iter()
+        .flat_map(|lane| lane.bits().chunks(8).map(|bits| UInt8::from_bits(bits.to_vec())).collect_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2::halo2curves::bn256;
+    use rand_core::{OsRng, RngCore};
+    use tiny_keccak::{Hasher, Keccak, keccakf};
+
+    use crate::{circuit::Circuit, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    fn check_permutation(max_degree: usize) {
+        let mut circuit = Circuit::new(max_degree, 1);
+        let ext = circuit.ext_val(25);
+        let state = ext.iter().map(|e| {
+            let var = input(&mut circuit, *e, 0);
+            UInt64::from_var(&mut circuit, var, 0)
+        }).collect_vec();
+        let out = keccak_f(&mut circuit, &state, 0);
+        let out = out.iter().map(|lane| lane.pack(&mut circuit, 0)).collect_vec();
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let mut lanes = [0u64; 25];
+        for (e, lane) in ext.iter().zip_eq(lanes.iter_mut()) {
+            *lane = OsRng.next_u64();
+            instance.set_ext(*e, F::from(*lane));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        keccakf(&mut lanes);
+        for (var, lane) in out.iter().zip_eq(lanes) {
+            assert_eq!(instance.cs.getvar(*var), F::from(lane));
+        }
+    }
+
+    fn check_keccak256(len: usize) {
+        let mut circuit = Circuit::new(9, 1);
+        let ext = circuit.ext_val(len);
+        let message = ext.iter().map(|e| {
+            let var = input(&mut circuit, *e, 0);
+            UInt8::from_var(&mut circuit, var, 0)
+        }).collect_vec();
+        let digest = keccak256(&mut circuit, &message, 0);
+        let digest = digest.iter().map(|b| b.pack(&mut circuit, 0)).collect_vec();
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let mut data = vec![0u8; len];
+        OsRng.fill_bytes(&mut data);
+        for (e, b) in ext.iter().zip_eq(data.iter()) {
+            instance.set_ext(*e, F::from(*b as u64));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        let mut expected = [0u8; 32];
+        let mut hasher = Keccak::v256();
+        hasher.update(&data);
+        hasher.finalize(&mut expected);
+        for (var, byte) in digest.iter().zip_eq(expected) {
+            assert_eq!(instance.cs.getvar(*var), F::from(byte as u64));
+        }
+    }
+
+    #[test]
+    fn permutation() {
+        check_permutation(3);
+    }
+
+    #[test]
+    fn permutation_fused() {
+        check_permutation(9);
+    }
+
+    #[test]
+    fn sponge() {
+        check_keccak256(0);
+        check_keccak256(140);
+    }
+}
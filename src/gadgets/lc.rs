@@ -26,7 +26,7 @@ pub fn sum_arr<F: PrimeField+FieldUtils>(args: &[F]) -> F {
 pub fn lc_constr<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, coeffs:&[F], vars: &[Variable]) -> () {
     assert_eq!(coeffs.len(), vars.len());
     let l = vars.len();
-    let gate = Gatebb::new(1, l, 1, Rc::new(|args, coeffs|{vec![inner_prod(coeffs, args)]}), vec![]); // NO MOVE HERE!!
+    let gate = Gatebb::new(1, l, 1, Rc::new(|args, coeffs|{vec![inner_prod(coeffs, args)]}), coeffs.to_vec());
     circuit.constrain(vars, gate);
 }
 
@@ -41,7 +41,8 @@ pub fn qc<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a,
 pub fn lc<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, coeffs:&[F], vars: &[Variable], round: usize) -> Variable {
     assert_eq!(coeffs.len(), vars.len());
     let l = vars.len();
-    let poly = PolyOp::new(1, l, 1, |args, coeffs|{vec![inner_prod(coeffs, args)]});
+    let coeffs = coeffs.to_vec();
+    let poly = PolyOp::new(1, l, 1, move |args, _|{vec![inner_prod(&coeffs, args)]});
     circuit.apply(round, poly, vars.to_vec())[0]
 }
 
@@ -49,4 +50,59 @@ pub fn sum_gadget<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gat
     let l = vars.len();
     let poly = PolyOp::new(1, l, 1, |arr, _|vec![sum_arr(arr)]);
     circuit.apply(round, poly, vars.to_vec())[0]
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+
+    use crate::gadgets::input::input;
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    fn lc_constr_circuit(values: [u64; 3]) {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(3);
+        let vars = ext.iter().map(|&e| input(&mut circuit, e, 0)).collect_vec();
+        lc_constr(&mut circuit, &[F::from(2), F::from(3), -F::ONE], &vars);
+        let constructed = circuit.finalize();
+
+        let mut instance = constructed.spawn();
+        for (&e, v) in ext.iter().zip_eq(values) {
+            instance.set_ext(e, F::from(v));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+    }
+
+    #[test]
+    fn lc_uses_coefficients() {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(3);
+        let vars = ext.iter().map(|&e| input(&mut circuit, e, 0)).collect_vec();
+        let res = lc(&mut circuit, &[F::from(2), F::from(3), F::from(5)], &vars, 0);
+        let constructed = circuit.finalize();
+
+        let mut instance = constructed.spawn();
+        for (&e, v) in ext.iter().zip_eq([7, 11, 13]) {
+            instance.set_ext(e, F::from(v));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+        assert_eq!(instance.cs.getvar(res), F::from(2*7 + 3*11 + 5*13));
+    }
+
+    #[test]
+    fn lc_constr_uses_coefficients() {
+        lc_constr_circuit([7, 11, 2*7 + 3*11]);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not satisfied")]
+    fn lc_constr_rejects_unit_combination() {
+        lc_constr_circuit([7, 11, 7 + 11]);
+    }
+}
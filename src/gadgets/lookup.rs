@@ -5,16 +5,16 @@
 // 2. Table right now is implemented as priveleged subset of variables. Considering it is the same for all
 // step instances, it is not, actually, getting folded. This should be made a primitive.
 
-use std::{iter::{once}, rc::Rc, collections::HashMap};
+use std::{iter::{once}, rc::Rc, collections::HashMap, marker::PhantomData};
 
 use ff::{PrimeField, BatchInvert};
 use itertools::Itertools;
 use num_bigint::BigUint;
 
 use crate::{constraint_system::Variable, utils::field_precomp::FieldUtils,
-    circuit::{Circuit, ExternalValue, Advice},
+    circuit::{Circuit, ExternalValue, Advice, AdviceError, PolyOp},
     gate::Gatebb,
-    gadgets::{lc::{sum_gadget, inner_prod, sum_arr}, input::input, arith::eq_gadget}};
+    gadgets::{lc::{sum_gadget, inner_prod, sum_arr}, input::input, arith::{eq_gadget, read_const_gadget}}};
 
 /// Outputs a product of vector elements and products skipping a single element.
 pub fn montgomery<F: PrimeField+FieldUtils>(v: &[F]) -> (F, Vec<F>) {
@@ -99,7 +99,7 @@ pub fn fracsum_flat_constrain<'a, 'c, F: PrimeField+FieldUtils>(
 
 /// Gadget which returns the sum of inverses of an array, shifted by a challenge.
 /// Assumes that array length is divisible by rate.
-/// Unsound if one of the inverses is undefined.
+/// Unsound if one of the inverses is undefined, the advice fails with DivisionByZero in this case.
 /// Rate - amount of values processed in a batch. Deg = rate+1
 pub fn invsum_gadget<'a, F: PrimeField+FieldUtils>(
     circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
@@ -113,10 +113,13 @@ pub fn invsum_gadget<'a, F: PrimeField+FieldUtils>(
         assert!(l%rate == 0);
         let mut vals = vals;
         let mut chunk;
-        let advice = Advice::new(l+1, l/rate, move |args: &[F], _|{
+        let advice = Advice::new_fallible(l+1, l/rate, move |args: &[F], _|{
             let (args, c) = args.split_at(l);
             let c = c[0];
             let mut inv = args.iter().map(|x|*x-c).collect_vec();
+            if inv.iter().any(|x| x.is_zero_vartime()) {
+                return Err(AdviceError::DivisionByZero)
+            }
             inv.batch_invert();
             let mut ret = vec![];
             let mut inv : &[F] = &inv;
@@ -125,7 +128,7 @@ pub fn invsum_gadget<'a, F: PrimeField+FieldUtils>(
                 (chunk, inv) = inv.split_at(rate);
                 ret.push(sum_arr(chunk));
             }
-            ret
+            Ok(ret)
         });
 
         let mut args = vals.to_vec();
@@ -143,7 +146,7 @@ pub fn invsum_gadget<'a, F: PrimeField+FieldUtils>(
 
 /// Gadget which returns the sum of fractions of an array, shifted by a challenge.
 /// Assumes that array length is divisible by rate, pad otherwise.
-/// Unsound if one of the inverses is undefined.
+/// Unsound if one of the inverses is undefined, the advice fails with DivisionByZero in this case.
 /// Rate - amount of values processed in a batch. Deg = rate+1
 pub fn fracsum_gadget<'a,'c, F: PrimeField+FieldUtils>(
     circuit: &mut Circuit<'c, F, Gatebb<'c, F>>,
@@ -162,10 +165,13 @@ pub fn fracsum_gadget<'a,'c, F: PrimeField+FieldUtils>(
         let captured_dens = dens.to_vec();
         let mut num_chunk;
         let mut den_chunk;
-        let advice = Advice::<'c, F>::new(l+1, l/rate, move |args: &[F], _|{
+        let advice = Advice::<'c, F>::new_fallible(l+1, l/rate, move |args: &[F], _|{
             let (nums, c) = args.split_at(l);
             let c = c[0];
             let mut inv = captured_dens.iter().map(|x|*x-c).collect_vec();
+            if inv.iter().any(|x| x.is_zero_vartime()) {
+                return Err(AdviceError::DivisionByZero)
+            }
             inv.batch_invert();
             let mut ret = vec![];
             let mut inv : &[F] = &inv;
@@ -177,7 +183,7 @@ pub fn fracsum_gadget<'a,'c, F: PrimeField+FieldUtils>(
                 (num_chunk, nums) = nums.split_at(rate);
                 ret.push(inner_prod(inv_chunk, num_chunk));
             }
-            ret
+            Ok(ret)
         });
 
         let args = nums.iter().map(|x|*x).chain(once(challenge)).collect();
@@ -191,6 +197,69 @@ pub fn fracsum_gadget<'a,'c, F: PrimeField+FieldUtils>(
 
     sum_gadget(circuit, &batches, round)        
     }
+
+/// Parses input as `a, c, nums[0], ... nums[k-1], dens[0], ... dens[k-1]` and returns F::ZERO if a == \sum nums[i]/(dens[i]-c) or one of denominators is F::ZERO itself
+pub fn sum_of_fractions_var<F:PrimeField+FieldUtils> (args: &[F], k: usize) -> F {
+    let (tmp, rest) = args.split_at(2);
+    assert_eq!(rest.len(), 2*k);
+    let (nums, dens) = rest.split_at(k);
+    sum_of_fractions_with_nums(&[tmp, nums].concat(), dens, k)
+}
+
+/// Constrains res to be sum of fractions, in which both numerators and denominators are variables.
+/// Degree of the constraint is nums.len() + 1.
+pub fn fracsum_var_flat_constrain<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    nums: &[Variable],
+    dens: &[Variable],
+    res: Variable,
+    challenge: Variable,
+) -> () {
+    assert!(dens.len() == nums.len() && dens.len() > 0);
+    let args = [res, challenge].iter().chain(nums.iter()).chain(dens.iter()).map(|x|*x).collect_vec();
+    let k = dens.len();
+    let gate = Gatebb::new(k+1, args.len(), 1, Rc::new(move |args, _|vec![sum_of_fractions_var(args, k)]), vec![]);
+    circuit.constrain(&args, gate);
+}
+
+/// Gadget which returns the sum of fractions nums[i]/(dens[i]-challenge), where denominators are variables too.
+/// Unlike fracsum_gadget, does not require the length to be divisible by rate: the last batch is just smaller.
+/// Unsound if one of the inverses is undefined, the advice fails with DivisionByZero in this case.
+/// Rate - amount of values processed in a batch. Deg = rate+1
+pub fn fracsum_var_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    nums: &[Variable],
+    dens: &[Variable],
+    challenge: Variable,
+    rate: usize,
+    round: usize,
+) -> Variable {
+    assert!(rate > 0);
+    assert!(nums.len() == dens.len());
+    let l = nums.len();
+    let num_batches = (l + rate - 1) / rate;
+    let advice = Advice::new_fallible(2*l+1, num_batches, move |args: &[F], _|{
+        let (nums, rest) = args.split_at(l);
+        let (dens, c) = rest.split_at(l);
+        let c = c[0];
+        let mut inv = dens.iter().map(|x|*x-c).collect_vec();
+        if inv.iter().any(|x| x.is_zero_vartime()) {
+            return Err(AdviceError::DivisionByZero)
+        }
+        inv.batch_invert();
+        Ok(inv.chunks(rate).zip(nums.chunks(rate)).map(|(inv_chunk, num_chunk)| inner_prod(inv_chunk, num_chunk)).collect())
+    });
+
+    let args = nums.iter().chain(dens.iter()).map(|x|*x).chain(once(challenge)).collect();
+
+    let batches = circuit.advice(round, advice, args);
+    for (i, (num_chunk, den_chunk)) in nums.chunks(rate).zip(dens.chunks(rate)).enumerate() {
+        fracsum_var_flat_constrain(circuit, num_chunk, den_chunk, batches[i], challenge);
+    }
+
+    sum_gadget(circuit, &batches, round)
+}
+
 /// 
 pub trait Lookup<'a, F: PrimeField+FieldUtils> {
     /// Adds the variable to the list of variables to look up.
@@ -273,6 +342,274 @@ impl<'c, F: PrimeField+FieldUtils> Lookup<'c, F> for StaticLookup<F> {
     }
 }
 
+/// Lookup of tuples of a fixed width. Tuples are compressed by a random linear combination with a separate
+/// challenge, x_0 + g x_1 + g^2 x_2 + ..., so both sides of the log-up are variables of the challenge round.
+pub trait TupleLookup<'a, F: PrimeField+FieldUtils> {
+    /// Adds the tuple to the list of tuples to look up.
+    fn check_tuple(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a,F>>, vars: &[Variable]) -> ();
+    /// Seals the lookup and applies the constraints, see Lookup::finalize. The table must be known in
+    /// table_round, and the challenges are allocated by the circuit, so they become available in challenge_round.
+    fn finalize(
+        self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+        table_round: usize,
+        access_round: usize,
+        challenge_round: usize,
+        rate: usize,
+    ) -> ();
+}
+
+/// Powers g, g^2, ..., g^{width-1} of the compression challenge.
+pub fn compression_powers<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    gamma: Variable,
+    width: usize,
+    round: usize,
+) -> Vec<Variable> {
+    let mut powers = vec![];
+    for i in 1..width {
+        if i == 1 {
+            powers.push(gamma);
+        } else {
+            let prev = powers[i-2];
+            powers.push(circuit.apply(round, PolyOp::new(2, 2, 1, |args, _| vec![args[0]*args[1]]), vec![prev, gamma])[0]);
+        }
+    }
+    powers
+}
+
+/// Compresses a tuple of variables using powers of the challenge. Tuples of width 1 are returned as is.
+pub fn compress_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    tuple: &[Variable],
+    powers: &[Variable],
+    round: usize,
+) -> Variable {
+    assert!(tuple.len() == powers.len() + 1);
+    if powers.is_empty() {
+        return tuple[0]
+    }
+    let w = tuple.len();
+    let args = tuple.iter().chain(powers.iter()).map(|x|*x).collect();
+    circuit.apply(round, PolyOp::new(2, 2*w-1, 1, move |args, _| {
+        let (tuple, powers) = args.split_at(w);
+        vec![tuple[0] + inner_prod(&tuple[1..], powers)]
+    }), args)[0]
+}
+
+/// Advice computing how many times each row of the table is accessed. Arguments are flattened accesses,
+/// followed by flattened table rows if the table is not constant. Fails with NotInTable if an access is missing.
+fn access_counts_advice<'a, F: PrimeField+FieldUtils>(
+    width: usize,
+    num_vars: usize,
+    static_table: Option<Vec<Vec<F>>>,
+    table_len: usize,
+) -> Advice<'a, F> {
+    let num_inputs = width * (num_vars + if static_table.is_some() {0} else {table_len});
+    Advice::new_fallible(num_inputs, table_len, move |args: &[F], _| {
+        let key = |row: &[F]| row.iter().map(|x| BigUint::from_bytes_le(x.to_repr().as_ref())).collect_vec();
+        let (vars, table) = args.split_at(width * num_vars);
+        let rows = match &static_table {
+            Some(t) => t.iter().map(|row| key(row)).collect_vec(),
+            None => table.chunks(width).map(key).collect_vec(),
+        };
+        let mut table_hash = HashMap::new();
+        for (i, row) in rows.into_iter().enumerate().rev() {
+            table_hash.insert(row, i); // duplicate rows are accounted in the first one
+        }
+        let mut ret = vec![0u64; table_len];
+        for var in vars.chunks(width) {
+            let idx = *table_hash.get(&key(var)).ok_or(AdviceError::NotInTable)?;
+            ret[idx] += 1;
+        }
+        Ok(ret.into_iter().map(|x|F::from(x)).collect())
+    })
+}
+
+/// Allocates the log-up challenge and, for tuples of width > 1, the compression challenge, which become
+/// available in challenge_round. Returns the log-up challenge and the powers of the compression challenge.
+fn lookup_challenges<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    width: usize,
+    challenge_round: usize,
+) -> (Variable, Vec<Variable>) {
+    let challenge = circuit.challenge(challenge_round - 1);
+    let powers = if width > 1 {
+        let gamma = circuit.challenge(challenge_round - 1);
+        compression_powers(circuit, gamma, width, challenge_round)
+    } else {
+        vec![]
+    };
+    (challenge, powers)
+}
+
+/// Checks that every compressed access is in the compressed table, with given access counts.
+fn tuple_logup<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a,F>>,
+    vars: &[Variable],
+    table: &[Variable],
+    access_counts: &[Variable],
+    challenge: Variable,
+    rate: usize,
+    round: usize,
+) -> () {
+    let ones = vec![circuit.one(); vars.len()];
+    let lhs = fracsum_var_gadget(circuit, &ones, vars, challenge, rate, round);
+    let rhs = fracsum_var_gadget(circuit, access_counts, table, challenge, rate, round);
+    eq_gadget(circuit, lhs, rhs);
+}
+
+/// Lookup into a constant table with several columns, f.e. (opcode, in1, in2, out).
+pub struct StaticTupleLookup<F: PrimeField+FieldUtils> {
+    vars: Vec<Vec<Variable>>,
+    round: usize,
+    table: Vec<Vec<F>>,
+}
+
+impl<F: PrimeField+FieldUtils> StaticTupleLookup<F> {
+    pub fn new(table: &[Vec<F>]) -> Self {
+        assert!(table.len() > 0, "Lookup table can not be empty.");
+        let width = table[0].len();
+        assert!(width > 0 && table.iter().all(|row| row.len() == width), "All rows of the table must have the same width.");
+        Self{
+            vars: vec![],
+            round: 0,
+            table: table.to_vec(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.table[0].len()
+    }
+}
+
+impl<'c, F: PrimeField+FieldUtils> TupleLookup<'c, F> for StaticTupleLookup<F> {
+    fn check_tuple(&mut self, _circuit: &mut Circuit<'c, F, Gatebb<'c,F>>, vars: &[Variable]) -> () {
+        assert!(vars.len() == self.width(), "Tuple width does not match the table.");
+        for var in vars {
+            if self.round < var.round {
+                self.round = var.round
+            }
+        }
+        self.vars.push(vars.to_vec());
+    }
+
+    fn finalize(
+        self,
+        circuit: &mut Circuit<'c, F, Gatebb<'c,F>>,
+        table_round: usize,
+        access_round: usize,
+        challenge_round: usize,
+        rate: usize,
+    ) -> () {
+        let width = self.width();
+        let Self{vars, round, table} = self;
+
+        assert!(table_round <= access_round);
+        assert!(access_round >= round);
+        assert!(challenge_round > access_round);
+
+        let compute_accesses = access_counts_advice(width, vars.len(), Some(table.clone()), table.len());
+        let access_counts = circuit.advice(access_round, compute_accesses, vars.iter().flatten().map(|x|*x).collect());
+
+        let (challenge, powers) = lookup_challenges(circuit, width, challenge_round);
+
+        let vars = vars.iter().map(|v| compress_gadget(circuit, v, &powers, challenge_round)).collect_vec();
+        // Table rows are constant, so they are combined with the powers directly.
+        let table = table.into_iter().map(|row| {
+            if powers.is_empty() {
+                read_const_gadget(circuit, row[0], challenge_round)
+            } else {
+                circuit.apply(challenge_round, PolyOp::new(1, width-1, 1, move |args, _| {
+                    vec![row[0] + inner_prod(&row[1..], args)]
+                }), powers.clone())[0]
+            }
+        }).collect_vec();
+
+        tuple_logup(circuit, &vars, &table, &access_counts, challenge, rate, challenge_round);
+    }
+}
+
+/// Lookup into a table whose entries are variables, f.e. a read-only memory filled during witness generation.
+/// Rows might be tuples, and the table can be extended until the lookup is finalized.
+pub struct DynamicLookup<F: PrimeField+FieldUtils> {
+    vars: Vec<Vec<Variable>>,
+    table: Vec<Vec<Variable>>,
+    round: usize,
+    table_round: usize,
+    width: usize,
+    _marker: PhantomData<F>,
+}
+
+impl<F: PrimeField+FieldUtils> DynamicLookup<F> {
+    pub fn new(width: usize) -> Self {
+        assert!(width > 0);
+        Self{
+            vars: vec![],
+            table: vec![],
+            round: 0,
+            table_round: 0,
+            width,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Adds a row to the table.
+    pub fn add_row(&mut self, row: &[Variable]) -> () {
+        assert!(row.len() == self.width, "Row width does not match the table.");
+        for var in row {
+            if self.table_round < var.round {
+                self.table_round = var.round
+            }
+        }
+        self.table.push(row.to_vec());
+    }
+}
+
+impl<'c, F: PrimeField+FieldUtils> TupleLookup<'c, F> for DynamicLookup<F> {
+    fn check_tuple(&mut self, _circuit: &mut Circuit<'c, F, Gatebb<'c,F>>, vars: &[Variable]) -> () {
+        assert!(vars.len() == self.width, "Tuple width does not match the table.");
+        for var in vars {
+            if self.round < var.round {
+                self.round = var.round
+            }
+        }
+        self.vars.push(vars.to_vec());
+    }
+
+    fn finalize(
+        self,
+        circuit: &mut Circuit<'c, F, Gatebb<'c,F>>,
+        table_round: usize,
+        access_round: usize,
+        challenge_round: usize,
+        rate: usize,
+    ) -> () {
+        let Self{vars, table, round, table_round: actual_table_round, width, ..} = self;
+
+        assert!(table.len() > 0, "Lookup table can not be empty.");
+        assert!(table_round >= actual_table_round);
+        assert!(table_round <= access_round);
+        assert!(access_round >= round);
+        assert!(challenge_round > access_round);
+
+        let compute_accesses = access_counts_advice(width, vars.len(), None, table.len());
+        let args = vars.iter().chain(table.iter()).flatten().map(|x|*x).collect();
+        let access_counts = circuit.advice(access_round, compute_accesses, args);
+
+        let (challenge, powers) = lookup_challenges(circuit, width, challenge_round);
+
+        let vars = vars.iter().map(|v| compress_gadget(circuit, v, &powers, challenge_round)).collect_vec();
+        let table = table.iter().map(|v| compress_gadget(circuit, v, &powers, challenge_round)).collect_vec();
+
+        tuple_logup(circuit, &vars, &table, &access_counts, challenge, rate, challenge_round);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -585,6 +922,188 @@ mod test {
             instance.valid_witness();
             assert_eq!(result, instance.cs.getvar(result_variable));
         }
+        #[test]
+        fn zero_denominator() {
+            type F = bn256::Fr;
+            let challenge = F::random(OsRng);
+            let mut points = (0..TEST_LEN).map(|_| F::random(OsRng)).collect_vec();
+            points[TEST_LEN - 1] = challenge;
+
+            let mut circuit = Circuit::new(TEST_LEN + 1, 1);
+            let challenge_value = circuit.ext_val(1)[0];
+            let numerators_values = circuit.ext_val(TEST_LEN);
+
+            let challenge_variable = input(&mut circuit, challenge_value, 0);
+            let numerator_variables = numerators_values.clone().into_iter().map(|val| input(&mut circuit, val, 0)).collect_vec();
+
+            fracsum_gadget(&mut circuit, &numerator_variables, &points, challenge_variable, 3, 0);
+
+            let constructed = circuit.finalize();
+            let mut instance = constructed.spawn();
+
+            instance.set_ext(challenge_value, challenge);
+            numerators_values.into_iter().map(|val| instance.set_ext(val, F::random(OsRng))).last();
+
+            assert_eq!(instance.try_execute(0).unwrap_err().error, AdviceError::DivisionByZero);
+            instance.finish();
+        }
+    }
+
+    mod fracsum_var_gadget {
+        use super::*;
+
+        #[test]
+        fn random_eq() {
+            type F = bn256::Fr;
+            let len = TEST_LEN + 1;
+
+            let challenge = F::random(OsRng);
+            let points = (0..len).map(|_| F::random(OsRng)).collect_vec();
+            let numerators = (0..len).map(|_| F::random(OsRng)).collect_vec();
+            let result = points.iter().zip_eq(&numerators).map(|(p, n)| (p - challenge).invert().unwrap() * n).fold(F::ZERO, |acc, n| acc + n);
+
+            let mut circuit = Circuit::new(5, 1);
+            let challenge_value = circuit.ext_val(1)[0];
+            let values = circuit.ext_val(2*len);
+
+            let challenge_variable = input(&mut circuit, challenge_value, 0);
+            let variables = values.iter().map(|val| input(&mut circuit, *val, 0)).collect_vec();
+            let (nums, dens) = variables.split_at(len);
+
+            let result_variable = fracsum_var_gadget(&mut circuit, nums, dens, challenge_variable, 4, 0);
+
+            let constructed = circuit.finalize();
+            let mut instance = constructed.spawn();
+
+            instance.set_ext(challenge_value, challenge);
+            values.into_iter().zip_eq(numerators.into_iter().chain(points)).map(|(val, x)| instance.set_ext(val, x)).last();
+
+            instance.execute(0);
+            instance.valid_witness();
+            assert_eq!(result, instance.cs.getvar(result_variable));
+        }
+
+        #[test]
+        fn zero_denominator() {
+            type F = bn256::Fr;
+            let len = TEST_LEN + 1;
+
+            let challenge = F::random(OsRng);
+            let mut points = (0..len).map(|_| F::random(OsRng)).collect_vec();
+            points[len - 1] = challenge;
+            let numerators = (0..len).map(|_| F::random(OsRng)).collect_vec();
+
+            let mut circuit = Circuit::new(5, 1);
+            let challenge_value = circuit.ext_val(1)[0];
+            let values = circuit.ext_val(2*len);
+
+            let challenge_variable = input(&mut circuit, challenge_value, 0);
+            let variables = values.iter().map(|val| input(&mut circuit, *val, 0)).collect_vec();
+            let (nums, dens) = variables.split_at(len);
+
+            fracsum_var_gadget(&mut circuit, nums, dens, challenge_variable, 4, 0);
+
+            let constructed = circuit.finalize();
+            let mut instance = constructed.spawn();
+
+            instance.set_ext(challenge_value, challenge);
+            values.into_iter().zip_eq(numerators.into_iter().chain(points)).map(|(val, x)| instance.set_ext(val, x)).last();
+
+            assert_eq!(instance.try_execute(0).unwrap_err().error, AdviceError::DivisionByZero);
+            instance.finish();
+        }
+    }
+
+    mod tuple_lookup {
+        use super::*;
+
+        use rand_core::RngCore;
+
+        use crate::{circuit::{random_ck, ExecutionError}, folding::{poseidon::Poseidon, oracle::{HashOracle, Oracle}}};
+
+        /// Rows (a, b, a xor b) for 2-bit a and b.
+        fn xor_table() -> Vec<Vec<bn256::Fr>> {
+            (0..4u64).cartesian_product(0..4u64).map(|(a, b)| vec![a, b, a^b].into_iter().map(bn256::Fr::from).collect()).collect()
+        }
+
+        fn check_xor(values: &[[u64; 3]]) -> Result<(), ExecutionError> {
+            type F = bn256::Fr;
+            let mut circuit = Circuit::new(4, 2);
+            let ext = circuit.ext_val(3*values.len());
+            let mut lookup = StaticTupleLookup::new(&xor_table());
+
+            let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+            for tuple in vars.chunks(3) {
+                lookup.check_tuple(&mut circuit, tuple);
+            }
+            lookup.finalize(&mut circuit, 0, 0, 1, 3);
+
+            let constructed = circuit.finalize();
+            let ck = random_ck(&constructed);
+            let mut instance = constructed.spawn();
+            for (e, v) in ext.iter().zip_eq(values.iter().flatten()) {
+                instance.set_ext(*e, F::from(*v));
+            }
+            instance.try_execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new())?;
+            instance.valid_witness();
+            Ok(())
+        }
+
+        #[test]
+        fn static_table() {
+            let values = (0..TEST_LEN).map(|_| {
+                let (a, b) = (OsRng.next_u64() % 4, OsRng.next_u64() % 4);
+                [a, b, a^b]
+            }).collect_vec();
+            assert_eq!(check_xor(&values), Ok(()));
+        }
+
+        #[test]
+        fn static_table_missing_tuple() {
+            let err = check_xor(&[[1, 2, 3], [1, 2, 0]]).unwrap_err();
+            assert_eq!((err.round, err.error), (0, AdviceError::NotInTable));
+        }
+
+        #[test]
+        fn dynamic_table() {
+            type F = bn256::Fr;
+            let size = 8;
+            let mut circuit = Circuit::new(4, 2);
+            let memory = circuit.ext_val(size);
+            let reads = circuit.ext_val(TEST_LEN);
+
+            let mut rom = DynamicLookup::new(2);
+            let mut single = DynamicLookup::new(1);
+            let memory_vars = memory.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+            for (i, var) in memory_vars.iter().enumerate() {
+                let index = read_const_gadget(&mut circuit, F::from(i as u64), 0);
+                rom.add_row(&[index, *var]);
+                single.add_row(&[*var]);
+            }
+            for e in reads.iter() {
+                let index = input(&mut circuit, *e, 0);
+                let value = circuit.advice(0, Advice::new(size + 1, 1, move |args: &[F], _| {
+                    let idx = args[size].to_repr().as_ref()[0] as usize;
+                    vec![args[idx]]
+                }), memory_vars.iter().chain(once(&index)).map(|x|*x).collect())[0];
+                rom.check_tuple(&mut circuit, &[index, value]);
+                single.check_tuple(&mut circuit, &[value]);
+            }
+            rom.finalize(&mut circuit, 0, 0, 1, 3);
+            single.finalize(&mut circuit, 0, 0, 1, 3);
+
+            let constructed = circuit.finalize();
+            let ck = random_ck(&constructed);
+            let mut instance = constructed.spawn();
+            for e in memory.iter() {
+                instance.set_ext(*e, F::random(OsRng));
+            }
+            for e in reads.iter() {
+                instance.set_ext(*e, F::from(OsRng.next_u64() % size as u64));
+            }
+            instance.execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new());
+            instance.valid_witness();
+        }
     }
 
     mod range_lookup {
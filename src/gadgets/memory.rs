@@ -0,0 +1,274 @@
+// Offline memory checking (Blum et al.). Every cell keeps the timestamp of its last access. An access at time t to
+// a cell holding (v, t_old) removes (addr, v, t_old) and inserts (addr, v', t), checking that t_old < t. Memory is
+// consistent if initial state together with all insertions equals all removals together with the final state,
+// as multisets of (addr, value, timestamp).
+//
+// Timestamps of accesses are constants given by the order of construction, starting from 1 (initial state has 0).
+// During witness generation the memory is simulated natively, the state of every run is kept in an InnerValue.
+
+use std::{cell::RefCell, rc::Rc};
+
+use ff::PrimeField;
+use itertools::Itertools;
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+
+use crate::{
+    circuit::{Circuit, Advice, PolyOp},
+    constraint_system::Variable,
+    external_interface::{InnerValue, RunIndex},
+    gate::Gatebb,
+    utils::{field_precomp::FieldUtils, arith_helper::to_biguint},
+};
+
+use super::{
+    arith::read_const_gadget,
+    multiset::{multiset_eq, MultisetStrategy},
+    rangecheck_small::limb_decompose_no_lookup_gadget,
+};
+
+/// Values and timestamps of all cells.
+type MemoryState<F> = Rc<RefCell<Vec<(F, F)>>>;
+
+pub struct Memory<'a, F: PrimeField+FieldUtils> {
+    init: Vec<Variable>,
+    round: usize,
+    ts_base: u32,
+    ts_limbs: usize,
+    timestamp: u64,
+    /// Removed and inserted tuples (addr, value, timestamp).
+    removed: Vec<Vec<Variable>>,
+    inserted: Vec<Vec<Variable>>,
+    state: InnerValue<MemoryState<F>>,
+    _marker: std::marker::PhantomData<&'a F>,
+}
+
+impl<'a, F: PrimeField+FieldUtils> Memory<'a, F> {
+    /// Creates a memory with given initial values. All accesses are processed in a given round. Differences of
+    /// timestamps are range-checked using ts_limbs limbs of base ts_base, which bounds the amount of accesses.
+    pub fn new(init: &[Variable], round: usize, ts_base: u32, ts_limbs: usize) -> Self {
+        assert!(init.len() > 0, "Memory can not be empty.");
+        for var in init {
+            assert!(var.round <= round);
+        }
+        Self {
+            init: init.to_vec(),
+            round,
+            ts_base,
+            ts_limbs,
+            timestamp: 0,
+            removed: vec![],
+            inserted: vec![],
+            state: InnerValue::new(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.init.len()
+    }
+
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    /// Native state of a run. It is initialized lazily, so the first operation of the memory also takes the
+    /// initial values as arguments.
+    fn native_state(state: &InnerValue<MemoryState<F>>, idx: &RunIndex, init: &[F]) -> MemoryState<F> {
+        if !init.is_empty() {
+            let cells = init.iter().map(|v| (*v, F::ZERO)).collect();
+            let _ = state.replace(idx, Rc::new(RefCell::new(cells)));
+        }
+        state.get(idx).expect("Memory state is not initialized.")
+    }
+
+    /// Arguments of the next advice: given variables, followed by initial values if this is the first operation.
+    fn advice_args(&self, vars: &[Variable]) -> Vec<Variable> {
+        let first = self.removed.is_empty();
+        vars.iter().chain(if first {self.init.iter()} else {[].iter()}).cloned().collect()
+    }
+
+    /// Records an access at the next timestamp. Returns the previous value of the cell.
+    fn access(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, addr: Variable, value: Option<Variable>) -> Variable {
+        assert!(addr.round <= self.round);
+        self.timestamp += 1;
+        let timestamp = self.timestamp;
+        assert!(BigUint::from(timestamp) < BigUint::from(self.ts_base).pow(self.ts_limbs as u32),
+            "Too many memory accesses: timestamp {} does not fit in {} limbs of base {}.", timestamp, self.ts_limbs, self.ts_base);
+        let size = self.size();
+
+        let vars = match value {
+            Some(v) => {assert!(v.round <= self.round); vec![addr, v]},
+            None => vec![addr],
+        };
+        let args = self.advice_args(&vars);
+        let nvars = vars.len();
+        let state = self.state.clone();
+        let prev = circuit.advice(self.round, Advice::new(args.len(), 2, move |args: &[F], idx| {
+            let (vars, init) = args.split_at(nvars);
+            let memory = Self::native_state(&state, idx, init);
+            let mut memory = memory.borrow_mut();
+            let addr = to_biguint(vars[0]).to_usize().filter(|a| *a < size)
+                .unwrap_or_else(|| panic!("Memory access out of bounds: {:?}", vars[0]));
+            let (old_value, old_ts) = memory[addr];
+            let new_value = if nvars > 1 {vars[1]} else {old_value};
+            memory[addr] = (new_value, F::from(timestamp));
+            vec![old_value, old_ts]
+        }), args);
+        let (old_value, old_ts) = (prev[0], prev[1]);
+
+        // old_ts < timestamp
+        let diff = circuit.apply(self.round, PolyOp::new(1, 1, 1, move |args, _| {
+            vec![F::from(timestamp - 1) - args[0]]
+        }), vec![old_ts])[0];
+        limb_decompose_no_lookup_gadget(circuit, self.ts_base, self.round, self.ts_limbs, diff);
+
+        let ts = read_const_gadget(circuit, F::from(timestamp), self.round);
+        self.removed.push(vec![addr, old_value, old_ts]);
+        self.inserted.push(vec![addr, value.unwrap_or(old_value), ts]);
+        old_value
+    }
+
+    pub fn read(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, addr: Variable) -> Variable {
+        self.access(circuit, addr, None)
+    }
+
+    /// Writes the value to the cell, and returns the value it replaced.
+    pub fn write(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, addr: Variable, value: Variable) -> Variable {
+        self.access(circuit, addr, Some(value))
+    }
+
+    /// Allocates the final state and checks consistency of the memory. Returns the final values of the cells.
+    /// Challenges are allocated in challenge_round, which must be larger than the round of the memory.
+    pub fn finalize(
+        self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        challenge_round: usize,
+        strategy: MultisetStrategy,
+    ) -> Vec<Variable> {
+        assert!(challenge_round > self.round);
+        let size = self.size();
+        let args = self.advice_args(&[]);
+        let state = self.state.clone();
+        let last = circuit.advice(self.round, Advice::new(args.len(), 2*size, move |init: &[F], idx| {
+            let memory = Self::native_state(&state, idx, init);
+            let memory = memory.borrow();
+            memory.iter().map(|(v, _)| *v).chain(memory.iter().map(|(_, t)| *t)).collect()
+        }), args);
+        let (final_values, final_ts) = last.split_at(size);
+
+        let Self{init, round, removed, mut inserted, ..} = self;
+        let zero = read_const_gadget(circuit, F::ZERO, round);
+        let addrs = (0..size).map(|i| read_const_gadget(circuit, F::from(i as u64), round)).collect_vec();
+
+        inserted.extend(addrs.iter().zip_eq(init.iter()).map(|(a, v)| vec![*a, *v, zero]));
+        let mut removed = removed;
+        removed.extend(addrs.iter().zip_eq(final_values.iter().zip_eq(final_ts.iter())).map(|(a, (v, t))| vec![*a, *v, *t]));
+
+        multiset_eq(circuit, &inserted, &removed, challenge_round, strategy);
+        final_values.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use rand_core::{OsRng, RngCore};
+
+    use crate::{circuit::random_ck, folding::{poseidon::Poseidon, oracle::{HashOracle, Oracle}}, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+    const SIZE: usize = 4;
+
+    /// Runs a program of (is_write, addr) operations, writing random values, and compares the reads
+    /// and the final state with a native simulation.
+    fn run(program: &[(bool, u64)], strategy: MultisetStrategy) {
+        let mut circuit = Circuit::new(5, 2);
+        let init_ext = circuit.ext_val(SIZE);
+        let addr_ext = circuit.ext_val(program.len());
+        let value_ext = circuit.ext_val(program.len());
+        let init = init_ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+
+        let mut memory = Memory::new(&init, 0, 4, 4);
+        let mut outputs = vec![];
+        for (i, (is_write, _)) in program.iter().enumerate() {
+            let addr = input(&mut circuit, addr_ext[i], 0);
+            outputs.push(if *is_write {
+                let value = input(&mut circuit, value_ext[i], 0);
+                memory.write(&mut circuit, addr, value)
+            } else {
+                memory.read(&mut circuit, addr)
+            });
+        }
+        let finals = memory.finalize(&mut circuit, 1, strategy);
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+        let mut instance = constructed.spawn();
+
+        let mut native = (0..SIZE).map(|_| F::random(OsRng)).collect_vec();
+        for (e, v) in init_ext.iter().zip_eq(native.iter()) {
+            instance.set_ext(*e, *v);
+        }
+        let mut expected = vec![];
+        for (i, (is_write, addr)) in program.iter().enumerate() {
+            instance.set_ext(addr_ext[i], F::from(*addr));
+            let value = F::from(OsRng.next_u64());
+            instance.set_ext(value_ext[i], value);
+            expected.push(native[*addr as usize]);
+            if *is_write {
+                native[*addr as usize] = value;
+            }
+        }
+        instance.execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new());
+        instance.valid_witness();
+
+        for (var, value) in outputs.iter().zip_eq(expected) {
+            assert_eq!(instance.cs.getvar(*var), value);
+        }
+        for (var, value) in finals.iter().zip_eq(native) {
+            assert_eq!(instance.cs.getvar(*var), value);
+        }
+        instance.finish();
+    }
+
+    const PROGRAM: [(bool, u64); 9] = [
+        (false, 2), (true, 2), (false, 2), (true, 0), (true, 0), (false, 3), (false, 0), (true, 3), (false, 3),
+    ];
+
+    #[test]
+    fn grand_product() {
+        run(&PROGRAM, MultisetStrategy::GrandProduct { rate: 4 });
+    }
+
+    #[test]
+    fn log_derivative() {
+        run(&PROGRAM, MultisetStrategy::LogDerivative { rate: 4 });
+    }
+
+    #[test]
+    fn no_accesses() {
+        run(&[], MultisetStrategy::LogDerivative { rate: 4 });
+    }
+
+    #[test]
+    #[should_panic(expected = "Memory access out of bounds")]
+    fn out_of_bounds() {
+        run(&[(true, 1), (false, SIZE as u64)], MultisetStrategy::LogDerivative { rate: 4 });
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many memory accesses: timestamp 4 does not fit in 2 limbs of base 2.")]
+    fn too_many_accesses() {
+        let mut circuit = Circuit::<F, Gatebb<F>>::new(5, 2);
+        let ext = circuit.ext_val(1)[0];
+        let addr = input(&mut circuit, ext, 0);
+        let mut memory = Memory::new(&[addr], 0, 2, 2);
+        for _ in 0..4 {
+            memory.read(&mut circuit, addr);
+        }
+    }
+}
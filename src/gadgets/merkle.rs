@@ -0,0 +1,144 @@
+// Merkle path gadgets for trees built by folding::merkle::MerkleTree.
+
+use ff::PrimeField;
+
+use crate::{circuit::{Circuit, PolyOp}, constraint_system::Variable, folding::poseidon::Poseidon, gate::Gatebb, utils::field_precomp::FieldUtils};
+
+use super::{arith::eq_gadget, bits::bit_decomposition_gadget, poseidon::poseidon_gadget_internal};
+
+/// Returns (a, b) if bit = 0 and (b, a) if bit = 1. Does not check that bit is boolean.
+fn conditional_swap<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    bit: Variable,
+    a: Variable,
+    b: Variable,
+    round: usize,
+) -> (Variable, Variable) {
+    let ret = circuit.apply(round, PolyOp::new(2, 3, 2, |args, _| {
+        let (bit, a, b) = (args[0], args[1], args[2]);
+        let t = bit * (b - a);
+        vec![a + t, b - t]
+    }), vec![bit, a, b]);
+    (ret[0], ret[1])
+}
+
+/// Computes the root from a leaf, its siblings (from the leaf level up) and boolean direction bits,
+/// where bit i = 1 means that the node on level i is a right child.
+pub fn merkle_root_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon<F>,
+    leaf: Variable,
+    siblings: &[Variable],
+    index_bits: &[Variable],
+    round: usize,
+) -> Variable {
+    assert_eq!(siblings.len(), index_bits.len());
+    siblings.iter().zip(index_bits.iter()).fold(leaf, |acc, (sibling, bit)| {
+        let (left, right) = conditional_swap(circuit, *bit, acc, *sibling, round);
+        poseidon_gadget_internal(circuit, cfg, 1, round, vec![left, right])
+    })
+}
+
+/// Checks that leaf is at position index of the tree with a given root. Returns the direction bits.
+pub fn merkle_inclusion_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon<F>,
+    root: Variable,
+    leaf: Variable,
+    siblings: &[Variable],
+    index: Variable,
+    round: usize,
+) -> Vec<Variable> {
+    let bits = bit_decomposition_gadget(circuit, round, siblings.len(), index);
+    let computed = merkle_root_gadget(circuit, cfg, leaf, siblings, &bits, round);
+    eq_gadget(circuit, root, computed);
+    bits
+}
+
+/// Checks that old_leaf is at position index of the tree with root old_root, and returns the root of the
+/// tree in which it is replaced by new_leaf. Both paths share the siblings and the decomposition of index.
+pub fn merkle_update_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon<F>,
+    old_root: Variable,
+    old_leaf: Variable,
+    new_leaf: Variable,
+    siblings: &[Variable],
+    index: Variable,
+    round: usize,
+) -> Variable {
+    let bits = merkle_inclusion_gadget(circuit, cfg, old_root, old_leaf, siblings, index, round);
+    merkle_root_gadget(circuit, cfg, new_leaf, siblings, &bits, round)
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use itertools::Itertools;
+    use rand_core::OsRng;
+
+    use crate::{circuit::Circuit, folding::merkle::MerkleTree, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+    const DEPTH: usize = 4;
+
+    #[test]
+    fn inclusion_and_update() {
+        let cfg = Poseidon::new();
+        let mut tree = MerkleTree::<F>::new(Poseidon::new(), DEPTH);
+        for _ in 0..11 {
+            tree.insert(F::random(OsRng));
+        }
+
+        let mut circuit = Circuit::new(5, 1);
+        let ext = circuit.ext_val(DEPTH + 4);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let (root, old_leaf, new_leaf, index) = (vars[0], vars[1], vars[2], vars[3]);
+        let new_root = merkle_update_gadget(&mut circuit, &cfg, root, old_leaf, new_leaf, &vars[4..], index, 0);
+
+        let constructed = circuit.finalize();
+        for index in [0, 6, 13] {
+            let path = tree.prove(index);
+            let old_root = tree.root();
+            let old_leaf = tree.get(index);
+            let leaf = F::random(OsRng);
+            tree.update(index, leaf);
+
+            let mut instance = constructed.spawn();
+            let values = [old_root, old_leaf, leaf, F::from(index)].into_iter().chain(path.siblings);
+            for (e, v) in ext.iter().zip_eq(values) {
+                instance.set_ext(*e, v);
+            }
+            instance.execute(0);
+            instance.valid_witness();
+            assert_eq!(instance.cs.getvar(new_root), tree.root());
+            instance.finish();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrong_leaf() {
+        let cfg = Poseidon::new();
+        let mut tree = MerkleTree::<F>::new(Poseidon::new(), DEPTH);
+        tree.insert(F::ONE);
+
+        let mut circuit = Circuit::new(5, 1);
+        let ext = circuit.ext_val(DEPTH + 3);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        merkle_inclusion_gadget(&mut circuit, &cfg, vars[0], vars[1], &vars[3..], vars[2], 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let path = tree.prove(0);
+        let values = [tree.root(), F::from(2), F::ZERO].into_iter().chain(path.siblings);
+        for (e, v) in ext.iter().zip_eq(values) {
+            instance.set_ext(*e, v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+    }
+}
@@ -1,14 +1,30 @@
 pub mod lc;
 pub mod poseidon;
+pub mod poseidon2;
 pub mod bits;
 pub mod rangecheck_small;
 pub mod rangecheck_lookup;
 pub mod rangecheck_common;
 pub mod ecmul;
+pub mod ecmul_fixed;
 pub mod running_prod;
+pub mod polynomial;
 pub mod nonzero_check;
 pub mod lookup;
 pub mod input;
 pub mod arith;
 pub mod cyclefold;
-pub mod folding_utils;
\ No newline at end of file
+pub mod folding_utils;
+pub mod fold;
+pub mod emulated;
+pub mod compare;
+pub mod uint;
+pub mod sha256;
+pub mod keccak;
+pub mod merkle;
+pub mod schnorr;
+pub mod multiset;
+pub mod sort;
+pub mod memory;
+pub mod range_arith;
+pub mod biguint;
@@ -0,0 +1,155 @@
+// Multiset equality (permutation) argument. Tuples are compressed by a random linear combination, and then
+// either products of (x - c) or sums of 1/(x - c) of both sides are compared, for a random challenge c.
+// Both challenges are allocated by the circuit, so all compared values must be known before the challenge round.
+
+use ff::PrimeField;
+use itertools::Itertools;
+
+use crate::{circuit::{Circuit, PolyOp}, constraint_system::Variable, gate::Gatebb, utils::field_precomp::FieldUtils};
+
+use super::{arith::eq_gadget, lookup::{compress_gadget, compression_powers, fracsum_var_gadget}};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultisetStrategy {
+    /// Compares products of (x - c). A witness per rate - 1 elements, degree = rate.
+    GrandProduct { rate: usize },
+    /// Compares sums of 1/(x - c). A witness per rate elements, degree = rate + 1.
+    LogDerivative { rate: usize },
+}
+
+/// Product of (vals[i] - c), multiplying in rate -sized chunks.
+fn shifted_prod_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    vals: &[Variable],
+    c: Variable,
+    rate: usize,
+    round: usize,
+) -> Variable {
+    assert!(rate > 1);
+    let (first, rest) = vals.split_at(rate.min(vals.len()));
+    let n = first.len();
+    let mut acc = circuit.apply(round, PolyOp::new(n, n+1, 1, |args, _| {
+        let (c, vals) = args.split_at(1);
+        vec![vals.iter().map(|x| *x - c[0]).product()]
+    }), [c].iter().chain(first.iter()).cloned().collect())[0];
+    for chunk in rest.chunks(rate - 1) {
+        let n = chunk.len();
+        acc = circuit.apply(round, PolyOp::new(n+1, n+2, 1, |args, _| {
+            let (tmp, vals) = args.split_at(2);
+            vec![vals.iter().fold(tmp[0], |acc, x| acc * (*x - tmp[1]))]
+        }), [acc, c].iter().chain(chunk.iter()).cloned().collect())[0];
+    }
+    acc
+}
+
+/// Checks that lhs and rhs are equal as multisets of tuples. Challenges are allocated in challenge_round,
+/// which must be larger than the round of every compared variable.
+pub fn multiset_eq<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    lhs: &[Vec<Variable>],
+    rhs: &[Vec<Variable>],
+    challenge_round: usize,
+    strategy: MultisetStrategy,
+) -> () {
+    assert_eq!(lhs.len(), rhs.len(), "Multisets of different sizes are never equal.");
+    if lhs.is_empty() {
+        return
+    }
+    let width = lhs[0].len();
+    assert!(width > 0);
+    for tuple in lhs.iter().chain(rhs.iter()) {
+        assert_eq!(tuple.len(), width, "All tuples must have the same width.");
+        for var in tuple {
+            assert!(var.round < challenge_round, "Variable of round {} can not be compared using challenge of round {}.", var.round, challenge_round);
+        }
+    }
+
+    let c = circuit.challenge(challenge_round - 1);
+    let powers = if width > 1 {
+        let gamma = circuit.challenge(challenge_round - 1);
+        compression_powers(circuit, gamma, width, challenge_round)
+    } else {
+        vec![]
+    };
+    let lhs = lhs.iter().map(|t| compress_gadget(circuit, t, &powers, challenge_round)).collect_vec();
+    let rhs = rhs.iter().map(|t| compress_gadget(circuit, t, &powers, challenge_round)).collect_vec();
+
+    let (l, r) = match strategy {
+        MultisetStrategy::GrandProduct { rate } => (
+            shifted_prod_gadget(circuit, &lhs, c, rate, challenge_round),
+            shifted_prod_gadget(circuit, &rhs, c, rate, challenge_round),
+        ),
+        MultisetStrategy::LogDerivative { rate } => {
+            let ones = vec![circuit.one(); lhs.len()];
+            (
+                fracsum_var_gadget(circuit, &ones, &lhs, c, rate, challenge_round),
+                fracsum_var_gadget(circuit, &ones, &rhs, c, rate, challenge_round),
+            )
+        }
+    };
+    eq_gadget(circuit, l, r);
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use rand::seq::SliceRandom;
+    use rand_core::OsRng;
+
+    use crate::{circuit::random_ck, folding::{poseidon::Poseidon, oracle::{HashOracle, Oracle}}, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    /// Compares a random vector of tuples with its permutation, in which the first tuple might be replaced.
+    fn check(width: usize, len: usize, strategy: MultisetStrategy, tamper: bool) {
+        let mut circuit = Circuit::new(5, 2);
+        let ext = circuit.ext_val(2 * width * len);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let (lhs, rhs) = vars.split_at(width * len);
+        let lhs = lhs.chunks(width).map(|t| t.to_vec()).collect_vec();
+        let rhs = rhs.chunks(width).map(|t| t.to_vec()).collect_vec();
+        multiset_eq(&mut circuit, &lhs, &rhs, 1, strategy);
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+        let mut instance = constructed.spawn();
+        let tuples = (0..len).map(|_| (0..width).map(|_| F::random(OsRng)).collect_vec()).collect_vec();
+        let mut permuted = tuples.clone();
+        permuted.shuffle(&mut OsRng);
+        if tamper {
+            permuted[0][width - 1] += F::ONE;
+        }
+        for (e, v) in ext.iter().zip_eq(tuples.iter().chain(permuted.iter()).flatten()) {
+            instance.set_ext(*e, *v);
+        }
+        instance.execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new());
+        instance.valid_witness();
+    }
+
+    #[test]
+    fn grand_product() {
+        check(1, 11, MultisetStrategy::GrandProduct { rate: 4 }, false);
+        check(3, 7, MultisetStrategy::GrandProduct { rate: 5 }, false);
+    }
+
+    #[test]
+    fn log_derivative() {
+        check(1, 11, MultisetStrategy::LogDerivative { rate: 4 }, false);
+        check(3, 7, MultisetStrategy::LogDerivative { rate: 3 }, false);
+    }
+
+    #[test]
+    #[should_panic]
+    fn grand_product_not_permutation() {
+        check(2, 6, MultisetStrategy::GrandProduct { rate: 4 }, true);
+    }
+
+    #[test]
+    #[should_panic]
+    fn log_derivative_not_permutation() {
+        check(2, 6, MultisetStrategy::LogDerivative { rate: 4 }, true);
+    }
+}
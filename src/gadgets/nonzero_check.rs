@@ -1,8 +1,7 @@
 use std::{cmp::max};
 use ff::PrimeField;
-use crate::{utils::field_precomp::FieldUtils, circuit::{Circuit, Advice}, gate::Gatebb, constraint_system::Variable};
-use super::running_prod::prod_run_gadget;
-use crate::gatelib::nonzero_check;
+use crate::{utils::field_precomp::FieldUtils, circuit::Circuit, gate::Gatebb, constraint_system::Variable};
+use super::{arith::inv_gadget, running_prod::prod_run_gadget};
 
 pub struct Nonzeros {
     entries: Vec<Variable>,
@@ -35,14 +34,7 @@ fn nonzero_gadget<'a, F: PrimeField + FieldUtils> (circuit: &mut Circuit<'a, F,
     }
     
     let prod = prod_run_gadget(circuit, input.to_vec(), round, rate);
-    let adv_invert = Advice::new(1, 1, |arg: &[F], _| vec![arg[0].invert().unwrap()]);
-
-    let prod_inv = circuit.advice(round, adv_invert, vec![prod])[0];
-
-    circuit.constrain_with(
-        &vec![prod, prod_inv], 
-        &nonzero_check(),
-    );
+    inv_gadget(circuit, prod, round);
 }
 
 // pub struct NonzeroSubroutine<'a, F: PrimeField+FieldUtils> {
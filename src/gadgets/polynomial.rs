@@ -0,0 +1,206 @@
+// Evaluation of polynomials with variable coefficients, and interpolation over constant domains.
+// Both are done by Horner's rule, packing as many coefficients into an operation as the degree of the circuit allows.
+
+use std::ops::Range;
+
+use ff::PrimeField;
+use itertools::Itertools;
+
+use crate::{
+    circuit::{Circuit, PolyOp},
+    constraint_system::Variable,
+    gate::Gatebb,
+    utils::{field_precomp::FieldUtils, poly_utils::{lagrange_basis, lagrange_coeffs, lagrange_weights}},
+};
+
+/// Splits coefficients 0..len into chunks processed by consecutive Horner steps, highest chunk first.
+/// The first step takes max_degree coefficients, every next one max_degree - 1, as it also multiplies the accumulator.
+fn horner_chunks(len: usize, max_degree: usize) -> Vec<Range<usize>> {
+    assert!(len > 0, "Can not evaluate the empty polynomial");
+    assert!(max_degree > 1 || len == 1, "Horner's rule requires circuit of degree at least 2");
+    let mut ret = vec![];
+    let mut end = len - std::cmp::min(max_degree, len);
+    ret.push(end..len);
+    while end > 0 {
+        let start = end.saturating_sub(max_degree - 1);
+        ret.push(start..end);
+        end = start;
+    }
+    ret
+}
+
+/// Evaluates the polynomial with given coefficients (lowest first) at x.
+pub fn horner_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    coeffs: &[Variable],
+    x: Variable,
+    round: usize,
+) -> Variable {
+    let mut acc: Option<Variable> = None;
+    for chunk in horner_chunks(coeffs.len(), circuit.max_degree()) {
+        let len = chunk.len();
+        let has_acc = acc.is_some();
+        // Arguments are x, the coefficients of the chunk and the accumulator, if any.
+        let args = [x].into_iter().chain(coeffs[chunk].iter().cloned()).chain(acc).collect_vec();
+        acc = Some(circuit.apply(round, PolyOp::new(len + has_acc as usize, args.len(), 1, move |args, _| {
+            let x = args[0];
+            let init = if has_acc {args[len + 1]} else {F::ZERO};
+            vec![args[1..len+1].iter().rev().fold(init, |acc, c| acc * x + c)]
+        }), args)[0]);
+    }
+    acc.unwrap()
+}
+
+/// Evaluates at x the polynomial of degree < n taking given values on the constant domain of size n.
+/// If n does not exceed the degree of the circuit, this is a single operation summing values with the Lagrange basis.
+/// Otherwise, the polynomial is evaluated by Horner's rule, and its coefficients, being linear combinations of
+/// values, are computed inside of the operations instead of being allocated.
+pub fn interpolate_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    domain: &[F],
+    values: &[Variable],
+    x: Variable,
+    round: usize,
+) -> Variable {
+    let n = domain.len();
+    assert!(n == values.len(), "Expected {} values, got {}", n, values.len());
+    assert!(n > 0, "Can not interpolate over the empty domain");
+
+    if n <= circuit.max_degree() {
+        let weights = lagrange_weights(domain);
+        let domain = domain.to_vec();
+        let args = values.iter().cloned().chain([x]).collect_vec();
+        return circuit.apply(round, PolyOp::new(n, n + 1, 1, move |args, _| {
+            let basis = lagrange_basis(&domain, &weights, args[n]);
+            vec![basis.iter().zip(args[..n].iter()).fold(F::ZERO, |acc, (l, v)| acc + *l * v)]
+        }), args)[0]
+    }
+
+    // Row k expresses the k-th coefficient through the values.
+    let basis = lagrange_coeffs(domain);
+    let rows = (0..n).map(|k| basis.iter().map(|l| l[k]).collect_vec()).collect_vec();
+
+    let mut acc: Option<Variable> = None;
+    for chunk in horner_chunks(n, circuit.max_degree()) {
+        let len = chunk.len();
+        let has_acc = acc.is_some();
+        let rows = rows[chunk].to_vec();
+        // Arguments are x, the values and the accumulator, if any.
+        let args = [x].into_iter().chain(values.iter().cloned()).chain(acc).collect_vec();
+        acc = Some(circuit.apply(round, PolyOp::new(len + has_acc as usize, args.len(), 1, move |args, _| {
+            let x = args[0];
+            let values = &args[1..n+1];
+            let init = if has_acc {args[n + 1]} else {F::ZERO};
+            vec![rows.iter().rev().fold(init, |acc, row| {
+                acc * x + row.iter().zip(values.iter()).fold(F::ZERO, |acc, (m, v)| acc + *m * v)
+            })]
+        }), args)[0]);
+    }
+    acc.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use rand_core::OsRng;
+
+    use crate::{circuit::ExternalValue, gadgets::input::input, utils::poly_utils::{evaluate, interpolate}};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    fn inputs<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, n: usize) -> (Vec<Variable>, Vec<ExternalValue<F>>) {
+        let ext = circuit.ext_val(n);
+        (ext.iter().map(|e| input(circuit, *e, 0)).collect(), ext)
+    }
+
+    #[test]
+    fn test_horner_chunks() {
+        assert_eq!(horner_chunks(1, 1), vec![0..1]);
+        assert_eq!(horner_chunks(3, 5), vec![0..3]);
+        assert_eq!(horner_chunks(5, 5), vec![0..5]);
+        assert_eq!(horner_chunks(10, 3), vec![7..10, 5..7, 3..5, 1..3, 0..1]);
+    }
+
+    #[test]
+    fn test_horner_gadget() {
+        for max_degree in [2, 3, 5] {
+            for len in [1, 2, 5, 6, 13] {
+                let mut circuit = Circuit::new(max_degree, 1);
+                let (coeffs, coeffs_ext) = inputs(&mut circuit, len);
+                let (x, x_ext) = inputs(&mut circuit, 1);
+                let res = horner_gadget(&mut circuit, &coeffs, x[0], 0);
+
+                let constructed = circuit.finalize();
+                let mut instance = constructed.spawn();
+                let coeffs_val = (0..len).map(|_| F::random(OsRng)).collect_vec();
+                let x_val = F::random(OsRng);
+                for (e, v) in coeffs_ext.iter().zip(coeffs_val.iter()) {
+                    instance.set_ext(*e, *v);
+                }
+                instance.set_ext(x_ext[0], x_val);
+                instance.execute(0);
+                instance.valid_witness();
+
+                assert_eq!(instance.cs.getvar(res), evaluate(&coeffs_val, x_val));
+                assert_eq!(instance.cs.wtns[0].privs.len(), horner_chunks(len, max_degree).len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpolate_gadget() {
+        // Sizes below and above the degree of the circuit.
+        for n in [1, 4, 5, 11] {
+            let mut circuit = Circuit::new(5, 1);
+            let domain = (0..n).map(|_| F::random(OsRng)).collect_vec();
+            let (values, values_ext) = inputs(&mut circuit, n);
+            let (x, x_ext) = inputs(&mut circuit, 1);
+            let res = interpolate_gadget(&mut circuit, &domain, &values, x[0], 0);
+
+            let constructed = circuit.finalize();
+            let mut instance = constructed.spawn();
+            let values_val = (0..n).map(|_| F::random(OsRng)).collect_vec();
+            for (e, v) in values_ext.iter().zip(values_val.iter()) {
+                instance.set_ext(*e, *v);
+            }
+            // The first point of the domain must give back the first value.
+            instance.set_ext(x_ext[0], domain[0]);
+            instance.execute(0);
+            instance.valid_witness();
+
+            assert_eq!(instance.cs.getvar(res), values_val[0]);
+
+            let coeffs = interpolate(&domain, &values_val);
+            for (d, v) in domain.iter().zip(values_val.iter()) {
+                assert_eq!(evaluate(&coeffs, *d), *v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpolate_gadget_outside_domain() {
+        let n = 9;
+        let mut circuit = Circuit::new(4, 1);
+        let domain = (0..n).map(|i| F::from(3 * i as u64 + 1)).collect_vec();
+        let (values, values_ext) = inputs(&mut circuit, n);
+        let (x, x_ext) = inputs(&mut circuit, 1);
+        let res = interpolate_gadget(&mut circuit, &domain, &values, x[0], 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let coeffs = (0..n).map(|_| F::random(OsRng)).collect_vec();
+        for (e, d) in values_ext.iter().zip(domain.iter()) {
+            instance.set_ext(*e, evaluate(&coeffs, *d));
+        }
+        let x_val = F::random(OsRng);
+        instance.set_ext(x_ext[0], x_val);
+        instance.execute(0);
+        instance.valid_witness();
+
+        assert_eq!(instance.cs.getvar(res), evaluate(&coeffs, x_val));
+        assert_eq!(instance.cs.wtns[0].privs.len(), horner_chunks(n, 4).len());
+    }
+}
@@ -4,19 +4,18 @@
 
 use std::rc::Rc;
 use elsa::map::FrozenMap;
-use ff::{Field, PrimeField};
-use gate_macro::make_gate;
-use crate::{circuit::{Advice}, folding::{poseidon::{ark, mix, sbox, Poseidon}, poseidon_constants}};
-use halo2::halo2curves::bn256;
+use ff::PrimeField;
+use itertools::Itertools;
+use crate::{checkpoint::ShapeHasher, circuit::{Advice}, folding::poseidon::{ark, mix, sbox, Poseidon, SpongeMode}, utils::field_precomp::FieldUtils};
 use crate::{circuit::{Circuit, PolyOp}, constraint_system::Variable, gate::Gatebb};
 use num_traits::pow;
 
-
-type F = bn256::Fr;
+use super::arith::read_const_gadget;
 
 /// A polynomial operation executing k rounds of Poseidon. Recommended k = 2, which amounts to the polyop of degree 25.
 /// Does not make any sanity checks on state length.
-pub fn poseidon_kround_poly(
+pub fn poseidon_kround_poly<F: PrimeField>(
+    alpha: u64,
     k: usize,
     state: &[F],
     i: usize,
@@ -31,21 +30,22 @@ pub fn poseidon_kround_poly(
     
     for j in 0..k {
         ark(&mut state, c, (i+j)*t);
-        sbox(n_rounds_f, n_rounds_p, &mut state, i+j);
+        sbox(alpha, n_rounds_f, n_rounds_p, &mut state, i+j);
         state = mix(&state, m);
     }
 
     state
 }
 
-/// This function is a single polynomial of degree 5, executing all partial rounds in one go.
+/// This function is a single polynomial of degree alpha, executing all partial rounds in one go.
 /// It takes as an advice the vector of 1st elements of each state after the sbox operation.
 /// It is given in a form of constraint, because otherwise we would need to introduce an additional linear constraint.
 /// This is an API limitation - currently there is no way to map output of a polynomial operation to an already
 /// existing variable.
-/// Input advices correspond to 5-th power of the state[0], and output advices to new state[0].
+/// Input advices correspond to alpha-th power of the state[0], and output advices to new state[0].
 /// User then must map them to the same variable input.
-pub fn poseidon_partial_rounds_constraint(
+pub fn poseidon_partial_rounds_constraint<F: PrimeField>(
+    alpha: u64,
     input_state: &[F],
     output_state: &[F],
     input_advices: &[F],
@@ -60,8 +60,7 @@ pub fn poseidon_partial_rounds_constraint(
     let mut ret = vec![];
     for j in 0 .. n_rounds_p {
         ark(&mut state, c, t*(j+n_rounds_f/2));
-        let tmp = state[0].square().square();
-        ret.push(state[0]*tmp - output_advices[j]); // push state[0]^5 == output_advices[j]
+        ret.push(state[0].pow_vartime([alpha]) - output_advices[j]); // push state[0]^alpha == output_advices[j]
         state[0] = input_advices[j]; // replace value with input advice
         state = mix(&state, m);
     }
@@ -73,27 +72,9 @@ pub fn poseidon_partial_rounds_constraint(
     ret
 }
 
-#[make_gate]
-pub fn poseidon_partial_rounds_gate<'c>(n_rounds_p: usize, n_rounds_f: usize, t: usize) -> Gatebb<'c, F> {
-    let (c_str, m_str) = poseidon_constants::constants();
-    let c: Vec<F> = c_str[t - 2].iter().map(|s| {F::from_str_vartime(s).unwrap()}).collect();
-    let m: Vec<Vec<F>> = m_str[t - 2].iter().map(|r| r.iter().map(|s| {F::from_str_vartime(s).unwrap()}).collect()).collect();
-    Gatebb::new(
-        5,
-        2*n_rounds_p+2*t,
-        n_rounds_p+t,
-        Rc::new(move|args, _|{
-            let (tmp, io) = args.split_at(2*n_rounds_p);
-            let (adv_in, adv_out) = tmp.split_at(n_rounds_p);
-            let (inp, out) = io.split_at(t);
-            poseidon_partial_rounds_constraint(inp, out, adv_in, adv_out, &c, &m, n_rounds_f, n_rounds_p, t)
-        }), 
-        vec![]
-    )
-}
-
 /// This will compute intermediate state[0] values and the final state
-pub fn poseidon_partial_rounds_advice(
+pub fn poseidon_partial_rounds_advice<F: PrimeField>(
+    alpha: u64,
     input_state: &[F],
     c: &Vec<F>,
     m: &Vec<Vec<F>>,
@@ -105,8 +86,7 @@ pub fn poseidon_partial_rounds_advice(
     let mut ret = vec![];
     for j in 0 .. n_rounds_p {
         ark(&mut state, c, t*(j+n_rounds_f/2));
-        let tmp = state[0].square().square();
-        state[0] *= tmp;
+        state[0] = state[0].pow_vartime([alpha]);
         ret.push(state[0]);
         state = mix(&state, m);
     }
@@ -118,9 +98,55 @@ pub fn poseidon_partial_rounds_advice(
     ret
 }
 
+fn init_poseidon_partial_rounds_gate<'c, F: PrimeField>(t: usize, cfg: Poseidon<F>) -> Gatebb<'c, F> {
+    let alpha = cfg.constants.alpha;
+    let n_rounds_f = cfg.constants.n_rounds_f;
+    let n_rounds_p = cfg.constants.n_rounds_p[t - 2];
+    Gatebb::new(
+        alpha as usize,
+        2*n_rounds_p+2*t,
+        n_rounds_p+t,
+        Rc::new(move|args, _|{
+            let (tmp, io) = args.split_at(2*n_rounds_p);
+            let (adv_in, adv_out) = tmp.split_at(n_rounds_p);
+            let (inp, out) = io.split_at(t);
+            let c = &cfg.constants.c[t-2];
+            let m = &cfg.constants.m[t-2];
+            poseidon_partial_rounds_constraint(alpha, inp, out, adv_in, adv_out, c, m, n_rounds_f, n_rounds_p, t)
+        }),
+        vec![]
+    )
+}
+
+/// Registry key of the partial rounds gate: the round parameters of width t, and a digest of its constants.
+fn poseidon_partial_rounds_key<F: PrimeField>(t: usize, cfg: &Poseidon<F>) -> String {
+    let mut hasher = ShapeHasher::new();
+    for x in cfg.constants.c[t-2].iter().chain(cfg.constants.m[t-2].iter().flatten()) {
+        hasher.write_bytes(x.to_repr().as_ref());
+    }
+    format!(
+        "{}::poseidon_partial_rounds_gate::<t = {}, alpha = {}, n_rounds_f = {}, n_rounds_p = {}, constants = {:016x}>",
+        module_path!(), t, cfg.constants.alpha, cfg.constants.n_rounds_f, cfg.constants.n_rounds_p[t-2], hasher.finish()
+    )
+}
+
+/// Constraint of `poseidon_partial_rounds_gadget` for the state of width t, registered once per set of parameters.
+/// Works as the gates produced by `make_gate`, which can not be used here because `Poseidon` is not `Copy`.
+pub fn poseidon_partial_rounds_gate<'c, F: PrimeField>(t: usize, cfg: &Poseidon<F>) -> impl Fn(&FrozenMap<String, Box<Gatebb<'c, F>>>) -> Gatebb<'c, F> {
+    let cfg = cfg.clone();
+    let qual_name = poseidon_partial_rounds_key(t, &cfg);
+    move |fm: &FrozenMap<String, Box<Gatebb<'c, F>>>| {
+        if fm.get(&qual_name).is_none() {
+            fm.insert(qual_name.clone(), Box::new(init_poseidon_partial_rounds_gate(t, cfg.clone())));
+        }
+        fm.get(&qual_name).unwrap().clone()
+    }
+}
+
 /// A gadget which implements partial rounds of Poseidon hash function.
-pub fn poseidon_partial_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon, inp: Vec<Variable>, round: usize) -> Vec<Variable>{
+pub fn poseidon_partial_rounds_gadget<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, inp: Vec<Variable>, round: usize) -> Vec<Variable>{
     let t = inp.len();
+    let alpha = cfg.constants.alpha;
     let n_rounds_f = cfg.constants.n_rounds_f;
     let n_rounds_p = cfg.constants.n_rounds_p[t - 2];
     let c = &cfg.constants.c[t-2];
@@ -131,7 +157,7 @@ pub fn poseidon_partial_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a
         Advice::new(
             t,
             n_rounds_p + t,
-            move |input_state, _| poseidon_partial_rounds_advice(input_state, c, m, n_rounds_f, n_rounds_p, t)
+            move |input_state, _| poseidon_partial_rounds_advice(alpha, input_state, c, m, n_rounds_f, n_rounds_p, t)
         ),
         inp.clone(),
     );
@@ -143,16 +169,17 @@ pub fn poseidon_partial_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a
 
     circuit.constrain_with(
         &to_constrain,
-        &poseidon_partial_rounds_gate(n_rounds_p, n_rounds_f, t)
+        &poseidon_partial_rounds_gate(t, cfg)
     );
 
     out.to_vec()
 }
 
-pub fn poseidon_full_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon, k: usize, round: usize, inp: Vec<Variable>, start: usize, finish: usize) -> Vec<Variable> {
+pub fn poseidon_full_rounds_gadget<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, k: usize, round: usize, inp: Vec<Variable>, start: usize, finish: usize) -> Vec<Variable> {
 
     let t = if start==0 {inp.len()+1} else {inp.len()};
 
+    let alpha = cfg.constants.alpha;
     let n_rounds_f = cfg.constants.n_rounds_f;
     let n_rounds_p = cfg.constants.n_rounds_p[t - 2];
 
@@ -169,13 +196,13 @@ pub fn poseidon_full_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F
         state = circuit.apply(
             round,
             PolyOp::new(
-                pow(5,k),
+                pow(alpha as usize, k),
                 t-1,
                 t,
                 move |inp, _| {
                     let mut state = vec![F::ZERO; t];
                     state[1..].clone_from_slice(&inp);            
-                    poseidon_kround_poly(k, &state, 0, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
+                    poseidon_kround_poly(alpha, k, &state, 0, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
                 }
             ),
             state,
@@ -187,11 +214,11 @@ pub fn poseidon_full_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F
         state = circuit.apply(
             round,
             PolyOp::new(
-                pow(5,k),
+                pow(alpha as usize, k),
                 t,
                 t,
                 move |inp, _| {
-                    poseidon_kround_poly(k, inp, i, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
+                    poseidon_kround_poly(alpha, k, inp, i, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
                 }
             ),
             state,
@@ -203,11 +230,11 @@ pub fn poseidon_full_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F
         state = circuit.apply(
             round,
             PolyOp::new(
-                pow(5,rem),
+                pow(alpha as usize, rem),
                 t,
                 t,
                 move |inp, _| {
-                    poseidon_kround_poly(rem, inp, i, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
+                    poseidon_kround_poly(alpha, rem, inp, i, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
                 }
             ),
             state,
@@ -217,79 +244,83 @@ pub fn poseidon_full_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F
     state
 }
 
-pub fn poseidon_mixed_strategy_start(
+pub fn poseidon_mixed_strategy_start<F: PrimeField>(
     state: &[F],
     i: usize,
-    cfg: &Poseidon,
+    cfg: &Poseidon<F>,
 ) -> Vec<F> {
     let t = state.len();
     let c = &cfg.constants.c[t-2];
     let m = &cfg.constants.m[t-2];
     let n_rounds_f = cfg.constants.n_rounds_f;
     let n_rounds_p = cfg.constants.n_rounds_p[t-2];
+    let alpha = cfg.constants.alpha;
     let mut state = state.to_vec();
 
     ark(&mut state, c, t*i);
-    sbox(n_rounds_f, n_rounds_p, &mut state, i);
+    sbox(alpha, n_rounds_f, n_rounds_p, &mut state, i);
     state = mix(&state, m);
     ark(&mut state, c, t*(i+1)); // The head of i+1-st round.
 
     state
 }
 
-pub fn poseidon_mixed_strategy_mid(
+pub fn poseidon_mixed_strategy_mid<F: PrimeField>(
     state: &[F],
     i: usize,
-    cfg: &Poseidon,
+    cfg: &Poseidon<F>,
 ) -> Vec<F> {
     let t = state.len();
     let c = &cfg.constants.c[t-2];
     let m = &cfg.constants.m[t-2];
     let n_rounds_f = cfg.constants.n_rounds_f;
     let n_rounds_p = cfg.constants.n_rounds_p[t-2];
+    let alpha = cfg.constants.alpha;
     let mut state = state.to_vec();
 
     
-    sbox(n_rounds_f, n_rounds_p, &mut state, i);
+    sbox(alpha, n_rounds_f, n_rounds_p, &mut state, i);
     state = mix(&state, m);
     ark(&mut state, c, t*(i+1)); // The head of i+1-st round.
-    sbox(n_rounds_f, n_rounds_p, &mut state, i+1);
+    sbox(alpha, n_rounds_f, n_rounds_p, &mut state, i+1);
 
     state
 }
 
-pub fn poseidon_mixed_strategy_end(
+pub fn poseidon_mixed_strategy_end<F: PrimeField>(
     state: &[F],
     i: usize,
-    cfg: &Poseidon,
+    cfg: &Poseidon<F>,
 ) -> Vec<F> {
     let t = state.len();
     let c = &cfg.constants.c[t-2];
     let m = &cfg.constants.m[t-2];
     let n_rounds_f = cfg.constants.n_rounds_f;
     let n_rounds_p = cfg.constants.n_rounds_p[t-2];
+    let alpha = cfg.constants.alpha;
     let mut state = state.to_vec();
 
     state = mix(&state, m); // The tail of i-1 st round.
     ark(&mut state, c, t*i);
-    sbox(n_rounds_f, n_rounds_p, &mut state, i);
+    sbox(alpha, n_rounds_f, n_rounds_p, &mut state, i);
     mix(&state, m) // Ends in i-th round
 }
 
-pub fn poseidon_mixed_strategy_full_rounds_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon, round: usize, state: Vec<Variable>, is_first_part:bool) -> Vec<Variable>{
+pub fn poseidon_mixed_strategy_full_rounds_gadget<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, round: usize, state: Vec<Variable>, is_first_part:bool) -> Vec<Variable>{
     let t = if is_first_part {state.len() + 1} else {state.len()};
     
+    let alpha = cfg.constants.alpha as usize;
     let n_rounds_f = cfg.constants.n_rounds_f;
     let n_rounds_p = cfg.constants.n_rounds_p[t-2];
 
-    assert!(n_rounds_f == 8, "This should never fail.");
+    assert!(n_rounds_f == 8, "Mixed strategy is only implemented for 8 full rounds.");
     
     let i = if is_first_part {0} else {n_rounds_f/2 + n_rounds_p};
     
     let mut state = if is_first_part {
         circuit.apply(round,
             PolyOp::new(
-                5,
+                alpha,
                 t-1,
                 t,
                 move |inp, _| {
@@ -303,7 +334,7 @@ pub fn poseidon_mixed_strategy_full_rounds_gadget<'a>(circuit: &mut Circuit<'a,
     } else {
         circuit.apply(round,
             PolyOp::new(
-                5,
+                alpha,
                 t,
                 t,
                 move |state, _| {
@@ -317,7 +348,7 @@ pub fn poseidon_mixed_strategy_full_rounds_gadget<'a>(circuit: &mut Circuit<'a,
     state = circuit.apply(
         round,
         PolyOp::new(
-            25,
+            alpha*alpha,
             t,
             t,
             move |state, _| poseidon_mixed_strategy_mid(state, i + 1, cfg)
@@ -328,7 +359,7 @@ pub fn poseidon_mixed_strategy_full_rounds_gadget<'a>(circuit: &mut Circuit<'a,
     circuit.apply(
         round,
         PolyOp::new(
-            5,
+            alpha,
             t,
             t,
             move |state, _| poseidon_mixed_strategy_end(state, i + 3, cfg)
@@ -337,7 +368,7 @@ pub fn poseidon_mixed_strategy_full_rounds_gadget<'a>(circuit: &mut Circuit<'a,
     )
 }
 
-pub fn poseidon_gadget_internal<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon, k: usize, round: usize, inp: Vec<Variable>) -> Variable {
+pub fn poseidon_gadget_internal<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, k: usize, round: usize, inp: Vec<Variable>) -> Variable {
     let t = inp.len()+1;
     if inp.is_empty() || inp.len() > cfg.constants.n_rounds_p.len() {
         panic!("Wrong inputs length");
@@ -354,7 +385,7 @@ pub fn poseidon_gadget_internal<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
     state[0]
 }
 
-pub fn poseidon_gadget_mixstrat<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon, round: usize, inp: Vec<Variable>) -> Variable {
+pub fn poseidon_gadget_mixstrat<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, round: usize, inp: Vec<Variable>) -> Variable {
     let mut state = poseidon_mixed_strategy_full_rounds_gadget(circuit, cfg, round, inp, true);
     state = poseidon_partial_rounds_gadget(circuit, cfg, state, round);
     state = poseidon_mixed_strategy_full_rounds_gadget(circuit, cfg, round, state, false);
@@ -363,7 +394,7 @@ pub fn poseidon_gadget_mixstrat<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
 
 /// Hashes an array with some rate. Recommended rate is (allegedly) around 10; need to check whether evaluation of
 /// linear matrices becomes too slow (might also explore Neptune strategy, which is very similar to what we are doing).
-pub fn poseidon_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon, round: usize, rate: usize, inp: &[Variable]) -> Variable {
+pub fn poseidon_gadget<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, round: usize, rate: usize, inp: &[Variable]) -> Variable {
     let k = 1;
     let l = inp.len();
     assert!(l>0, "Can not hash empty array without padding.");
@@ -382,4 +413,170 @@ pub fn poseidon_gadget<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a
     }
 
     to_hash[0]
-}
\ No newline at end of file
+}
+
+/// Element of the sponge state: a constant plus a sum of variables.
+type SpongeCell<F> = (F, Vec<Variable>);
+
+/// Applies the permutation to a state given by constants and sums of variables. These are folded into the first
+/// polynomial operation, so absorbing costs no witness. k must divide the amount of full rounds in a half.
+fn poseidon_permutation_gadget<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, cfg: &'a Poseidon<F>, k: usize, round: usize, state: &[SpongeCell<F>]) -> Vec<Variable> {
+    let t = state.len();
+    let alpha = cfg.constants.alpha;
+    let n_rounds_f = cfg.constants.n_rounds_f;
+    let n_rounds_p = cfg.constants.n_rounds_p[t - 2];
+
+    assert!(k > 0 && (n_rounds_f/2) % k == 0, "k must divide the amount of full rounds in a half.");
+
+    let consts = state.iter().map(|(c, _)| *c).collect_vec();
+    let sizes = state.iter().map(|(_, vars)| vars.len()).collect_vec();
+    let inp = state.iter().flat_map(|(_, vars)| vars.iter().cloned()).collect_vec();
+
+    let mut state = circuit.apply(
+        round,
+        PolyOp::new(
+            pow(alpha as usize, k),
+            inp.len(),
+            t,
+            move |inp, _| {
+                let mut inp = inp.iter();
+                let state = consts.iter().zip_eq(sizes.iter())
+                    .map(|(c, size)| inp.by_ref().take(*size).fold(*c, |acc, x| acc + x))
+                    .collect_vec();
+                poseidon_kround_poly(alpha, k, &state, 0, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
+            }
+        ),
+        inp,
+    );
+
+    for i in (k..n_rounds_f/2).step_by(k) {
+        state = circuit.apply(
+            round,
+            PolyOp::new(
+                pow(alpha as usize, k),
+                t,
+                t,
+                move |inp, _| {
+                    poseidon_kround_poly(alpha, k, inp, i, &cfg.constants.c[t-2], &cfg.constants.m[t-2], n_rounds_f, n_rounds_p, t)
+                }
+            ),
+            state,
+        );
+    }
+
+    state = poseidon_partial_rounds_gadget(circuit, cfg, state, round);
+    poseidon_full_rounds_gadget(circuit, cfg, k, round, state, n_rounds_f/2 + n_rounds_p, n_rounds_f + n_rounds_p)
+}
+
+/// In-circuit counterpart of `PoseidonSponge`, producing exactly the same outputs. Absorbed variables are accumulated
+/// in the state and only enter the circuit with the next permutation, which costs t n_rounds_f / k + n_rounds_p + t
+/// witnesses. Requires circuit degree at least alpha^k.
+pub struct PoseidonSpongeGadget<'a, F: PrimeField+FieldUtils> {
+    cfg: &'a Poseidon<F>,
+    k: usize,
+    round: usize,
+    state: Vec<SpongeCell<F>>,
+    mode: SpongeMode,
+}
+
+impl<'a, F: PrimeField+FieldUtils> PoseidonSpongeGadget<'a, F> {
+    pub fn new(cfg: &'a Poseidon<F>, rate: usize, domain: F, k: usize, round: usize) -> Self {
+        assert!(rate > 0 && rate <= cfg.constants.n_rounds_p.len(), "Unsupported rate {}", rate);
+        let mut state = vec![(F::ZERO, vec![]); rate + 1];
+        state[0].0 = domain;
+        Self { cfg, k, round, state, mode: SpongeMode::Absorbing(0) }
+    }
+
+    pub fn rate(&self) -> usize {
+        self.state.len() - 1
+    }
+
+    pub fn mode(&self) -> SpongeMode {
+        self.mode
+    }
+
+    /// Permutes the state. If it does not depend on any variables, it is permuted natively.
+    fn permute(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>) {
+        if self.state.iter().all(|(_, vars)| vars.is_empty()) {
+            let mut state = self.state.iter().map(|(c, _)| *c).collect_vec();
+            self.cfg.permute(&mut state);
+            self.state = state.into_iter().map(|c| (c, vec![])).collect();
+        } else {
+            let state = poseidon_permutation_gadget(circuit, self.cfg, self.k, self.round, &self.state);
+            self.state = state.into_iter().map(|v| (F::ZERO, vec![v])).collect();
+        }
+    }
+
+    fn absorb_cell(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, c: F, var: Option<Variable>) {
+        let pos = match self.mode {
+            SpongeMode::Absorbing(pos) if pos == self.rate() => {
+                self.permute(circuit);
+                0
+            },
+            SpongeMode::Absorbing(pos) => pos,
+            SpongeMode::Squeezing(_) => 0,
+        };
+        let cell = &mut self.state[1 + pos];
+        cell.0 += c;
+        cell.1.extend(var);
+        self.mode = SpongeMode::Absorbing(pos + 1);
+    }
+
+    pub fn absorb(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, inp: &[Variable]) {
+        for x in inp {
+            assert!(x.round <= self.round, "Can not absorb a variable from a later round.");
+            self.absorb_cell(circuit, F::ZERO, Some(*x));
+        }
+    }
+
+    pub fn squeeze(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>) -> Variable {
+        let pos = match self.mode {
+            SpongeMode::Absorbing(_) => {
+                self.absorb_cell(circuit, F::ONE, None);
+                self.permute(circuit);
+                0
+            },
+            SpongeMode::Squeezing(pos) if pos == self.rate() => {
+                self.permute(circuit);
+                0
+            },
+            SpongeMode::Squeezing(pos) => pos,
+        };
+        self.mode = SpongeMode::Squeezing(pos + 1);
+
+        // Squeezed elements are outputs of the permutation, or constants if nothing was absorbed.
+        match &self.state[1 + pos] {
+            (c, vars) if vars.is_empty() => read_const_gadget(circuit, *c, self.round),
+            (_, vars) => vars[0],
+        }
+    }
+
+    pub fn squeeze_n(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, n: usize) -> Vec<Variable> {
+        (0..n).map(|_| self.squeeze(circuit)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2::halo2curves::bn256;
+
+    use crate::folding::poseidon::{load_constants, Poseidon};
+
+    use super::poseidon_partial_rounds_key;
+
+    #[test]
+    fn partial_rounds_key_depends_on_parameters() {
+        // separately allocated instances with the same constants share the gate
+        let cfg = Poseidon::new();
+        let copy = Poseidon::from_constants(load_constants());
+        assert_eq!(poseidon_partial_rounds_key(3, &cfg), poseidon_partial_rounds_key(3, &copy));
+        assert_ne!(poseidon_partial_rounds_key(3, &cfg), poseidon_partial_rounds_key(4, &cfg));
+
+        let a = Poseidon::<bn256::Fq>::generate(5, 8, vec![56, 57]);
+        let b = Poseidon::<bn256::Fq>::generate(5, 8, vec![56, 58]);
+        let c = Poseidon::<bn256::Fq>::generate(7, 8, vec![56, 57]);
+        assert_eq!(poseidon_partial_rounds_key(2, &a), poseidon_partial_rounds_key(2, &b));
+        assert_ne!(poseidon_partial_rounds_key(3, &a), poseidon_partial_rounds_key(3, &b));
+        assert_ne!(poseidon_partial_rounds_key(2, &a), poseidon_partial_rounds_key(2, &c));
+    }
+}
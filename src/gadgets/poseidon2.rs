@@ -0,0 +1,332 @@
+// Poseidon2 gadget, following the strategies of the Poseidon gadget: external rounds are packed into polynomial
+// operations (k rounds of degree alpha^k, or the mixed strategy), and all internal rounds form a single constraint
+// of degree alpha with sbox outputs given as advice. The witness is the same as for Poseidon of the same width,
+// but internal rounds cost t multiplications instead of t^2, so the constraints are much cheaper to evaluate.
+
+use std::rc::Rc;
+use ff::PrimeField;
+use num_traits::pow;
+
+use crate::{
+    circuit::{Advice, Circuit, PolyOp},
+    constraint_system::Variable,
+    folding::poseidon2::{external_mix, Poseidon2, Poseidon2Constants},
+    gate::Gatebb,
+    utils::field_precomp::FieldUtils,
+};
+
+/// A polynomial operation executing k rounds of Poseidon2 starting from the i-th. The first one also applies the
+/// initial linear layer.
+pub fn poseidon2_kround_poly<F: PrimeField>(cfg: &Poseidon2Constants<F>, k: usize, state: &[F], i: usize) -> Vec<F> {
+    let mut state = state.to_vec();
+    if i == 0 {
+        external_mix(&mut state);
+    }
+    for j in i..i+k {
+        cfg.round(&mut state, j);
+    }
+    state
+}
+
+/// Constraint of all internal rounds, see poseidon_partial_rounds_constraint. Advices are sbox outputs for state[0].
+pub fn poseidon2_partial_rounds_constraint<F: PrimeField>(
+    cfg: &Poseidon2Constants<F>,
+    input_state: &[F],
+    output_state: &[F],
+    input_advices: &[F],
+    output_advices: &[F],
+) -> Vec<F> {
+    let start = cfg.n_rounds_f / 2;
+    let mut state = input_state.to_vec();
+    let mut ret = vec![];
+    for j in 0..cfg.n_rounds_p {
+        cfg.ark(&mut state, start + j);
+        ret.push(state[0].pow_vartime([cfg.alpha]) - output_advices[j]);
+        state[0] = input_advices[j];
+        cfg.mix(&mut state, start + j);
+    }
+
+    for j in 0..state.len() {
+        ret.push(state[j] - output_state[j]);
+    }
+
+    ret
+}
+
+/// Computes sbox outputs of all internal rounds, followed by the final state.
+pub fn poseidon2_partial_rounds_advice<F: PrimeField>(cfg: &Poseidon2Constants<F>, input_state: &[F]) -> Vec<F> {
+    let start = cfg.n_rounds_f / 2;
+    let mut state = input_state.to_vec();
+    let mut ret = vec![];
+    for j in 0..cfg.n_rounds_p {
+        cfg.ark(&mut state, start + j);
+        cfg.sbox(&mut state, start + j);
+        ret.push(state[0]);
+        cfg.mix(&mut state, start + j);
+    }
+    ret.extend(state);
+    ret
+}
+
+pub fn poseidon2_partial_rounds_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon2<F>,
+    inp: Vec<Variable>,
+    round: usize,
+) -> Vec<Variable> {
+    let constants: &'a Poseidon2Constants<F> = &cfg.constants;
+    let t = constants.t;
+    let n_rounds_p = constants.n_rounds_p;
+    assert!(inp.len() == t, "Wrong state width.");
+
+    let tmp = circuit.advice(
+        round,
+        Advice::new(t, n_rounds_p + t, move |input_state, _| poseidon2_partial_rounds_advice(constants, input_state)),
+        inp.clone(),
+    );
+
+    let (adv, out) = tmp.split_at(n_rounds_p);
+
+    // repeat intermediate values twice, then append input and output
+    let to_constrain: Vec<Variable> = adv.iter().chain(adv.iter()).chain(inp.iter()).chain(out.iter()).cloned().collect();
+
+    let gate = Gatebb::new(
+        constants.alpha as usize,
+        2*n_rounds_p + 2*t,
+        n_rounds_p + t,
+        Rc::new(move |args, _| {
+            let (tmp, io) = args.split_at(2*n_rounds_p);
+            let (adv_in, adv_out) = tmp.split_at(n_rounds_p);
+            let (inp, out) = io.split_at(t);
+            poseidon2_partial_rounds_constraint(constants, inp, out, adv_in, adv_out)
+        }),
+        vec![],
+    );
+
+    circuit.constrain(&to_constrain, gate);
+
+    out.to_vec()
+}
+
+/// Executes external rounds from start to finish, k rounds per polynomial operation.
+pub fn poseidon2_full_rounds_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon2<F>,
+    k: usize,
+    round: usize,
+    inp: Vec<Variable>,
+    start: usize,
+    finish: usize,
+) -> Vec<Variable> {
+    let constants: &'a Poseidon2Constants<F> = &cfg.constants;
+    let t = constants.t;
+    let n_rounds_f = constants.n_rounds_f;
+    let n_rounds_p = constants.n_rounds_p;
+
+    assert!(inp.len() == t, "Wrong state width.");
+    assert!(k > 0 && start < finish, "Must give positive range.");
+    assert!(finish <= n_rounds_f/2 || start >= n_rounds_f/2 + n_rounds_p, "Range intersects internal rounds region.");
+
+    let mut state = inp;
+    let mut i = start;
+    while i < finish {
+        let step = k.min(finish - i);
+        state = circuit.apply(
+            round,
+            PolyOp::new(
+                pow(constants.alpha as usize, step),
+                t,
+                t,
+                move |inp, _| poseidon2_kround_poly(constants, step, inp, i),
+            ),
+            state,
+        );
+        i += step;
+    }
+
+    state
+}
+
+/// Round i followed by the round constants of the next one.
+pub fn poseidon2_mixed_strategy_start<F: PrimeField>(cfg: &Poseidon2Constants<F>, state: &[F], i: usize) -> Vec<F> {
+    let mut state = state.to_vec();
+    if i == 0 {
+        external_mix(&mut state);
+    }
+    cfg.round(&mut state, i);
+    cfg.ark(&mut state, i + 1); // The head of i+1-st round.
+    state
+}
+
+pub fn poseidon2_mixed_strategy_mid<F: PrimeField>(cfg: &Poseidon2Constants<F>, state: &[F], i: usize) -> Vec<F> {
+    let mut state = state.to_vec();
+    cfg.sbox(&mut state, i);
+    cfg.mix(&mut state, i);
+    cfg.ark(&mut state, i + 1); // The head of i+1-st round.
+    cfg.sbox(&mut state, i + 1);
+    state
+}
+
+pub fn poseidon2_mixed_strategy_end<F: PrimeField>(cfg: &Poseidon2Constants<F>, state: &[F], i: usize) -> Vec<F> {
+    let mut state = state.to_vec();
+    cfg.mix(&mut state, i - 1); // The tail of i-1 st round.
+    cfg.round(&mut state, i); // Ends in i-th round
+    state
+}
+
+/// Executes 4 external rounds by polynomial operations of degrees alpha, alpha^2 and alpha, see
+/// poseidon_mixed_strategy_full_rounds_gadget.
+pub fn poseidon2_mixed_strategy_full_rounds_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon2<F>,
+    round: usize,
+    state: Vec<Variable>,
+    is_first_part: bool,
+) -> Vec<Variable> {
+    let constants: &'a Poseidon2Constants<F> = &cfg.constants;
+    let t = constants.t;
+    let alpha = constants.alpha as usize;
+
+    assert!(state.len() == t, "Wrong state width.");
+    assert!(constants.n_rounds_f == 8, "Mixed strategy is only implemented for 8 full rounds.");
+
+    let i = if is_first_part {0} else {constants.n_rounds_f/2 + constants.n_rounds_p};
+
+    let state = circuit.apply(
+        round,
+        PolyOp::new(alpha, t, t, move |state, _| poseidon2_mixed_strategy_start(constants, state, i)),
+        state,
+    );
+    let state = circuit.apply(
+        round,
+        PolyOp::new(alpha*alpha, t, t, move |state, _| poseidon2_mixed_strategy_mid(constants, state, i + 1)),
+        state,
+    );
+    circuit.apply(
+        round,
+        PolyOp::new(alpha, t, t, move |state, _| poseidon2_mixed_strategy_end(constants, state, i + 3)),
+        state,
+    )
+}
+
+/// Applies the permutation to a state of width t, packing k external rounds into a polynomial operation.
+pub fn poseidon2_permutation_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon2<F>,
+    k: usize,
+    round: usize,
+    state: Vec<Variable>,
+) -> Vec<Variable> {
+    let n_rounds_f = cfg.constants.n_rounds_f;
+    let n_rounds_p = cfg.constants.n_rounds_p;
+
+    let mut state = poseidon2_full_rounds_gadget(circuit, cfg, k, round, state, 0, n_rounds_f/2);
+    state = poseidon2_partial_rounds_gadget(circuit, cfg, state, round);
+    poseidon2_full_rounds_gadget(circuit, cfg, k, round, state, n_rounds_f/2 + n_rounds_p, n_rounds_f + n_rounds_p)
+}
+
+pub fn poseidon2_permutation_gadget_mixstrat<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon2<F>,
+    round: usize,
+    state: Vec<Variable>,
+) -> Vec<Variable> {
+    let mut state = poseidon2_mixed_strategy_full_rounds_gadget(circuit, cfg, round, state, true);
+    state = poseidon2_partial_rounds_gadget(circuit, cfg, state, round);
+    poseidon2_mixed_strategy_full_rounds_gadget(circuit, cfg, round, state, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use itertools::Itertools;
+    use rand_core::OsRng;
+
+    use crate::{folding::poseidon::Poseidon, gadgets::{input::input, poseidon::{poseidon_gadget_internal, poseidon_gadget_mixstrat}}};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    /// Permutes a random state in the circuit, compares with the native permutation and returns the witness size.
+    fn check_permutation(cfg: &Poseidon2<F>, k: Option<usize>) -> usize {
+        let t = cfg.width();
+        let mut circuit = Circuit::new(k.map_or(25, |k| pow(5, k).max(25)), 1);
+        let ext = circuit.ext_val(t);
+        let state = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let ret = match k {
+            Some(k) => poseidon2_permutation_gadget(&mut circuit, cfg, k, 0, state),
+            None => poseidon2_permutation_gadget_mixstrat(&mut circuit, cfg, 0, state),
+        };
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let mut expected = (0..t).map(|_| F::random(OsRng)).collect_vec();
+        for (e, v) in ext.iter().zip_eq(expected.iter()) {
+            instance.set_ext(*e, *v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        cfg.permute(&mut expected);
+        assert_eq!(instance.cs.get_vars(&ret), expected);
+        instance.cs.wtns[0].privs.len()
+    }
+
+    #[test]
+    fn permutation() {
+        for t in [2, 3] {
+            let cfg = Poseidon2::new(t);
+            for k in [1, 2, 3] {
+                check_permutation(&cfg, Some(k));
+            }
+        }
+        let mu = (1..9).map(|x| F::from(x)).collect();
+        check_permutation(&Poseidon2::generate(8, 5, 8, 57, mu), Some(2));
+    }
+
+    #[test]
+    fn permutation_mixstrat() {
+        for t in [2, 3] {
+            check_permutation(&Poseidon2::new(t), None);
+        }
+    }
+
+    /// Witness of the Poseidon hash of arity t-1, by k rounds per operation or by the mixed strategy.
+    fn poseidon_witness_size(cfg: &Poseidon, t: usize, k: Option<usize>) -> usize {
+        let mut circuit = Circuit::new(25, 1);
+        let ext = circuit.ext_val(t - 1);
+        let inp = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        match k {
+            Some(k) => poseidon_gadget_internal(&mut circuit, cfg, k, 0, inp),
+            None => poseidon_gadget_mixstrat(&mut circuit, cfg, 0, inp),
+        };
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        for e in ext {
+            instance.set_ext(e, F::random(OsRng));
+        }
+        instance.execute(0);
+        instance.cs.wtns[0].privs.len()
+    }
+
+    /// Witness sizes of a single permutation, as listed in the README.
+    #[test]
+    fn witness_size() {
+        let poseidon = Poseidon::new();
+        let expected = [
+            (2, Some(1), 74, 74),
+            (2, Some(2), 66, 66),
+            (2, None, 70, 70),
+            (3, Some(1), 84, 83),
+            (3, Some(2), 72, 71),
+            (3, None, 78, 77),
+        ];
+        for (t, k, poseidon_size, poseidon2_size) in expected {
+            let poseidon2 = Poseidon2::new(t);
+            assert_eq!(poseidon_witness_size(&poseidon, t, k), poseidon_size, "Poseidon of width {}, k = {:?}", t, k);
+            assert_eq!(check_permutation(&poseidon2, k), poseidon2_size, "Poseidon2 of width {}, k = {:?}", t, k);
+        }
+    }
+}
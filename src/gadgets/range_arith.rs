@@ -0,0 +1,327 @@
+// Arithmetic of range-checked values with bound tracking. Every operation computes the range of its result, and fails
+// with RangeError if the result might not fit in the field, i.e. if the integer computation could wrap around.
+//
+// Optionally the context reduces values modulo the word W = base^limbs: once a range exceeds modulus / 2^headroom, the
+// value is decomposed into limbs and replaced by the lower ones. Note that reduction changes the values, so such a
+// context implements arithmetic modulo W (like machine words), in which the wraparound happens only in reductions.
+
+use ff::PrimeField;
+use itertools::Itertools;
+use num_bigint::BigUint;
+use num_traits::One;
+
+use crate::{
+    circuit::{Circuit, PolyOp},
+    gate::Gatebb,
+    utils::{field_precomp::FieldUtils, arith_helper::{modulus, from_biguint}},
+};
+
+use super::{
+    rangecheck_common::{VarRange, lc_uint, mul_uint, from_limbs},
+    rangecheck_lookup::{RangeLookup, limb_decompose_with_lookup_gadget},
+    rangecheck_small::limb_decompose_no_lookup_gadget,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeError {
+    /// Range of the result exceeds the field modulus.
+    Overflow{ range: BigUint },
+    /// Offset of a subtraction is less than the largest value of the subtrahend.
+    Underflow{ offset: BigUint, range: BigUint },
+}
+
+/// Range check used for limbs of reductions.
+pub enum Reducer<'r, F: PrimeField+FieldUtils> {
+    NoLookup{ base: u32 },
+    Lookup(&'r mut RangeLookup<F>),
+}
+
+impl<'r, F: PrimeField+FieldUtils> Reducer<'r, F> {
+    pub fn base(&self) -> u32 {
+        match self {
+            Reducer::NoLookup { base } => *base,
+            Reducer::Lookup(checker) => checker.range() as u32,
+        }
+    }
+}
+
+struct Reduction<'r, F: PrimeField+FieldUtils> {
+    reducer: Reducer<'r, F>,
+    limbs: usize,
+    /// Results with range above this are reduced.
+    threshold: BigUint,
+}
+
+pub struct RangeArith<'r, F: PrimeField+FieldUtils> {
+    round: usize,
+    reduction: Option<Reduction<'r, F>>,
+}
+
+/// Range of sum c_i x_i.
+fn lc_range(coeffs: &[BigUint], ranges: &[BigUint]) -> BigUint {
+    coeffs.iter().zip_eq(ranges.iter()).fold(BigUint::one(), |acc, (c, r)| acc + c * (r - 1u8))
+}
+
+fn check_range<F: PrimeField>(range: BigUint) -> Result<BigUint, RangeError> {
+    match range <= modulus::<F>() {
+        true => Ok(range),
+        false => Err(RangeError::Overflow { range }),
+    }
+}
+
+impl<'r, F: PrimeField+FieldUtils> RangeArith<'r, F> {
+    /// Context without reductions, every operation either fits in the field or fails.
+    pub fn new(round: usize) -> Self {
+        Self { round, reduction: None }
+    }
+
+    /// Context reducing values modulo reducer.base()^limbs once their range exceeds modulus / 2^headroom_bits.
+    pub fn with_reduction(round: usize, reducer: Reducer<'r, F>, limbs: usize, headroom_bits: usize) -> Self {
+        assert!(limbs > 0);
+        assert!(BigUint::from(reducer.base()).pow(limbs as u32) < modulus::<F>(), "Word does not fit in the field.");
+        let threshold = modulus::<F>() >> headroom_bits;
+        Self { round, reduction: Some(Reduction { reducer, limbs, threshold }) }
+    }
+
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    /// The word modulo which the values are reduced, if any.
+    pub fn word(&self) -> Option<BigUint> {
+        self.reduction.as_ref().map(|r| BigUint::from(r.reducer.base()).pow(r.limbs as u32))
+    }
+
+    /// Replaces x by x mod W. The quotient is range-checked by the limbs above the word, so the decomposition
+    /// is unique as long as it fits in the field.
+    pub fn reduce<'a>(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, x: &VarRange<F>) -> Result<VarRange<F>, RangeError> {
+        let round = self.round;
+        let reduction = self.reduction.as_mut().expect("Reduction is not configured.");
+        let base = BigUint::from(reduction.reducer.base());
+
+        let mut num_limbs = reduction.limbs;
+        let mut bound = base.pow(num_limbs as u32);
+        while bound < x.range() {
+            num_limbs += 1;
+            bound *= &base;
+        }
+        check_range::<F>(bound)?;
+
+        let limbs = match &mut reduction.reducer {
+            Reducer::NoLookup { base } => limb_decompose_no_lookup_gadget(circuit, *base, round, num_limbs, x.var()),
+            Reducer::Lookup(checker) => limb_decompose_with_lookup_gadget(circuit, round, num_limbs, checker, x.var()),
+        };
+        let lower = &limbs[..reduction.limbs];
+        Ok(from_limbs(circuit, lower, &vec![base; lower.len()], round))
+    }
+
+    /// Reduces the value if its range is above the threshold.
+    fn normalize<'a>(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, x: VarRange<F>) -> Result<VarRange<F>, RangeError> {
+        match &self.reduction {
+            Some(r) if x.range() > r.threshold => self.reduce(circuit, &x),
+            _ => Ok(x),
+        }
+    }
+
+    /// Reduces the operands which exceed the word, if the range of the result computed by f would not fit otherwise.
+    fn fit_operands<'a>(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        vars: &[VarRange<F>],
+        f: impl Fn(&[BigUint]) -> BigUint,
+    ) -> Result<Vec<VarRange<F>>, RangeError> {
+        let range = f(&vars.iter().map(|x| x.range()).collect_vec());
+        let word = match self.word() {
+            Some(word) if range > modulus::<F>() => word,
+            _ => return check_range::<F>(range).map(|_| vars.to_vec()),
+        };
+        let vars = vars.iter().map(|x| match x.range() > word {
+            true => self.reduce(circuit, x),
+            false => Ok(x.clone()),
+        }).collect::<Result<Vec<_>, _>>()?;
+        check_range::<F>(f(&vars.iter().map(|x| x.range()).collect_vec()))?;
+        Ok(vars)
+    }
+
+    /// Computes sum c_i x_i with nonnegative coefficients.
+    pub fn lc<'a>(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        coeffs: &[BigUint],
+        vars: &[VarRange<F>],
+    ) -> Result<VarRange<F>, RangeError> {
+        let vars = self.fit_operands(circuit, vars, |ranges| lc_range(coeffs, ranges))?;
+        let ret = lc_uint(circuit, coeffs, &vars, self.round);
+        self.normalize(circuit, ret)
+    }
+
+    pub fn add<'a>(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &VarRange<F>, b: &VarRange<F>) -> Result<VarRange<F>, RangeError> {
+        self.lc(circuit, &[BigUint::one(), BigUint::one()], &[a.clone(), b.clone()])
+    }
+
+    pub fn scale<'a>(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &VarRange<F>, c: &BigUint) -> Result<VarRange<F>, RangeError> {
+        self.lc(circuit, &[c.clone()], &[a.clone()])
+    }
+
+    /// Computes a + offset - b, which is nonnegative for offset >= b.range() - 1. In a reducing context the offset
+    /// should be a multiple of the word.
+    pub fn sub<'a>(
+        &mut self,
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        a: &VarRange<F>,
+        b: &VarRange<F>,
+        offset: &BigUint,
+    ) -> Result<VarRange<F>, RangeError> {
+        if offset + 1u8 < b.range() {
+            return Err(RangeError::Underflow { offset: offset.clone(), range: b.range() })
+        }
+        let range = check_range::<F>(a.range() + offset)?;
+        let c = from_biguint::<F>(offset);
+        let ret = circuit.apply(self.round, PolyOp::new(1, 2, 1, move |args, _| {
+            vec![args[0] + c - args[1]]
+        }), vec![a.var(), b.var()])[0];
+        self.normalize(circuit, VarRange::new_unchecked(ret, range))
+    }
+
+    pub fn mul<'a>(&mut self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &VarRange<F>, b: &VarRange<F>) -> Result<VarRange<F>, RangeError> {
+        let vars = self.fit_operands(circuit, &[a.clone(), b.clone()], |ranges| {
+            (&ranges[0] - 1u8) * (&ranges[1] - 1u8) + 1u8
+        })?;
+        let ret = mul_uint(circuit, &vars[0], &vars[1], self.round);
+        self.normalize(circuit, ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::bn256;
+    use rand_core::{OsRng, RngCore};
+
+    use crate::{gadgets::{input::input, lookup::Lookup}, utils::arith_helper::to_biguint};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    /// Range checked inputs of 16 bits.
+    fn inputs<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, n: usize) -> (Vec<crate::circuit::ExternalValue<F>>, Vec<VarRange<F>>) {
+        let ext = circuit.ext_val(n);
+        let vars = ext.iter().map(|e| {
+            let v = input(circuit, *e, 0);
+            let limbs = limb_decompose_no_lookup_gadget(circuit, 16, 0, 4, v);
+            from_limbs(circuit, &limbs, &vec![BigUint::from(16u8); 4], 0)
+        }).collect_vec();
+        (ext, vars)
+    }
+
+    #[test]
+    fn bounds_without_reduction() {
+        let mut circuit = Circuit::new(16, 1);
+        let (ext, x) = inputs(&mut circuit, 3);
+        let mut arith = RangeArith::new(0);
+
+        let sum = arith.add(&mut circuit, &x[0], &x[1]).unwrap();
+        assert_eq!(sum.range(), BigUint::from(2 * 65535 + 1u32));
+        let diff = arith.sub(&mut circuit, &x[0], &x[2], &BigUint::from(65535u32)).unwrap();
+        assert_eq!(diff.range(), BigUint::from(2 * 65535 + 1u32));
+        assert!(matches!(arith.sub(&mut circuit, &x[0], &x[2], &BigUint::from(100u32)), Err(RangeError::Underflow { .. })));
+        let prod = arith.mul(&mut circuit, &sum, &x[2]).unwrap();
+        let scaled = arith.scale(&mut circuit, &prod, &BigUint::from(3u8)).unwrap();
+
+        // (2^16)^16 exceeds 254 bits
+        let mut acc = x[0].clone();
+        let mut err = None;
+        for _ in 0..16 {
+            match arith.mul(&mut circuit, &acc, &x[1]) {
+                Ok(v) => acc = v,
+                Err(e) => {err = Some(e); break},
+            }
+        }
+        assert!(matches!(err, Some(RangeError::Overflow { .. })));
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let values = (0..3).map(|_| OsRng.next_u32() as u64 % 65536).collect_vec();
+        for (e, v) in ext.iter().zip_eq(values.iter()) {
+            instance.set_ext(*e, F::from(*v));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+        assert_eq!(instance.cs.getvar(diff.var()), F::from(values[0] + 65535 - values[2]));
+        assert_eq!(instance.cs.getvar(scaled.var()), F::from(3 * (values[0] + values[1]) * values[2]));
+    }
+
+    #[test]
+    fn reduction_modulo_word() {
+        let mut circuit = Circuit::new(16, 1);
+        let (ext, x) = inputs(&mut circuit, 2);
+        // words of 64 bits, reduced once above 2^200
+        let mut arith = RangeArith::with_reduction(0, Reducer::NoLookup { base: 16 }, 16, 54);
+        let word = arith.word().unwrap();
+        assert_eq!(word, BigUint::from(2u8).pow(64));
+
+        let mut acc = x[0].clone();
+        for _ in 0..40 {
+            acc = arith.mul(&mut circuit, &acc, &x[1]).unwrap();
+            assert!(acc.range() <= modulus::<F>() >> 54);
+        }
+        let sum = arith.add(&mut circuit, &acc, &x[0]).unwrap();
+        let reduced = arith.reduce(&mut circuit, &sum).unwrap();
+        assert_eq!(reduced.range(), word);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let (a, b) = (F::random(OsRng), F::random(OsRng));
+        let (a, b) = (to_biguint(a) % 65536u32, to_biguint(b) % 65536u32);
+        instance.set_ext(ext[0], from_biguint(&a));
+        instance.set_ext(ext[1], from_biguint(&b));
+        instance.execute(0);
+        instance.valid_witness();
+        let expected = (&a * b.pow(40) + &a) % &word;
+        assert_eq!(to_biguint(instance.cs.getvar(reduced.var())), expected);
+    }
+
+    #[test]
+    fn lookup_reduction_of_operands() {
+        let mut circuit = Circuit::new(16, 2);
+        let challenge = circuit.ext_val(1)[0];
+        let (ext, x) = inputs(&mut circuit, 2);
+        let mut checker = RangeLookup::new(challenge, 256);
+
+        let (prod, word) = {
+            // words of 64 bits, reduced only above 2^253
+            let mut arith = RangeArith::with_reduction(0, Reducer::Lookup(&mut checker), 8, 1);
+            let word = arith.word().unwrap();
+            let mut a = x[0].clone();
+            let mut b = x[1].clone();
+            for _ in 0..7 {
+                a = arith.mul(&mut circuit, &a, &x[0]).unwrap();
+            }
+            for _ in 0..8 {
+                b = arith.mul(&mut circuit, &b, &x[1]).unwrap();
+            }
+            // operands are above the word, but below the threshold
+            assert!(a.range() > word && b.range() > word);
+            assert!(a.range() * b.range() > modulus::<F>());
+
+            // so both are reduced before the multiplication
+            let prod = arith.mul(&mut circuit, &a, &b).unwrap();
+            assert_eq!(prod.range(), (&word - 1u8) * (&word - 1u8) + 1u8);
+            (prod, word)
+        };
+        checker.finalize(&mut circuit, 0, 0, 1, 2);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let (a, b) = (OsRng.next_u32() as u64 % 65536, OsRng.next_u32() as u64 % 65536);
+        instance.set_ext(ext[0], F::from(a));
+        instance.set_ext(ext[1], F::from(b));
+        instance.set_ext(challenge, F::random(OsRng));
+        instance.execute(0);
+        instance.execute(1);
+        instance.valid_witness();
+        let expected = (BigUint::from(a).pow(8) % &word) * (BigUint::from(b).pow(9) % &word);
+        assert_eq!(to_biguint(instance.cs.getvar(prod.var())), expected);
+    }
+}
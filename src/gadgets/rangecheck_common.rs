@@ -124,7 +124,7 @@ pub fn limbs<F: PrimeField>(x: F, base: u32) ->  Vec<u32>{
     loop {
         let y = x.clone()%base;
         x = x/base;
-        ret.push(y.to_u32_digits()[0]);
+        ret.push(y.to_u32_digits().first().copied().unwrap_or(0));
         if x==BigUint::from(0 as u64) {break}
     }
     ret
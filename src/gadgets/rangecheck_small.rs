@@ -53,7 +53,8 @@ pub fn lagrange_choice<F: PrimeField+FieldUtils>(x: F, t: u64, range: u64) -> F{
     ret * F::inv_lagrange_prod(t, range)
 }
 
-/// Returns the vector of values of lagrange interpolation polynomials on the set 0..n.
+/// Returns the vector of values of lagrange interpolation polynomials on the set 0..n, for any n > 0.
+/// For arbitrary domains, see poly_utils::lagrange_basis.
 pub fn lagrange_choice_batched<F: PrimeField+FieldUtils>(x: F, n: u64) -> Vec<F> {
     
     let ret = if n>3 { // General case
@@ -133,7 +134,9 @@ pub fn lagrange_choice_batched<F: PrimeField+FieldUtils>(x: F, n: u64) -> Vec<F>
         vec![x1*x2, x*x2, x*x1]
     } else if n == 2 {
         vec![x-F::from(1), x]
-    } else {panic!()};
+    } else if n == 1 {
+        vec![F::ONE]
+    } else {panic!("Lagrange basis of the empty domain")};
 
     ret.iter().enumerate().map(|(i, val)| *val*F::inv_lagrange_prod(i as u64, n)).collect()
 }
@@ -0,0 +1,292 @@
+// Schnorr signature verification, see folding::schnorr for the scheme. The scalar s lives in the scalar field of the
+// curve, which may be larger than the native field, so it is given as two halves s = s_lo + 2^128 s_hi, and the check
+// s G = R + e pk becomes a single MSM s_lo G + s_hi (2^128 G) + e (-pk) = R.
+//
+// The challenge is the low part of the hash h = e + 2^128 t. The decomposition must be canonical, otherwise another
+// challenge would be accepted for the same hash. Both e < 2^128 and t < [p / 2^128] are range checked, so the sum
+// does not wrap around and the decomposition is unique. Honest prover fails only if h is within 2^128 from p.
+
+use std::rc::Rc;
+
+use ff::PrimeField;
+use group::Group;
+use halo2::halo2curves::CurveExt;
+use num_bigint::BigUint;
+use num_traits::One;
+
+use crate::{
+    circuit::{Circuit, Advice, PolyOp},
+    constraint_system::Variable,
+    folding::{poseidon::Poseidon, schnorr::CHALLENGE_BITS},
+    gate::Gatebb,
+    utils::{field_precomp::FieldUtils, arith_helper::{j2a, modulus, to_biguint, from_biguint}},
+};
+
+use super::{
+    arith::{eq_gadget, read_const_gadget},
+    ecmul::{EcAffinePoint, Window, ec_table_gadget, ecmsm_tables_gadget, num_limbs_for_bits},
+    nonzero_check::Nonzeros,
+    poseidon::poseidon_gadget_internal,
+    rangecheck_small::limb_decompose_no_lookup_gadget,
+};
+
+#[derive(Clone, Copy)]
+pub struct SchnorrSignatureVar<F: PrimeField+FieldUtils, C: CurveExt<Base=F>> {
+    pub r: EcAffinePoint<F, C>,
+    pub s_lo: Variable,
+    pub s_hi: Variable,
+}
+
+/// Amount of limbs of the scalars in the MSM. Offset points of the verification must satisfy
+/// b + (1+B+B^2+...B^{n-1}) (a_1 + a_2 + a_3) = 0 for this n.
+pub fn schnorr_num_limbs(window: &Window) -> usize {
+    num_limbs_for_bits(window.base(), CHALLENGE_BITS)
+}
+
+/// Checks that x < bound, because both x and x + B^k - bound are below B^k.
+fn bounded_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    x: Variable,
+    bound: &BigUint,
+    base: u32,
+    round: usize,
+) {
+    let num_limbs = num_limbs_for_bits(base, bound.bits() as usize);
+    let shift = from_biguint::<F>(&(BigUint::from(base).pow(num_limbs as u32) - bound));
+    let shifted = circuit.apply(round, PolyOp::new(1, 1, 1, move |args, _| vec![args[0] + shift]), vec![x])[0];
+    limb_decompose_no_lookup_gadget(circuit, base, round, num_limbs, x);
+    limb_decompose_no_lookup_gadget(circuit, base, round, num_limbs, shifted);
+}
+
+/// Constrains h = e + 2^128 t to be the canonical decomposition of the hash.
+fn challenge_split_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    h: Variable,
+    e: Variable,
+    t: Variable,
+    base: u32,
+    round: usize,
+) {
+    let scale = from_biguint::<F>(&(BigUint::one() << CHALLENGE_BITS));
+    circuit.constrain(&[h, e, t], Gatebb::new(1, 3, 1, Rc::new(move |args, _| {
+        vec![args[1] + args[2] * scale - args[0]]
+    }), vec![]));
+
+    bounded_gadget(circuit, e, &(BigUint::one() << CHALLENGE_BITS), base, round);
+    bounded_gadget(circuit, t, &(modulus::<F>() >> CHALLENGE_BITS), base, round);
+}
+
+/// Computes the challenge from the hash of the signature nonce, the public key and the message.
+fn challenge_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon<F>,
+    r: EcAffinePoint<F, C>,
+    pk: EcAffinePoint<F, C>,
+    msg: Variable,
+    window: &Window,
+    round: usize,
+) -> Variable {
+    let h = poseidon_gadget_internal(circuit, cfg, 1, round, vec![r.x, r.y, pk.x, pk.y, msg]);
+    let split = circuit.advice(round, Advice::new(1, 2, |args, _| {
+        let h = to_biguint(args[0]);
+        let mask = (BigUint::one() << CHALLENGE_BITS) - BigUint::one();
+        vec![from_biguint(&(&h & mask)), from_biguint(&(h >> CHALLENGE_BITS))]
+    }), vec![h]);
+    let (e, t) = (split[0], split[1]);
+
+    challenge_split_gadget(circuit, h, e, t, window.base(), round);
+
+    e
+}
+
+/// Verifies a batch of signatures. Generator tables and the nonzero checks are shared by all signatures,
+/// and every signature costs its challenge, the table of its public key and a single MSM.
+/// Offset points a (for G, 2^128 G and -pk respectively) and b are common for the batch, see schnorr_num_limbs.
+/// The circuit must allow degree 5 for Poseidon, and the degree required by the window.
+pub fn schnorr_batch_verify_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon<F>,
+    pks: &[EcAffinePoint<F, C>],
+    msgs: &[Variable],
+    sigs: &[SchnorrSignatureVar<F, C>],
+    window: &Window,
+    round: usize,
+    a: &[EcAffinePoint<F, C>],
+    b: EcAffinePoint<F, C>,
+    nonzeros: &mut Nonzeros,
+) {
+    assert!(pks.len() == msgs.len() && sigs.len() == msgs.len());
+    assert!(a.len() == 3, "Expected offset points for G, 2^128 G and the public key.");
+    let base = window.base();
+    let num_limbs = schnorr_num_limbs(window);
+
+    let g = C::generator();
+    let gens = [g, g * from_biguint::<C::ScalarExt>(&(BigUint::one() << 128))].map(|pt| {
+        let (x, y) = j2a(pt.jacobian_coordinates());
+        let x = read_const_gadget(circuit, x, round);
+        let y = read_const_gadget(circuit, y, round);
+        EcAffinePoint::<F, C>::new_unchecked(x, y)
+    });
+    let g_table = ec_table_gadget(circuit, gens[0], a[0], base, round, nonzeros);
+    let h_table = ec_table_gadget(circuit, gens[1], a[1], base, round, nonzeros);
+
+    for ((pk, msg), sig) in pks.iter().zip(msgs.iter()).zip(sigs.iter()) {
+        let e = challenge_gadget(circuit, cfg, sig.r, *pk, *msg, window, round);
+        let neg_y = circuit.apply(round, PolyOp::new(1, 1, 1, |args: &[F], _| vec![-args[0]]), vec![pk.y])[0];
+        let neg_pk = EcAffinePoint::<F, C>::new_unchecked(pk.x, neg_y);
+        let pk_table = ec_table_gadget(circuit, neg_pk, a[2], base, round, nonzeros);
+
+        let tables = [g_table.clone(), h_table.clone(), pk_table];
+        let ret = ecmsm_tables_gadget(circuit, &[sig.s_lo, sig.s_hi, e], &tables, window, num_limbs, round, b, nonzeros);
+        eq_gadget(circuit, ret.x, sig.r.x);
+        eq_gadget(circuit, ret.y, sig.r.y);
+    }
+}
+
+/// Verifies a single signature, see schnorr_batch_verify_gadget.
+pub fn schnorr_verify_gadget<'a, F: PrimeField+FieldUtils, C: CurveExt<Base=F>>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    cfg: &'a Poseidon<F>,
+    pk: EcAffinePoint<F, C>,
+    msg: Variable,
+    sig: SchnorrSignatureVar<F, C>,
+    window: &Window,
+    round: usize,
+    a: &[EcAffinePoint<F, C>],
+    b: EcAffinePoint<F, C>,
+    nonzeros: &mut Nonzeros,
+) {
+    schnorr_batch_verify_gadget(circuit, cfg, &[pk], &[msg], &[sig], window, round, a, b, nonzeros)
+}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::{bn256, grumpkin};
+    use itertools::Itertools;
+    use rand_core::OsRng;
+
+    use crate::{folding::schnorr::{SigningKey, SchnorrSignature}, gadgets::{ecmul::offset_point, input::input}};
+
+    use super::*;
+
+    type F = bn256::Fr;
+    type C = grumpkin::G1;
+
+    fn point_values(pt: C) -> [F; 2] {
+        let (x, y) = j2a(pt.jacobian_coordinates());
+        [x, y]
+    }
+
+    /// Verifies signatures in a single circuit and returns the private witness size.
+    /// Every entry is (public key, message, signature).
+    fn check_batch(entries: &[(C, F, SchnorrSignature<C>)]) -> usize {
+        let cfg = Poseidon::new();
+        let window = Window::from_base(9, true).unwrap();
+        let n = entries.len();
+
+        let mut circuit = Circuit::new(10, 1);
+        let ext = circuit.ext_val(8 + 7*n);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let (offsets, rest) = vars.split_at(8);
+        let offsets = offsets.chunks(2).map(|v| EcAffinePoint::<F, C>::new(&mut circuit, v[0], v[1])).collect_vec();
+        let mut pks = vec![];
+        let mut msgs = vec![];
+        let mut sigs = vec![];
+        for v in rest.chunks(7) {
+            pks.push(EcAffinePoint::<F, C>::new(&mut circuit, v[0], v[1]));
+            msgs.push(v[2]);
+            let r = EcAffinePoint::<F, C>::new(&mut circuit, v[3], v[4]);
+            sigs.push(SchnorrSignatureVar { r, s_lo: v[5], s_hi: v[6] });
+        }
+
+        let mut nonzeros = Nonzeros::new(9);
+        schnorr_batch_verify_gadget(&mut circuit, &cfg, &pks, &msgs, &sigs, &window, 0, &offsets[..3], offsets[3], &mut nonzeros);
+        nonzeros.finalize(&mut circuit);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        let a = (0..3).map(|_| C::random(OsRng)).collect_vec();
+        let b = offset_point(a.iter().fold(C::identity(), |acc, x| acc + x), window.base(), schnorr_num_limbs(&window));
+        let mut values = a.iter().chain([&b]).flat_map(|pt| point_values(*pt)).collect_vec();
+        for (pk, msg, sig) in entries {
+            let (s_lo, s_hi) = sig.s_halves();
+            values.extend(point_values(*pk));
+            values.push(*msg);
+            values.extend(point_values(sig.r));
+            values.extend([s_lo, s_hi]);
+        }
+        for (e, v) in ext.iter().zip_eq(values) {
+            instance.set_ext(*e, v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+        let size = instance.cs.wtns[0].privs.len();
+        instance.finish();
+        size
+    }
+
+    fn random_entry() -> (C, F, SchnorrSignature<C>) {
+        let key = SigningKey::<C>::random(OsRng);
+        let msg = F::random(OsRng);
+        (key.public_key(), msg, key.sign(&Poseidon::new(), msg, OsRng))
+    }
+
+    #[test]
+    fn single_signature() {
+        assert_eq!(check_batch(&[random_entry()]), 1365);
+    }
+
+    #[test]
+    fn batch() {
+        assert_eq!(check_batch(&(0..3).map(|_| random_entry()).collect_vec()), 3983);
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrong_message() {
+        let (pk, msg, sig) = random_entry();
+        check_batch(&[random_entry(), (pk, msg + F::ONE, sig)]);
+    }
+
+    /// Splits the hash into (e, t) given as inputs, and checks the witness.
+    fn check_split(h: F, e: F, t: F) {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(3);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        challenge_split_gadget(&mut circuit, vars[0], vars[1], vars[2], 9, 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        for (e, v) in ext.iter().zip_eq([h, e, t]) {
+            instance.set_ext(*e, v);
+        }
+        instance.execute(0);
+        instance.valid_witness();
+    }
+
+    fn random_split() -> (F, F, F) {
+        let h = F::random(OsRng);
+        let hb = to_biguint(h);
+        let e = from_biguint(&(&hb & ((BigUint::one() << CHALLENGE_BITS) - BigUint::one())));
+        let t = from_biguint(&(hb >> CHALLENGE_BITS));
+        (h, e, t)
+    }
+
+    #[test]
+    fn canonical_split() {
+        let (h, e, t) = random_split();
+        check_split(h, e, t);
+    }
+
+    // e + 2^128 still fits in the limbs of the MSM, but not below 2^128: its shifted copy has too many limbs,
+    // so no witness satisfies the range check.
+    #[test]
+    #[should_panic(expected = "The value has too many limbs.")]
+    fn non_canonical_split() {
+        let (h, e, t) = random_split();
+        let shift = from_biguint::<F>(&(BigUint::one() << CHALLENGE_BITS));
+        check_split(h, e + shift, t - F::ONE);
+    }
+}
@@ -0,0 +1,199 @@
+// SHA-256 compression function and message padding (FIPS 180-4).
+// Ch and Maj are single degree 2 and degree 3 gates per bit, each Σ/σ is a degree 3 xor of rotations.
+// Additions of a round are merged: new a and e are computed by a single modular addition each.
+
+use ff::PrimeField;
+use itertools::Itertools;
+
+use crate::{circuit::Circuit, gate::Gatebb, utils::field_precomp::FieldUtils};
+
+use super::uint::{UInt32, UInt8, UInt};
+
+pub const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Padding appended to a message of a given length: 0x80, zeros, and the bit length as big-endian u64.
+pub fn sha256_padding(len: usize) -> Vec<u8> {
+    let mut ret = vec![0x80];
+    while (len + ret.len()) % 64 != 56 {
+        ret.push(0);
+    }
+    ret.extend(((len as u64) * 8).to_be_bytes());
+    ret
+}
+
+fn xor_rotations<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    x: &UInt32,
+    rotations: [usize; 3],
+    shift: bool,
+    round: usize,
+) -> UInt32 {
+    let a = x.rotate_right(rotations[0]);
+    let b = x.rotate_right(rotations[1]);
+    let c = if shift {x.shr(rotations[2])} else {x.rotate_right(rotations[2])};
+    UInt32::xor_many(circuit, &[&a, &b, &c], round)
+}
+
+/// Compresses a single 16 word block into the state.
+pub fn sha256_compress<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    state: &[UInt32],
+    block: &[UInt32],
+    round: usize,
+) -> Vec<UInt32> {
+    assert_eq!(state.len(), 8);
+    assert_eq!(block.len(), 16);
+    assert!(circuit.max_degree() >= 3, "SHA-256 gadget requires degree at least 3.");
+
+    let mut w = block.to_vec();
+    for t in 16..64 {
+        let s0 = xor_rotations(circuit, &w[t-15], [7, 18, 3], true, round);
+        let s1 = xor_rotations(circuit, &w[t-2], [17, 19, 10], true, round);
+        let next = UInt32::add_mod(circuit, &[&s1, &w[t-7], &s0, &w[t-16]], round);
+        w.push(next);
+    }
+
+    let mut v = state.to_vec();
+    for t in 0..64 {
+        let (a, b, c, d, e, f, g, h) = v.iter().collect_tuple().unwrap();
+        let s1 = xor_rotations(circuit, e, [6, 11, 25], false, round);
+        let ch = UInt32::bitwise(circuit, &[e, f, g], 2, |x| x[0] * x[1] + (F::ONE - x[0]) * x[2], round);
+        let s0 = xor_rotations(circuit, a, [2, 13, 22], false, round);
+        let maj = UInt32::bitwise(circuit, &[a, b, c], 3, |x| {
+            x[0] * x[1] + x[0] * x[2] + x[1] * x[2] - (x[0] * x[1] * x[2]).double()
+        }, round);
+        let k = UInt32::constant(K[t] as u64);
+
+        let new_e = UInt32::add_mod(circuit, &[d, h, &s1, &ch, &k, &w[t]], round);
+        let new_a = UInt32::add_mod(circuit, &[h, &s1, &ch, &k, &w[t], &s0, &maj], round);
+        v = vec![new_a, a.clone(), b.clone(), c.clone(), new_e, e.clone(), f.clone(), g.clone()];
+    }
+
+    state.iter().zip_eq(v.iter()).map(|(x, y)| UInt32::add_mod(circuit, &[x, y], round)).collect()
+}
+
+/// Big-endian word from 4 bytes.
+fn word_from_bytes(bytes: &[UInt8]) -> UInt32 {
+    UInt::from_bits(bytes.iter().rev().flat_map(|b| b.bits().iter().cloned()).collect())
+}
+
+/// SHA-256 of a message of fixed length. Returns the digest as 8 big-endian words.
+pub fn sha256<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    message: &[UInt8],
+    round: usize,
+) -> Vec<UInt32> {
+    let mut bytes = message.to_vec();
+    bytes.extend(sha256_padding(message.len()).into_iter().map(|b| UInt8::constant(b as u64)));
+
+    let mut state = IV.iter().map(|x| UInt32::constant(*x as u64)).collect_vec();
+    for block in bytes.chunks(64) {
+        let words = block.chunks(4).map(word_from_bytes).collect_vec();
+        state = sha256_compress(circuit, &state, &words, round);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2::halo2curves::bn256;
+    use rand_core::{OsRng, RngCore};
+    use sha2::{Sha256, Digest};
+
+    use crate::{circuit::Circuit, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    fn check_sha256(len: usize) {
+        let mut circuit = Circuit::new(3, 1);
+        let ext = circuit.ext_val(len);
+        let message = ext.iter().map(|e| {
+            let var = input(&mut circuit, *e, 0);
+            UInt8::from_var(&mut circuit, var, 0)
+        }).collect_vec();
+        let digest = sha256(&mut circuit, &message, 0);
+        let digest = digest.iter().map(|w| w.pack(&mut circuit, 0)).collect_vec();
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let mut data = vec![0u8; len];
+        OsRng.fill_bytes(&mut data);
+        for (e, b) in ext.iter().zip_eq(data.iter()) {
+            instance.set_ext(*e, F::from(*b as u64));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        let expected = Sha256::digest(&data);
+        for (var, word) in digest.iter().zip_eq(expected.chunks(4)) {
+            let word = u32::from_be_bytes(word.try_into().unwrap());
+            assert_eq!(instance.cs.getvar(*var), F::from(word as u64));
+        }
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(sha256_padding(0).len(), 64);
+        assert_eq!(sha256_padding(55).len(), 9);
+        assert_eq!(sha256_padding(56).len(), 72);
+        assert_eq!(sha256_padding(3)[58..], [0, 0, 24]);
+    }
+
+    #[test]
+    fn single_block() {
+        check_sha256(3);
+        check_sha256(55);
+    }
+
+    #[test]
+    fn two_blocks() {
+        check_sha256(80);
+    }
+
+    /// Witness size of the compression of several blocks, which consist of variables.
+    fn compression_witness_size(num_blocks: usize) -> usize {
+        let mut circuit = Circuit::new(3, 1);
+        let ext = circuit.ext_val(16 * num_blocks);
+        let words = ext.iter().map(|e| {
+            let var = input(&mut circuit, *e, 0);
+            UInt32::from_var(&mut circuit, var, 0)
+        }).collect_vec();
+
+        let mut state = IV.iter().map(|x| UInt32::constant(*x as u64)).collect_vec();
+        for block in words.chunks(16) {
+            state = sha256_compress(&mut circuit, &state, block, 0);
+        }
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        for e in ext.iter() {
+            instance.set_ext(*e, F::from(OsRng.next_u32() as u64));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+        instance.cs.wtns[0].privs.len()
+    }
+
+    /// Witness sizes of the first and the following block, as listed in the README.
+    /// Each input word additionally costs its 32 bits.
+    #[test]
+    fn witness_size() {
+        assert_eq!(compression_witness_size(1), 16 * 32 + 17696);
+        assert_eq!(compression_witness_size(2), 32 * 32 + 17696 + 17824);
+    }
+}
@@ -0,0 +1,146 @@
+// Sorting argument. Sorted records are given by an advice, and it is checked that they are a permutation of the
+// input records (see multiset_eq), and that their keys are nondecreasing. Keys are range-bounded values, so
+// next - prev >= 0 is checked by the decomposition of the difference into limbs.
+
+use ff::PrimeField;
+use itertools::Itertools;
+use num_bigint::BigUint;
+
+use crate::{
+    circuit::{Advice, Circuit, PolyOp},
+    constraint_system::Variable,
+    gate::Gatebb,
+    utils::{arith_helper::to_biguint, field_precomp::FieldUtils},
+};
+
+use super::{
+    compare::comparison_domain,
+    multiset::{multiset_eq, MultisetStrategy},
+    rangecheck_common::VarRange,
+    rangecheck_small::limb_decompose_no_lookup_gadget,
+};
+
+/// Key extractor taking the column of a record. The range of the column must be checked for the input records,
+/// sorted records are then in range too, as they are a permutation of the input ones.
+pub fn key_column<'a, F: PrimeField+FieldUtils>(
+    index: usize,
+    range: BigUint,
+) -> impl Fn(&mut Circuit<'a, F, Gatebb<'a, F>>, &[Variable]) -> VarRange<F> {
+    move |_, record| VarRange::new_unchecked(record[index], range.clone())
+}
+
+/// Returns the records sorted by key in nondecreasing order, with ties kept in the input order.
+///
+/// The key extractor is applied both to input and sorted records, and must bound the key by its range for every
+/// record which is a permutation of the input ones. Differences of adjacent keys are range-checked in given base.
+/// Sorted records are allocated in a given round, and challenges of the permutation argument in challenge_round.
+pub fn sort_gadget<'a, F: PrimeField+FieldUtils>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    records: &[Vec<Variable>],
+    key: impl Fn(&mut Circuit<'a, F, Gatebb<'a, F>>, &[Variable]) -> VarRange<F>,
+    base: u32,
+    round: usize,
+    challenge_round: usize,
+    strategy: MultisetStrategy,
+) -> Vec<Vec<Variable>> {
+    let n = records.len();
+    if n == 0 {
+        return vec![]
+    }
+    let width = records[0].len();
+    for record in records {
+        assert_eq!(record.len(), width, "All records must have the same width.");
+    }
+
+    let keys = records.iter().map(|r| key(circuit, r).var()).collect_vec();
+    let args = records.iter().flatten().cloned().chain(keys).collect_vec();
+    let sorted = circuit.advice(round, Advice::new(n * (width + 1), n * width, move |args: &[F], _| {
+        let (records, keys) = args.split_at(n * width);
+        let order = (0..n).sorted_by_key(|i| to_biguint(keys[*i])).collect_vec();
+        order.iter().flat_map(|i| records[i * width..(i + 1) * width].iter().cloned()).collect()
+    }), args);
+    let sorted = sorted.chunks(width).map(|r| r.to_vec()).collect_vec();
+
+    let sorted_keys = sorted.iter().map(|r| key(circuit, r)).collect_vec();
+    for (prev, next) in sorted_keys.iter().tuple_windows() {
+        let (num_limbs, _) = comparison_domain(prev, next, base);
+        let diff = circuit.apply(round, PolyOp::new(1, 2, 1, |args, _| {
+            vec![args[1] - args[0]]
+        }), vec![prev.var(), next.var()])[0];
+        limb_decompose_no_lookup_gadget(circuit, base, round, num_limbs, diff);
+    }
+
+    multiset_eq(circuit, records, &sorted, challenge_round, strategy);
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2::halo2curves::bn256;
+    use rand_core::{OsRng, RngCore};
+
+    use crate::{circuit::{ExternalValue, random_ck}, folding::{poseidon::Poseidon, oracle::{HashOracle, Oracle}}, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    /// Sorts random records of given width, whose values are below bound, and compares the result with the native
+    /// sort by the given key.
+    fn check<'a>(
+        width: usize,
+        len: usize,
+        bound: u64,
+        key: impl Fn(&mut Circuit<'a, F, Gatebb<'a, F>>, &[Variable]) -> VarRange<F>,
+        native_key: impl Fn(&[u64]) -> u64,
+    ) {
+        let mut circuit = Circuit::new(5, 2);
+        let ext: Vec<ExternalValue<F>> = circuit.ext_val(width * len);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let records = vars.chunks(width).map(|r| r.to_vec()).collect_vec();
+        let sorted = sort_gadget(&mut circuit, &records, key, 4, 0, 1, MultisetStrategy::LogDerivative { rate: 4 });
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+        let mut instance = constructed.spawn();
+        let mut values = (0..len).map(|_| (0..width).map(|_| OsRng.next_u64() % bound).collect_vec()).collect_vec();
+        for (e, v) in ext.iter().zip_eq(values.iter().flatten()) {
+            instance.set_ext(*e, F::from(*v));
+        }
+        instance.execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new());
+        instance.valid_witness();
+
+        values.sort_by_key(|r| native_key(r));
+        for (record, expected) in sorted.iter().zip_eq(values) {
+            assert_eq!(record.iter().map(|v| instance.cs.getvar(*v)).collect_vec(), expected.iter().map(|v| F::from(*v)).collect_vec());
+        }
+        instance.finish();
+    }
+
+    #[test]
+    fn sort_by_column() {
+        // Small bound makes repeated keys likely.
+        check(3, 12, 8, key_column(1, BigUint::from(8u64)), |r| r[1]);
+        check(1, 1, 8, key_column(0, BigUint::from(8u64)), |r| r[0]);
+    }
+
+    /// Key of the record (a, b, payload) is 256 a + b.
+    fn lexicographic_key<'a>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, record: &[Variable]) -> VarRange<F> {
+        let packed = circuit.apply(0, PolyOp::new(1, 2, 1, |args, _| {
+            vec![args[0] * F::from(256) + args[1]]
+        }), vec![record[0], record[1]])[0];
+        VarRange::new_unchecked(packed, BigUint::from(1u64 << 16))
+    }
+
+    #[test]
+    fn sort_lexicographically() {
+        check(3, 10, 4, lexicographic_key, |r| 256 * r[0] + r[1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many limbs")]
+    fn key_out_of_range() {
+        // Keys are claimed to be bits, so differences of sorted keys must fit in a single limb.
+        check(1, 4, 1 << 32, key_column(0, BigUint::from(2u64)), |r| r[0]);
+    }
+}
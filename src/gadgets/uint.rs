@@ -0,0 +1,282 @@
+// Fixed width unsigned integers represented by constrained bits.
+//
+// Bitwise operations are evaluated as multilinear polynomials of the bits, one witness per output bit:
+// f.e. xor of k words costs a single degree k gate per bit. Log-up over packed nibbles would need the packed
+// operands, the table access and its fractional sum per lookup, so with cheap high degree gates the bitwise
+// form is preferable. Modular addition packs the operands into a single field element and decomposes the sum.
+
+use std::rc::Rc;
+
+use ff::PrimeField;
+use itertools::Itertools;
+
+use crate::{circuit::{Circuit, PolyOp}, constraint_system::Variable, gate::Gatebb, utils::field_precomp::FieldUtils};
+
+use super::{bits::bit_decomposition_gadget, arith::read_const_gadget};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bit {
+    Constant(bool),
+    Var(Variable),
+}
+
+#[derive(Clone, Debug)]
+/// Unsigned integer of N bits, little-endian.
+pub struct UInt<const N: usize> {
+    bits: Vec<Bit>,
+}
+
+pub type UInt8 = UInt<8>;
+pub type UInt32 = UInt<32>;
+pub type UInt64 = UInt<64>;
+
+impl<const N: usize> UInt<N> {
+    pub fn constant(x: u64) -> Self {
+        assert!(N >= 64 || x >> N == 0, "Constant {} does not fit in {} bits.", x, N);
+        Self { bits: (0..N).map(|i| Bit::Constant(i < 64 && (x >> i) & 1 == 1)).collect() }
+    }
+
+    /// Does not check that the bits are boolean.
+    pub fn from_bits(bits: Vec<Bit>) -> Self {
+        assert_eq!(bits.len(), N);
+        Self { bits }
+    }
+
+    pub fn bits(&self) -> &[Bit] {
+        &self.bits
+    }
+
+    /// Decomposes a variable into N bits. Fails on witness generation if it does not fit.
+    pub fn from_var<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, var: Variable, round: usize) -> Self {
+        Self { bits: bit_decomposition_gadget(circuit, round, N, var).into_iter().map(Bit::Var).collect() }
+    }
+
+    /// Returns a variable equal to the value of the integer.
+    pub fn pack<'a, F: PrimeField+FieldUtils>(&self, circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, round: usize) -> Variable {
+        pack_sum(circuit, &[self], round)
+    }
+
+    pub fn rotate_right(&self, k: usize) -> Self {
+        let k = k % N;
+        Self { bits: self.bits[k..].iter().chain(self.bits[..k].iter()).cloned().collect() }
+    }
+
+    pub fn rotate_left(&self, k: usize) -> Self {
+        self.rotate_right(N - k % N)
+    }
+
+    pub fn shr(&self, k: usize) -> Self {
+        let k = k.min(N);
+        Self { bits: self.bits[k..].iter().cloned().chain((0..k).map(|_| Bit::Constant(false))).collect() }
+    }
+
+    pub fn shl(&self, k: usize) -> Self {
+        let k = k.min(N);
+        Self { bits: (0..k).map(|_| Bit::Constant(false)).chain(self.bits[..N-k].iter().cloned()).collect() }
+    }
+
+    /// Applies a function of given degree to the bits of the operands at every position. The function must be
+    /// multilinear and map booleans to booleans, so positions with constant bits get gates of smaller degree.
+    pub fn bitwise<'a, F: PrimeField+FieldUtils>(
+        circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+        operands: &[&Self],
+        degree: usize,
+        f: impl Fn(&[F]) -> F + 'a,
+        round: usize,
+    ) -> Self {
+        let f = Rc::new(f);
+        let bits = (0..N).map(|i| {
+            let column = operands.iter().map(|x| x.bits[i]).collect_vec();
+            let vars = column.iter().filter_map(|b| match b {Bit::Var(v) => Some(*v), Bit::Constant(_) => None}).collect_vec();
+            if vars.is_empty() {
+                let value = f(&column.iter().map(|b| F::from((*b == Bit::Constant(true)) as u64)).collect_vec());
+                assert!(value == F::ZERO || value == F::ONE, "Bitwise function must map booleans to booleans.");
+                return Bit::Constant(value == F::ONE)
+            }
+            let f = f.clone();
+            let n = vars.len();
+            Bit::Var(circuit.apply(round, PolyOp::new(n.min(degree), n, 1, move |args, _| {
+                let mut args = args.iter();
+                let values = column.iter().map(|b| match b {
+                    Bit::Constant(c) => F::from(*c as u64),
+                    Bit::Var(_) => *args.next().unwrap(),
+                }).collect_vec();
+                vec![f(&values)]
+            }), vars)[0])
+        }).collect();
+        Self { bits }
+    }
+
+    pub fn xor<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &Self, b: &Self, round: usize) -> Self {
+        Self::xor_many(circuit, &[a, b], round)
+    }
+
+    /// Xor of several words. Operands are processed in chunks not exceeding the maximal degree of the circuit.
+    pub fn xor_many<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, operands: &[&Self], round: usize) -> Self {
+        let chunk = circuit.max_degree();
+        assert!(chunk >= 2, "Xor requires degree at least 2.");
+        let first = &operands[..chunk.min(operands.len())];
+        let mut acc = Self::bitwise(circuit, first, first.len(), xor_poly, round);
+        for ops in operands[chunk.min(operands.len())..].chunks(chunk - 1) {
+            let args = [&acc].into_iter().chain(ops.iter().cloned()).collect_vec();
+            acc = Self::bitwise(circuit, &args, args.len(), xor_poly, round);
+        }
+        acc
+    }
+
+    pub fn and<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &Self, b: &Self, round: usize) -> Self {
+        Self::bitwise(circuit, &[a, b], 2, |x| x[0] * x[1], round)
+    }
+
+    /// Bitwise negation. Does not allocate anything for constant bits.
+    pub fn not<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, a: &Self, round: usize) -> Self {
+        Self::bitwise(circuit, &[a], 1, |x| F::ONE - x[0], round)
+    }
+
+    /// Sum of operands modulo 2^N. Requires at least one operand.
+    pub fn add_mod<'a, F: PrimeField+FieldUtils>(circuit: &mut Circuit<'a, F, Gatebb<'a, F>>, operands: &[&Self], round: usize) -> Self {
+        assert!(!operands.is_empty(), "add_mod requires at least one operand.");
+        let overflow_bits = (usize::BITS - (operands.len() - 1).leading_zeros()) as usize;
+        assert!(N + overflow_bits < F::CAPACITY as usize, "Sum does not fit in the field.");
+        let sum = pack_sum(circuit, operands, round);
+        let bits = bit_decomposition_gadget(circuit, round, N + overflow_bits, sum);
+        Self { bits: bits[..N].iter().map(|b| Bit::Var(*b)).collect() }
+    }
+}
+
+/// (1 - prod(1 - 2x_i)) / 2, which is a parity of boolean inputs.
+pub fn xor_poly<F: PrimeField+FieldUtils>(x: &[F]) -> F {
+    (F::ONE - x.iter().fold(F::ONE, |acc, b| acc * (F::ONE - b.double()))) * F::TWO_INV
+}
+
+/// Allocates sum of the values of several words.
+fn pack_sum<'a, F: PrimeField+FieldUtils, const N: usize>(
+    circuit: &mut Circuit<'a, F, Gatebb<'a, F>>,
+    operands: &[&UInt<N>],
+    round: usize,
+) -> Variable {
+    let mut constant = F::ZERO;
+    let mut vars = vec![];
+    let mut coeffs = vec![];
+    let mut power = F::ONE;
+    for i in 0..N {
+        for x in operands {
+            match x.bits[i] {
+                Bit::Constant(true) => constant += power,
+                Bit::Constant(false) => (),
+                Bit::Var(v) => {
+                    vars.push(v);
+                    coeffs.push(power);
+                }
+            }
+        }
+        power = power.double();
+    }
+    if vars.is_empty() {
+        return read_const_gadget(circuit, constant, round)
+    }
+    circuit.apply(round, PolyOp::new(1, vars.len(), 1, move |args, _| {
+        vec![args.iter().zip_eq(coeffs.iter()).fold(constant, |acc, (x, c)| acc + *x * c)]
+    }), vars)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2::halo2curves::bn256;
+    use rand_core::{OsRng, RngCore};
+
+    use crate::{circuit::Circuit, gadgets::input::input};
+
+    use super::*;
+
+    type F = bn256::Fr;
+
+    #[test]
+    fn word_operations() {
+        let mut circuit = Circuit::new(3, 1);
+        let ext = circuit.ext_val(5);
+        let words = ext.iter().map(|e| {
+            let var = input(&mut circuit, *e, 0);
+            UInt32::from_var(&mut circuit, var, 0)
+        }).collect_vec();
+        let (a, b, c) = (&words[0], &words[1], &words[2]);
+        let k = UInt32::constant(0x428a2f98);
+
+        let results = [
+            UInt32::xor(&mut circuit, a, b, 0),
+            UInt32::and(&mut circuit, a, &k, 0),
+            UInt32::not(&mut circuit, c, 0),
+            a.rotate_right(7),
+            b.rotate_left(13),
+            c.shr(3),
+            a.shl(9),
+            UInt32::xor_many(&mut circuit, &words.iter().chain([&k]).collect_vec(), 0),
+            UInt32::add_mod(&mut circuit, &[a, b, c, &k], 0),
+            UInt32::add_mod(&mut circuit, &[&k, &k], 0),
+        ];
+        let packed = results.iter().map(|r| r.pack(&mut circuit, 0)).collect_vec();
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let values = (0..5).map(|_| OsRng.next_u32()).collect_vec();
+        for (e, v) in ext.iter().zip_eq(values.iter()) {
+            instance.set_ext(*e, F::from(*v as u64));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        let (x, y, z) = (values[0], values[1], values[2]);
+        let expected = [
+            x ^ y,
+            x & 0x428a2f98,
+            !z,
+            x.rotate_right(7),
+            y.rotate_left(13),
+            z >> 3,
+            x << 9,
+            values.iter().fold(0x428a2f98, |acc, v| acc ^ v),
+            x.wrapping_add(y).wrapping_add(z).wrapping_add(0x428a2f98),
+            0x428a2f98u32.wrapping_add(0x428a2f98),
+        ];
+        for (var, e) in packed.iter().zip_eq(expected) {
+            assert_eq!(instance.cs.getvar(*var), F::from(e as u64));
+        }
+    }
+
+    #[test]
+    fn constant_folding() {
+        let a = UInt8::constant(0xa5);
+        let b = UInt8::constant(0x3c);
+        let mut circuit = Circuit::<F, Gatebb<F>>::new(2, 1);
+        let x = UInt8::xor(&mut circuit, &a, &b, 0);
+        assert!(x.bits().iter().all(|b| matches!(b, Bit::Constant(_))));
+        assert_eq!(x.bits().to_vec(), UInt8::constant(0xa5 ^ 0x3c).bits().to_vec());
+    }
+
+    #[test]
+    fn wide_addition() {
+        let mut circuit = Circuit::new(2, 1);
+        let ext = circuit.ext_val(2);
+        let words = ext.iter().map(|e| {
+            let var = input(&mut circuit, *e, 0);
+            UInt64::from_var(&mut circuit, var, 0)
+        }).collect_vec();
+        let sum = UInt64::add_mod(&mut circuit, &[&words[0], &words[1]], 0).pack(&mut circuit, 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let (x, y) = (OsRng.next_u64(), OsRng.next_u64());
+        instance.set_ext(ext[0], F::from(x));
+        instance.set_ext(ext[1], F::from(y));
+        instance.execute(0);
+        instance.valid_witness();
+        assert_eq!(instance.cs.getvar(sum), F::from(x.wrapping_add(y)));
+    }
+
+    #[test]
+    #[should_panic(expected = "add_mod requires at least one operand")]
+    fn empty_addition() {
+        let mut circuit = Circuit::<F, Gatebb<F>>::new(2, 1);
+        UInt8::add_mod(&mut circuit, &[], 0);
+    }
+}
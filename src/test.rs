@@ -4,19 +4,27 @@ mod tests {
 
     use crate::{
         gate::Gatebb,
-        constraint_system::{Variable, Visibility},
-        circuit::{Circuit, PolyOp, Advice},
+        constraint_system::{Variable, Visibility, CS},
+        circuit::{Circuit, PolyOp, Advice, AdviceError, random_ck},
         gadgets::{
             poseidon::{
                 poseidon_gadget_mixstrat,
-                poseidon_gadget_internal
+                poseidon_gadget_internal,
+                PoseidonSpongeGadget,
             },
             bits::bit_decomposition_gadget,
             ecmul::{
                 add_proj,
                 double_proj,
                 EcAffinePoint,
-                escalarmul_gadget_9
+                escalarmul_gadget_9,
+                escalarmul_gadget,
+                ecmsm_gadget,
+                offset_point,
+                num_limbs_for_bits,
+                choose_window,
+                MulStep,
+                Window,
             },
             rangecheck_small::{
                 rangecheck,
@@ -28,14 +36,15 @@ mod tests {
             rangecheck_common::{
                 VarRange,
             },
-            nonzero_check::Nonzeros, input::input
-        }, folding::poseidon::Poseidon
+            nonzero_check::Nonzeros, input::input, arith::{mul_gadget, add_gadget, inv_gadget}
+        }, checkpoint::CheckpointError, folding::{poseidon::{Poseidon, PoseidonSponge}, oracle::{HashOracle, Oracle}}
     };
     use ff::{PrimeField, Field};
     use group::{Group, Curve};
+    use itertools::Itertools;
     use halo2::halo2curves::{bn256, grumpkin, CurveAffine, CurveExt};
     use num_bigint::BigUint;
-    use rand_core::OsRng;
+    use rand_core::{OsRng, RngCore};
     use crate::utils::poly_utils::{check_poly, find_degree};
     use crate::utils::field_precomp::FieldUtils;
     
@@ -145,6 +154,209 @@ mod tests {
         instance.valid_witness(); // test that constraints are satisfied
     }
     
+    #[test]
+    fn test_permutation_argument_with_oracle() {
+
+        let mut circuit = Circuit::new(2, 2);
+        let pi_ext = circuit.ext_val(5);
+
+        let one = circuit.one();
+
+        let mut pi = vec![];
+        for k in 0..5{
+            pi.push(
+                input(&mut circuit, pi_ext[k], 0)
+            );
+        }
+
+        // private round-0 value which is not produced by any operation, it is set by the prover directly
+        let secret = circuit.cs.alloc_in_round(0, Visibility::Private, 1)[0];
+
+        let challenge = circuit.challenge(0);
+
+        let division_advice = Advice::new(2, 1, |ivar : &[F], _| {
+            let ch = ivar[0];
+            let x = ivar[1];
+            vec![(x-ch).invert().unwrap()]
+        });
+
+        let mut fractions = vec![];
+        for k in 0..5 {
+            fractions.push(
+                circuit.advice(1, division_advice.clone(), vec![challenge, pi[k]])[0]
+            );
+        }
+
+        let div_constr = Gatebb::<F>::new(
+            2, 4, 1,
+            Rc::new(|args, _|{
+                let one = args[0];
+                let ch = args[1];
+                let x = args[2];
+                let res = args[3];
+                vec![one*one - res * (x-ch)]
+            }),
+            vec![],
+        );
+
+        for k in 0..5 {
+            circuit.constrain(&[one, challenge, pi[k], fractions[k]], div_constr.clone());
+        }
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+
+        let run = |pi_vals: [u64; 5], secret_val: u64| {
+            let mut instance = constructed.spawn();
+            for k in 0..5 {
+                instance.set_ext(pi_ext[k], F::from(pi_vals[k]));
+            }
+            instance.cs.setvar(secret, F::from(secret_val));
+
+            let mut oracle = HashOracle::<F, Poseidon>::new();
+            instance.execute_with_oracle(1, &ck, &mut oracle);
+
+            instance.valid_witness();
+            let ret = instance.cs.getvar(challenge);
+            instance.finish();
+            ret
+        };
+
+        let pi_vals = [2, 3, 4, 5, 6];
+        let ch = run(pi_vals, 7);
+
+        // the challenge is determined by the transcript
+        assert_eq!(ch, run(pi_vals, 7));
+
+        // and depends on the public inputs
+        let mut other_pi = pi_vals;
+        other_pi[4] = 8;
+        assert_ne!(ch, run(other_pi, 7));
+
+        // and on the committed private witness
+        assert_ne!(ch, run(pi_vals, 8));
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let mut circuit = Circuit::new(2, 2);
+        let ext = circuit.ext_val(2);
+        let a = input(&mut circuit, ext[0], 0);
+        let b = input(&mut circuit, ext[1], 1);
+        let prod = mul_gadget(&mut circuit, a, b, 1);
+
+        let constructed = circuit.finalize();
+
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext[0], F::from(3));
+        instance.execute(0);
+        let data = instance.checkpoint();
+        instance.finish();
+
+        let mut restored = constructed.resume(&data).unwrap();
+        assert_eq!(restored.cs.getvar(a), F::from(3));
+        restored.set_ext(ext[1], F::from(5));
+        restored.execute(1);
+        restored.valid_witness();
+        assert_eq!(restored.cs.getvar(prod), F::from(15));
+
+        // the restored run holds its slot
+        let data = restored.checkpoint();
+        assert_eq!(constructed.resume(&data).err(), Some(CheckpointError::SlotInUse(0)));
+        restored.finish();
+
+        let mut other = Circuit::<F, Gatebb<F>>::new(2, 2);
+        let ext = other.ext_val(2);
+        let a = input(&mut other, ext[0], 0);
+        let b = input(&mut other, ext[1], 1);
+        add_gadget(&mut other, a, b, 1);
+        let other = other.finalize();
+        assert!(matches!(other.resume(&data).err(), Some(CheckpointError::ShapeMismatch{..})));
+
+        assert_eq!(constructed.resume(&data[..data.len()-1]).err(), Some(CheckpointError::Malformed));
+    }
+
+    #[test]
+    fn test_checkpoint_resume_with_oracle() {
+        let mut circuit = Circuit::new(2, 3);
+        let ext = circuit.ext_val(1)[0];
+        let a = input(&mut circuit, ext, 0);
+        let ch0 = circuit.challenge(0);
+        let x = mul_gadget(&mut circuit, a, ch0, 1);
+        let ch1 = circuit.challenge(1);
+        let y = mul_gadget(&mut circuit, x, ch1, 2);
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext, F::from(3));
+        instance.execute_with_oracle(2, &ck, &mut HashOracle::<F, Poseidon>::new());
+        let expected = instance.cs.get_vars(&[ch0, ch1, y]);
+        instance.finish();
+
+        // interrupt the run between the challenge rounds
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext, F::from(3));
+        let mut oracle = HashOracle::<F, Poseidon>::new();
+        instance.execute_with_oracle(1, &ck, &mut oracle);
+        let data = instance.checkpoint_with_oracle(&oracle);
+        instance.finish();
+
+        assert_eq!(constructed.resume(&data).err(), Some(CheckpointError::OracleMismatch));
+
+        let (mut restored, mut oracle) = constructed.resume_with_oracle::<HashOracle<F, Poseidon>>(&data).unwrap();
+        restored.execute_with_oracle(2, &ck, &mut oracle);
+        restored.valid_witness();
+        assert_eq!(restored.cs.get_vars(&[ch0, ch1, y]), expected);
+        restored.finish();
+    }
+
+    #[test]
+    fn test_try_execute_with_oracle() {
+        let mut circuit = Circuit::new(2, 2);
+        let ext = circuit.ext_val(1)[0];
+        let a = input(&mut circuit, ext, 0);
+        let ch = circuit.challenge(0);
+        let x = mul_gadget(&mut circuit, a, ch, 1);
+        let inv = inv_gadget(&mut circuit, x, 1);
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext, F::from(3));
+        assert_eq!(instance.try_execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new()), Ok(()));
+        instance.valid_witness();
+        assert_eq!(instance.cs.getvar(inv) * instance.cs.getvar(x), F::ONE);
+        instance.finish();
+
+        // the advice of the challenge round fails instead of panicking
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext, F::ZERO);
+        let err = instance.try_execute_with_oracle(1, &ck, &mut HashOracle::<F, Poseidon>::new()).unwrap_err();
+        assert_eq!((err.round, err.error), (1, AdviceError::DivisionByZero));
+        instance.finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "use checkpoint_with_oracle instead")]
+    fn test_checkpoint_refuses_to_drop_oracle() {
+        let mut circuit = Circuit::new(2, 3);
+        let ext = circuit.ext_val(1)[0];
+        let a = input(&mut circuit, ext, 0);
+        let ch = circuit.challenge(1);
+        mul_gadget(&mut circuit, a, ch, 2);
+
+        let constructed = circuit.finalize();
+        let ck = random_ck(&constructed);
+
+        let mut instance = constructed.spawn();
+        instance.set_ext(ext, F::from(3));
+        instance.execute_with_oracle(0, &ck, &mut HashOracle::<F, Poseidon>::new());
+        instance.checkpoint();
+    }
+
     #[test]
     fn test_poseidon_gadget(){
         let cfg = Poseidon::new();
@@ -206,6 +418,91 @@ mod tests {
         println!("{:?}", instance.cs.getvar(ret).to_repr());
     }
 
+    /// Runs a program of absorbs (Some(amount)) and squeezes (None) with random inputs, and compares squeezed
+    /// elements with the native sponge.
+    fn check_poseidon_sponge(rate: usize, k: usize, program: &[Option<usize>]) {
+        let cfg = Poseidon::new();
+        let domain = F::from(42);
+        let mut circuit = Circuit::new(5usize.pow(k as u32), 1);
+        let mut sponge = PoseidonSpongeGadget::new(&cfg, rate, domain, k, 0);
+        let mut exts = vec![];
+        let mut outputs = vec![];
+        for op in program {
+            match op {
+                Some(amount) => {
+                    let ext = circuit.ext_val(*amount);
+                    let inp = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+                    sponge.absorb(&mut circuit, &inp);
+                    exts.push(ext);
+                },
+                None => outputs.push(sponge.squeeze(&mut circuit)),
+            }
+        }
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        let mut native = PoseidonSponge::new(&cfg, rate, domain);
+        let mut expected = vec![];
+        let mut exts = exts.iter();
+        for op in program {
+            match op {
+                Some(_) => {
+                    let values = exts.next().unwrap().iter().map(|e| {
+                        let v = F::random(OsRng);
+                        instance.set_ext(*e, v);
+                        v
+                    }).collect_vec();
+                    native.absorb(&values);
+                },
+                None => expected.push(native.squeeze()),
+            }
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        assert_eq!(instance.cs.get_vars(&outputs), expected);
+        assert_eq!(sponge.mode(), native.mode());
+    }
+
+    #[test]
+    fn test_poseidon_sponge_gadget() {
+        for len in 0..7 {
+            check_poseidon_sponge(2, 1, &[Some(len), None, None, None]);
+        }
+        check_poseidon_sponge(3, 1, &[Some(4), None, Some(1), Some(2), None, Some(3), None, None, None, None]);
+        check_poseidon_sponge(1, 1, &[None, Some(2), Some(1), None]);
+    }
+
+    #[test]
+    fn test_poseidon_sponge_gadget_k_equals_two() {
+        check_poseidon_sponge(4, 2, &[Some(9), None, None, Some(1), None]);
+    }
+
+    #[test]
+    fn test_poseidon_gadget_generated_params(){
+        type Fq = bn256::Fq;
+        let cfg = Poseidon::<Fq>::generate(5, 8, vec![56, 57]);
+        let mut circuit = Circuit::<Fq, Gatebb<Fq>>::new(25, 1);
+        let pi_ext = circuit.ext_val(2);
+        let pi = pi_ext.iter().map(|&ext| input(&mut circuit, ext, 0)).collect_vec();
+        let ret_kround = poseidon_gadget_internal(&mut circuit, &cfg, 2, 0, pi.clone());
+        let ret_mixstrat = poseidon_gadget_mixstrat(&mut circuit, &cfg, 0, pi);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        let values = vec![Fq::random(OsRng), Fq::random(OsRng)];
+        instance.set_ext(pi_ext[0], values[0]);
+        instance.set_ext(pi_ext[1], values[1]);
+
+        instance.execute(0);
+        instance.valid_witness();
+
+        let expected = cfg.hash(values);
+        assert_eq!(instance.cs.getvar(ret_kround), expected);
+        assert_eq!(instance.cs.getvar(ret_mixstrat), expected);
+    }
+
     #[test]
     
     fn test_bit_decomposition(){
@@ -344,6 +641,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lagrange_batch_any_size() -> () {
+        assert!(lagrange_choice_batched(F::random(OsRng), 1) == vec![F::ONE]);
+        // Beyond the precomputed tables.
+        for n in [30usize, 41, 64] {
+            for x in [0, n/2, n-1] {
+                let v = lagrange_choice_batched(F::from(x as u64), n as u64);
+                for t in 0..n {
+                    assert!(if t==x {v[t] == F::ONE} else {v[t] == F::ZERO})
+                }
+            }
+            let x = F::random(OsRng);
+            let v = lagrange_choice_batched(x, n as u64);
+            let sum = v.iter().fold(F::ZERO, |acc, l| acc + l);
+            let weighted = v.iter().enumerate().fold(F::ZERO, |acc, (t, l)| acc + F::from(t as u64) * l);
+            assert!(sum == F::ONE);
+            assert!(weighted == x);
+        }
+    }
+
     #[test]
 
     fn test_choice_gadget() -> () {
@@ -388,6 +705,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_choice_gadget_large_domain() -> () {
+        let n = 33;
+        let mut circuit = Circuit::new(n, 1);
+        let pi_ext = circuit.ext_val(n);
+        let pi_id_ext = circuit.ext_val(1)[0];
+        let pi : Vec<_> = pi_ext.iter().map(|e| vec![input(&mut circuit, *e, 0)]).collect();
+        let pi_id = input(&mut circuit, pi_id_ext, 0);
+
+        let pi : Vec<_> = pi.iter().map(|x|x.as_ref()).collect();
+        let chosen = choice_gadget(&mut circuit, &pi, VarRange::new_unchecked(pi_id, BigUint::from(n)), 0);
+
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+        instance.set_ext(pi_id_ext, F::from(31));
+        for e in pi_ext {
+            instance.set_ext(e, F::random(OsRng));
+        }
+        instance.execute(0);
+        instance.valid_witness();
+
+        assert!(instance.cs.getvar(pi[31][0]) == instance.cs.getvar(chosen[0]));
+    }
+
     #[test]
     fn test_escalarmul_gadget()->(){
         let mut circuit = Circuit::new(10, 1);
@@ -447,5 +788,114 @@ mod tests {
         println!("Total circuit size: private: {} public: {}", instance.cs.wtns[0].privs.len(), instance.cs.wtns[0].pubs.len());
     }
 
+    fn check_escalarmul_window(window: Window, max_degree: usize) {
+        let mut circuit = Circuit::new(max_degree, 1);
+        let ext = circuit.ext_val(7);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let a = EcAffinePoint::<F,C>::new(&mut circuit, vars[0], vars[1]);
+        let b = EcAffinePoint::<F,C>::new(&mut circuit, vars[2], vars[3]);
+        let pt = EcAffinePoint::<F,C>::new(&mut circuit, vars[4], vars[5]);
+
+        let mut nonzeros = Nonzeros::new(max_degree - 1);
+        let num_limbs = num_limbs_for_bits(window.base(), 64);
+        let scmul = escalarmul_gadget(&mut circuit, vars[6], pt, &window, num_limbs, 0, a, b, &mut nonzeros);
+
+        nonzeros.finalize(&mut circuit);
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        let pi_a = C::random(OsRng);
+        let pi_b = offset_point(pi_a, window.base(), num_limbs).to_affine();
+        let pi_a = pi_a.to_affine();
+        let pi_pt = C::random(OsRng).to_affine();
+        let sc = OsRng.next_u64();
+        let values = [pi_a.x, pi_a.y, pi_b.x, pi_b.y, pi_pt.x, pi_pt.y, F::from(sc)];
+        for (e, v) in ext.iter().zip_eq(values) {
+            instance.set_ext(*e, v);
+        }
+
+        instance.execute(0);
+        instance.valid_witness();
+
+        let answer = grumpkin::G1Affine::from_xy(instance.cs.getvar(scmul.x), instance.cs.getvar(scmul.y)).unwrap();
+        assert!(answer == (pi_pt*Fq::from(sc)).to_affine());
+        instance.finish();
+    }
+
+    #[test]
+    fn test_escalarmul_gadget_windows() {
+        check_escalarmul_window(Window::from_base(4, false).unwrap(), 4);
+        check_escalarmul_window(Window::from_base(8, false).unwrap(), 8);
+        check_escalarmul_window(Window::from_base(12, true).unwrap(), 12);
+        check_escalarmul_window(Window::new(vec![MulStep::Triple, MulStep::Double, MulStep::Double]), 12);
+        check_escalarmul_window(Window::from_base(16, true).unwrap(), 16);
+    }
+
+    fn check_ecmsm(window: Window, max_degree: usize, num_terms: usize) -> usize {
+        let mut circuit = Circuit::new(max_degree, 1);
+        let ext = circuit.ext_val(5*num_terms + 2);
+        let vars = ext.iter().map(|e| input(&mut circuit, *e, 0)).collect_vec();
+        let (pt_vars, rest) = vars.split_at(4*num_terms);
+        let points = pt_vars.chunks(2).map(|v| EcAffinePoint::<F,C>::new(&mut circuit, v[0], v[1])).collect_vec();
+        let (pts, a) = points.split_at(num_terms);
+        let b = EcAffinePoint::<F,C>::new(&mut circuit, rest[0], rest[1]);
+        let scalars = &rest[2..];
+
+        let mut nonzeros = Nonzeros::new(max_degree - 1);
+        let num_limbs = num_limbs_for_bits(window.base(), 64);
+        let msm = ecmsm_gadget(&mut circuit, scalars, pts, &window, num_limbs, 0, a, b, &mut nonzeros);
+
+        nonzeros.finalize(&mut circuit);
+        let constructed = circuit.finalize();
+        let mut instance = constructed.spawn();
+
+        let mut pi_pts = (0..num_terms).map(|_| C::random(OsRng)).collect_vec();
+        pi_pts[num_terms-1] = pi_pts[0]; // repeated base point
+        let pi_a = (0..num_terms).map(|_| C::random(OsRng)).collect_vec();
+        let pi_b = offset_point(pi_a.iter().fold(C::identity(), |acc, x| acc + x), window.base(), num_limbs).to_affine();
+        let sc = (0..num_terms).map(|_| OsRng.next_u64()).collect_vec();
+
+        let values = pi_pts.iter().chain(pi_a.iter())
+            .flat_map(|p| {let p = p.to_affine(); [p.x, p.y]})
+            .chain([pi_b.x, pi_b.y])
+            .chain(sc.iter().map(|s| F::from(*s)))
+            .collect_vec();
+        for (e, v) in ext.iter().zip_eq(values) {
+            instance.set_ext(*e, v);
+        }
+
+        instance.execute(0);
+        instance.valid_witness();
+
+        let expected = pi_pts.iter().zip_eq(sc.iter()).fold(C::identity(), |acc, (p, s)| acc + *p * Fq::from(*s));
+        let answer = grumpkin::G1Affine::from_xy(instance.cs.getvar(msm.x), instance.cs.getvar(msm.y)).unwrap();
+        assert!(answer == expected.to_affine());
+        let size = instance.cs.wtns[0].privs.len();
+        instance.finish();
+        size
+    }
+
+    #[test]
+    fn test_ecmsm_gadget() {
+        assert_eq!(check_ecmsm(Window::from_base(9, true).unwrap(), 10, 3), 587);
+        assert_eq!(check_ecmsm(Window::from_base(4, false).unwrap(), 4, 2), 655);
+        assert_eq!(check_ecmsm(Window::from_base(8, false).unwrap(), 8, 1), 359);
+    }
+
+    #[test]
+    fn test_choose_window() {
+        assert!(Window::from_base(10, true).is_none());
+        assert!(Window::from_base(9, false).is_none());
+        assert_eq!(Window::from_base(9, true).unwrap().min_degree(), 9);
+        assert_eq!(Window::from_base(4, true).unwrap().min_degree(), 4);
+
+        for max_degree in [3, 4, 8, 10] {
+            let window = choose_window::<F,C>(max_degree, 254);
+            assert!(window.min_degree() <= max_degree);
+            println!("Degree {}: base {}", max_degree, window.base());
+        }
+        assert_eq!(choose_window::<F,C>(3, 254).base(), 2);
+    }
+
 
 }
\ No newline at end of file
@@ -46,6 +46,11 @@ pub fn shift64<F: PrimeField> (x: F) -> F {
     x
 }
 
+/// Canonical representative of a field element. Assumes little-endian representation.
+pub fn to_biguint<F: PrimeField>(x: F) -> BigUint {
+    BigUint::from_bytes_le(x.to_repr().as_ref())
+}
+
 pub fn from_biguint<F:PrimeField>(x: &BigUint) -> F {
     assert!(*x < modulus::<F>());
     x
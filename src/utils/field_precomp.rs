@@ -1,36 +1,119 @@
+use std::{any::{Any, TypeId}, collections::HashMap, sync::{OnceLock, RwLock}};
+
 use ff::{Field, PrimeField};
 use halo2::arithmetic::best_fft;
-use halo2::halo2curves::bn256;
+use halo2::halo2curves::{bn256, pasta, secp256k1};
 use num_traits::pow;
 
 use super::{powers_of_omega, half_squares, inv_lagrange_prod};
 
+/// Field helpers used by gadgets.
+///
+/// Default implementations work for any prime field. Lagrange products, which take O(n) to compute,
+/// are cached on first use. Fields for which tables were generated (bn256::Fr) override them.
 pub trait FieldUtils where Self : PrimeField{
     // /// Returns power of a primitive root of unity of order 2^logorder.
     // fn roots_of_unity(power: u64, logorder: usize) -> Self;
     /// Returns power of 1/2.
-    fn half_pow(power: u64) -> Self;
+    fn half_pow(power: u64) -> Self {
+        Self::TWO_INV.pow([power])
+    }
     // /// Returns FFT of the binomial.
     // fn binomial_fft(power: usize, logorder: usize) -> Vec<Self>;
     /// Multiplies the value by the small scalar.
-    fn scale(&self, scale: u64) -> Self;
+    fn scale(&self, scale: u64) -> Self {
+        scale_by_chain(*self, scale)
+    }
     /// Returns [k/2]^2
-    fn half_square(k: u64) -> Self;
+    fn half_square(k: u64) -> Self {
+        half_square_generic(k)
+    }
     /// Given 0=<k<n, returns 1/\prod_{i \in 0..n; i!=k}(k-i).
-    fn inv_lagrange_prod(k: u64, n: u64) -> Self;
+    fn inv_lagrange_prod(k: u64, n: u64) -> Self {
+        cached_inv_lagrange_prod(k, n)
+    }
+}
+
+type CacheKey = (TypeId, u64, u64);
+
+/// Maximal amount of cached values, the values computed after the cache is full are not stored.
+const CACHE_CAPACITY: usize = 1 << 12;
+
+/// Values of inv_lagrange_prod, shared between all fields and keyed by the type of the field.
+static CACHE: OnceLock<RwLock<HashMap<CacheKey, Box<dyn Any + Send + Sync>>>> = OnceLock::new();
+
+fn cached_inv_lagrange_prod<F: PrimeField>(k: u64, n: u64) -> F {
+    let key = (TypeId::of::<F>(), k, n);
+    let cache = CACHE.get_or_init(|| RwLock::new(HashMap::new()));
+    if let Some(value) = cache.read().unwrap().get(&key) {
+        return *value.downcast_ref::<F>().expect("cache is keyed by the field type")
+    }
+    let value = inv_lagrange_prod_generic(k, n);
+    let mut cache = cache.write().unwrap();
+    if cache.len() < CACHE_CAPACITY {
+        cache.insert(key, Box::new(value));
+    }
+    value
+}
+
+/// Computes [k/2]^2 directly.
+pub fn half_square_generic<F: PrimeField>(k: u64) -> F {
+    let half = F::from(k) * F::TWO_INV;
+    half * half
+}
+
+/// Computes 1/\prod_{i \in 0..n; i!=k}(k-i) directly.
+pub fn inv_lagrange_prod_generic<F: PrimeField>(k: u64, n: u64) -> F {
+    assert!(k < n, "Lagrange basis index {} is out of domain of size {}", k, n);
+    let prod = (0..n).filter(|&i| i != k).fold(F::ONE, |acc, i| {
+        let diff = if k > i { F::from(k - i) } else { -F::from(i - k) };
+        acc * diff
+    });
+    prod.invert().expect("domain size should be smaller than the field characteristic")
 }
 
-type F = bn256::Fr;
+/// Addition chains mostly taken from https://github.com/mratsim/constantine/blob/master/constantine/math/arithmetic/finite_fields.nim#L443 
+fn scale_by_chain<F: PrimeField>(x: F, scale: u64) -> F {
+    let mut x = x;
+    let mut acc = F::ZERO;
+    if scale > 15 {
+        let mut scale = scale;
+        while scale > 0 {
+            if scale%2 == 1 {
+                acc += x;
+            }
+            x = x.double();
+            scale >>= 1;
+        }
+        acc
+    } else {
+        match scale {
+            0 => F::ZERO,
+            1 => x,
+            2 => x.double(),
+            3 => {let y = x.double(); y+x},
+            4 => x.double().double(),
+            5 => {let y = x.double().double(); y+x},
+            6 => {x = x.double(); let y = x.double(); y+x},
+            7 => {let y = x.double().double().double(); y-x},
+            8 => {x.double().double().double()},
+            9 => {let y = x.double().double().double(); y+x},
+            10 => {x = x.double(); let y = x.double().double(); y+x},
+            11 => {let y = x.double().double(); y.double()+y-x},
+            12 => {let y = x.double().double(); y.double()+y},
+            13 => {let y = x.double().double(); y.double()+y+x},
+            14 => {x=x.double(); let y = x.double().double().double(); y-x},
+            15 => {let y = x.double().double().double().double(); y-x},
+            _ => unreachable!(),
+        }
+    }
+}
 
-impl FieldUtils for F {
+impl FieldUtils for bn256::Fr {
     // /// Returns power of a primitive root of unity of order 2^logorder.
     // fn roots_of_unity(power: u64, logorder: usize) -> Self{
     //     powers_of_omega::roots_of_unity(power, logorder)
     // }
-    /// Returns power of 1/2.
-    fn half_pow(power: u64) -> Self {
-        F::TWO_INV.pow([power])
-    }
 
     // fn binomial_fft(power: usize, logorder: usize) -> Vec<Self> {
     //     assert!(power < pow(2, logorder));
@@ -50,48 +133,111 @@ impl FieldUtils for F {
     //     bin_coeffs
     // }
 
-    /// Addition chains mostly taken from https://github.com/mratsim/constantine/blob/master/constantine/math/arithmetic/finite_fields.nim#L443 
-    fn scale(&self, scale: u64) -> Self {
-        let mut x = *self;
-        let mut acc = Self::ZERO;
-        if scale > 15 {
-            let mut scale = scale;
-            while scale > 0 {
-                if scale%2 == 1 {
-                    acc += x;
-                }
-                x = x.double();
-                scale >>= 1;
-            }
-            acc
+    fn half_square(k:u64) -> Self {
+        // values beyond the generated table are computed generically
+        if k < half_squares::LIMIT {
+            half_squares::half_square(k)
         } else {
-            match scale {
-                0 => F::ZERO,
-                1 => x,
-                2 => x.double(),
-                3 => {let y = x.double(); y+x},
-                4 => x.double().double(),
-                5 => {let y = x.double().double(); y+x},
-                6 => {x = x.double(); let y = x.double(); y+x},
-                7 => {let y = x.double().double().double(); y-x},
-                8 => {x.double().double().double()},
-                9 => {let y = x.double().double().double(); y+x},
-                10 => {x = x.double(); let y = x.double().double(); y+x},
-                11 => {let y = x.double().double(); y.double()+y-x},
-                12 => {let y = x.double().double(); y.double()+y},
-                13 => {let y = x.double().double(); y.double()+y+x},
-                14 => {x=x.double(); let y = x.double().double().double(); y-x},
-                15 => {let y = x.double().double().double().double(); y-x},
-                _ => unreachable!(),
-            }
+            half_square_generic(k)
         }
     }
 
-    fn half_square(k:u64) -> Self {
-        half_squares::half_square(k)
+    fn inv_lagrange_prod(k: u64, n: u64) -> Self {
+        if (2..inv_lagrange_prod::LIMIT).contains(&n) {
+            inv_lagrange_prod::inv_lagrange_prod(k, n)
+        } else {
+            cached_inv_lagrange_prod(k, n)
+        }
     }
+}
 
-    fn inv_lagrange_prod(k: u64, n: u64) -> Self {
-        inv_lagrange_prod::inv_lagrange_prod(k, n)
+impl FieldUtils for bn256::Fq {}
+impl FieldUtils for pasta::Fp {}
+impl FieldUtils for pasta::Fq {}
+impl FieldUtils for secp256k1::Fp {}
+impl FieldUtils for secp256k1::Fq {}
+
+#[cfg(test)]
+mod tests {
+    use ff::Field;
+    use halo2::halo2curves::{bn256, pasta, secp256k1};
+    use rand_core::OsRng;
+
+    use super::{FieldUtils, half_square_generic, inv_lagrange_prod_generic, half_squares, inv_lagrange_prod};
+
+    macro_rules! field_utils_tests {
+        ($name:ident, $field:ty) => {
+            mod $name {
+                use super::*;
+
+                type F = $field;
+
+                #[test]
+                fn test_half_pow() {
+                    for power in [0, 1, 5, 64] {
+                        assert_eq!(F::half_pow(power) * F::from(2).pow([power]), F::ONE);
+                    }
+                }
+
+                #[test]
+                fn test_scale() {
+                    let x = F::random(OsRng);
+                    for scale in [0, 1, 7, 15, 16, 1000, u64::MAX] {
+                        assert_eq!(x.scale(scale), x * F::from(scale));
+                    }
+                }
+
+                #[test]
+                fn test_half_square() {
+                    for k in 0..40 {
+                        assert_eq!(F::half_square(k).scale(4), F::from(k * k));
+                    }
+                }
+
+                #[test]
+                fn test_inv_lagrange_prod() {
+                    for n in 2..30 {
+                        for k in 0..n {
+                            let prod = (0..n).filter(|&i| i != k).fold(F::ONE, |acc, i| acc * (F::from(k) - F::from(i)));
+                            assert_eq!(F::inv_lagrange_prod(k, n) * prod, F::ONE);
+                        }
+                    }
+                }
+            }
+        };
     }
-}
\ No newline at end of file
+
+    field_utils_tests!(bn256_fr, bn256::Fr);
+    field_utils_tests!(bn256_fq, bn256::Fq);
+    field_utils_tests!(pasta_fp, pasta::Fp);
+    field_utils_tests!(pasta_fq, pasta::Fq);
+    field_utils_tests!(secp256k1_fp, secp256k1::Fp);
+    field_utils_tests!(secp256k1_fq, secp256k1::Fq);
+
+    #[test]
+    fn test_bn256_tables_match_generic() {
+        for k in 0..40 {
+            assert_eq!(bn256::Fr::half_square(k), half_square_generic::<bn256::Fr>(k));
+        }
+        for n in 2..30 {
+            for k in 0..n {
+                assert_eq!(bn256::Fr::inv_lagrange_prod(k, n), inv_lagrange_prod_generic::<bn256::Fr>(k, n));
+            }
+        }
+    }
+
+    #[test]
+    fn test_bn256_beyond_tables() {
+        let limit = half_squares::LIMIT;
+        for k in [limit - 1, limit, limit + 1, 2 * limit] {
+            assert_eq!(bn256::Fr::half_square(k).scale(4), bn256::Fr::from(k * k));
+        }
+        assert_eq!(bn256::Fr::inv_lagrange_prod(0, 1), bn256::Fr::ONE);
+        let limit = inv_lagrange_prod::LIMIT;
+        for n in [limit - 1, limit, limit + 1, 2 * limit] {
+            for k in [0, n / 2, n - 1] {
+                assert_eq!(bn256::Fr::inv_lagrange_prod(k, n), inv_lagrange_prod_generic::<bn256::Fr>(k, n));
+            }
+        }
+    }
+}
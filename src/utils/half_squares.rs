@@ -1,4 +1,6 @@
 use halo2::halo2curves::{bn256::Fr as F, serde::SerdeObject};
+/// Values of k in 0..LIMIT are tabulated.
+pub const LIMIT: u64 = 50;
 pub fn half_square(k:u64) -> F {
     match k {
        0 => F::from_raw_bytes_unchecked(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
@@ -78,4 +78,77 @@ pub fn find_degree<'a, F: PrimeField>(max_degree: usize, i: usize, o:usize, f: R
     }
 
     Ok(top)
-}
\ No newline at end of file
+}
+
+/// Barycentric weights of the domain, w_i = 1/\prod_{j != i}(d_i - d_j). Points of the domain must be distinct.
+pub fn lagrange_weights<F: PrimeField>(domain: &[F]) -> Vec<F> {
+    domain.iter().enumerate().map(|(i, di)| {
+        domain.iter().enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(F::ONE, |acc, (_, dj)| acc * (*di - dj))
+            .invert()
+            .expect("Points of the interpolation domain must be distinct")
+    }).collect()
+}
+
+/// Values of the Lagrange basis polynomials of the domain at x, given the weights of the domain (see lagrange_weights).
+/// Uses O(n) multiplications and no inversions.
+pub fn lagrange_basis<F: PrimeField>(domain: &[F], weights: &[F], x: F) -> Vec<F> {
+    assert!(domain.len() == weights.len());
+    let n = domain.len();
+    // suffix[i] is the product of (x - d_j) for j >= i.
+    let mut suffix = vec![F::ONE; n + 1];
+    for i in (0..n).rev() {
+        suffix[i] = suffix[i + 1] * (x - domain[i]);
+    }
+    let mut prefix = F::ONE;
+    let mut ret = Vec::with_capacity(n);
+    for i in 0..n {
+        ret.push(weights[i] * prefix * suffix[i + 1]);
+        prefix *= x - domain[i];
+    }
+    ret
+}
+
+/// Coefficients (lowest first) of the Lagrange basis polynomials of the domain.
+pub fn lagrange_coeffs<F: PrimeField>(domain: &[F]) -> Vec<Vec<F>> {
+    let n = domain.len();
+    // Coefficients of \prod_j (X - d_j).
+    let mut vanishing = vec![F::ONE];
+    for d in domain {
+        let mut next = vec![F::ZERO; vanishing.len() + 1];
+        for (k, c) in vanishing.iter().enumerate() {
+            next[k + 1] += c;
+            next[k] -= *c * d;
+        }
+        vanishing = next;
+    }
+
+    domain.iter().zip(lagrange_weights(domain)).map(|(d, w)| {
+        // Synthetic division of the vanishing polynomial by X - d.
+        let mut ret = vec![F::ZERO; n];
+        let mut carry = F::ZERO;
+        for k in (0..n).rev() {
+            carry = vanishing[k + 1] + carry * d;
+            ret[k] = carry * w;
+        }
+        ret
+    }).collect()
+}
+
+/// Coefficients (lowest first) of the polynomial of degree < n taking given values on the domain of size n.
+pub fn interpolate<F: PrimeField>(domain: &[F], values: &[F]) -> Vec<F> {
+    assert!(domain.len() == values.len());
+    let mut ret = vec![F::ZERO; domain.len()];
+    for (basis, v) in lagrange_coeffs(domain).iter().zip(values) {
+        for (acc, c) in ret.iter_mut().zip(basis) {
+            *acc += *c * v;
+        }
+    }
+    ret
+}
+
+/// Evaluates the polynomial with given coefficients (lowest first) at x.
+pub fn evaluate<F: PrimeField>(coeffs: &[F], x: F) -> F {
+    coeffs.iter().rev().fold(F::ZERO, |acc, c| acc * x + c)
+}